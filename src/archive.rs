@@ -0,0 +1,183 @@
+use rusqlite::{params, Connection, OpenFlags};
+use serde_json::json;
+use std::path::Path;
+
+use crate::db;
+
+/// Another history database, from a backup or another machine, opened
+/// read-only next to the app's own so its chats can be looked through and
+/// picked for import. It may come from an older version of the app, so
+/// columns added since are only read where the file has them.
+pub struct Archive {
+    pub name: String,
+    conn: Connection,
+    session_columns: Vec<String>,
+    message_columns: Vec<String>,
+    has_blobs: bool,
+}
+
+/// A chat in the archive, as listed before it's opened.
+pub struct ArchiveSession {
+    pub id: String,
+    pub title: String,
+    pub updated: String,
+    pub messages: usize,
+}
+
+fn columns(db: &Connection, table: &str) -> Vec<String> {
+    db.prepare(&format!("PRAGMA table_info({})", table))
+        .and_then(|mut stmt| {
+            let names: Vec<String> = stmt
+                .query_map([], |row| row.get::<usize, String>(1))?
+                .flatten()
+                .collect();
+            Ok(names)
+        })
+        .unwrap_or_default()
+}
+
+/// Opens the database at `path` without ever writing to it. Fails for files
+/// that aren't a history database, and for the one the app is using.
+pub fn open(path: &Path, own: &Path) -> Result<Archive, String> {
+    if path.canonicalize().ok() == own.canonicalize().ok() {
+        return Err("that's the history this app is using".into());
+    }
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| e.to_string())?;
+    db::register_functions(&conn);
+    let session_columns = columns(&conn, "sessions");
+    let message_columns = columns(&conn, "messages");
+    if session_columns.is_empty() || message_columns.is_empty() {
+        return Err("not a chat history database".into());
+    }
+    let has_blobs = !columns(&conn, "blobs").is_empty();
+    Ok(Archive {
+        name: path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        conn,
+        session_columns,
+        message_columns,
+        has_blobs,
+    })
+}
+
+impl Archive {
+    fn has(&self, table: &str, column: &str) -> bool {
+        let columns = if table == "sessions" {
+            &self.session_columns
+        } else {
+            &self.message_columns
+        };
+        columns.iter().any(|c| c == column)
+    }
+
+    /// `column` of `table` if the file has it, otherwise `fallback`.
+    fn column<'a>(&self, table: &str, column: &'a str, fallback: &'a str) -> &'a str {
+        if self.has(table, column) {
+            column
+        } else {
+            fallback
+        }
+    }
+
+    /// Condition for messages that were finished and not deleted.
+    fn live_messages(&self) -> String {
+        format!(
+            "COALESCE({}, 0) = 0 AND COALESCE({}, 0) = 0",
+            self.column("messages", "partial", "0"),
+            self.column("messages", "deleted", "0")
+        )
+    }
+
+    /// Chats in the archive, most recently active first.
+    pub fn sessions(&self) -> Vec<ArchiveSession> {
+        let sql = format!(
+            "SELECT s.id, COALESCE(title, 'Untitled'), COALESCE({}, created_at, ''),
+                 (SELECT COUNT(*) FROM messages WHERE session_id = s.id AND {})
+             FROM sessions s WHERE COALESCE({}, 0) = 0 ORDER BY 3 DESC",
+            self.column("sessions", "updated_at", "created_at"),
+            self.live_messages(),
+            self.column("sessions", "deleted", "0"),
+        );
+        self.conn
+            .prepare(&sql)
+            .and_then(|mut stmt| {
+                let rows: Vec<ArchiveSession> = stmt
+                    .query_map([], |row| {
+                        Ok(ArchiveSession {
+                            id: row.get(0)?,
+                            title: row.get(1)?,
+                            updated: row.get(2)?,
+                            messages: row.get::<usize, i64>(3)? as usize,
+                        })
+                    })?
+                    .flatten()
+                    .collect();
+                Ok(rows)
+            })
+            .unwrap_or_default()
+    }
+
+    /// A chat's messages in the shape of `sync::export_messages`.
+    pub fn messages(&self, session_id: &str) -> Vec<serde_json::Value> {
+        let text = if self.has_blobs && self.has("messages", "blob") {
+            db::MESSAGE_TEXT
+        } else {
+            "unpack(messages.content)"
+        };
+        let sql = format!(
+            "SELECT role, {}, {}, {} FROM messages
+             WHERE session_id = ?1 AND {} ORDER BY rowid",
+            text,
+            self.column("messages", "created_at", "NULL"),
+            self.column("messages", "model", "NULL"),
+            self.live_messages(),
+        );
+        self.conn
+            .prepare(&sql)
+            .and_then(|mut stmt| {
+                let rows: Vec<serde_json::Value> = stmt
+                    .query_map(params![session_id], |row| {
+                        Ok(json!({
+                            "role": row.get::<usize, String>(0)?,
+                            "content": row.get::<usize, Option<String>>(1)?.unwrap_or_default(),
+                            "created_at": row.get::<usize, Option<String>>(2)?,
+                            "model": row.get::<usize, Option<String>>(3)?,
+                        }))
+                    })?
+                    .flatten()
+                    .collect();
+                Ok(rows)
+            })
+            .unwrap_or_default()
+    }
+
+    /// A chat in the session shape `import::import` takes.
+    pub fn session(&self, session_id: &str) -> Option<serde_json::Value> {
+        let sql = format!(
+            "SELECT title, created_at, {}, {} FROM sessions WHERE id = ?1",
+            self.column("sessions", "icon", "NULL"),
+            self.column("sessions", "summary", "NULL"),
+        );
+        let (title, created_at, icon, summary): (
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        ) = self
+            .conn
+            .query_row(&sql, params![session_id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .ok()?;
+        Some(json!({
+            "title": title.unwrap_or_else(|| "Imported chat".into()),
+            "created_at": created_at,
+            "icon": icon,
+            "summary": summary,
+            "messages": self.messages(session_id),
+        }))
+    }
+}
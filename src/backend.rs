@@ -0,0 +1,625 @@
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{FutureExt, StreamExt};
+use ollama_rs::generation::chat::{request::ChatMessageRequest, ChatMessage, MessageRole};
+use ollama_rs::generation::embeddings::request::GenerateEmbeddingsRequest;
+use ollama_rs::generation::options::GenerationOptions;
+use ollama_rs::Ollama;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// One streamed piece of an assistant reply.
+#[derive(Clone, Debug, Default)]
+pub struct ChatChunk {
+    pub content: String,
+    pub done: bool,
+    // Token counts, only present on the final chunk
+    pub prompt_tokens: Option<u64>,
+    pub response_tokens: Option<u64>,
+    // Server-side timings of the final chunk, where the server reports them
+    pub prompt_eval_ms: Option<u64>,
+    pub eval_ms: Option<u64>,
+}
+
+/// A model the server can run. `size` is the on-disk size in bytes where
+/// the server reports it, 0 otherwise; `modified_at` is empty likewise.
+#[derive(Clone, Debug)]
+pub struct ModelEntry {
+    pub name: String,
+    pub size: u64,
+    pub modified_at: String,
+}
+
+/// What the server knows about an installed model beyond its name and size.
+#[derive(Clone, Debug, Default)]
+pub struct ModelDetails {
+    /// As the server writes it, e.g. `8.0B`
+    pub parameter_size: String,
+    /// e.g. `Q4_K_M`
+    pub quantization: String,
+    pub family: String,
+}
+
+pub type ChatStream = BoxStream<'static, Result<ChatChunk, String>>;
+
+/// One progress report of a model download.
+#[derive(Clone, Debug, Default)]
+pub struct PullProgress {
+    pub status: String,
+    /// Bytes of the layer being downloaded, both 0 between layers
+    pub completed: u64,
+    pub total: u64,
+}
+
+pub type PullStream = BoxStream<'static, Result<PullProgress, String>>;
+
+/// Sampling settings for a chat. Unset fields leave the model's own
+/// defaults (from its Modelfile or the server) in place.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SamplingOptions {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub top_k: Option<u32>,
+    pub repeat_penalty: Option<f32>,
+    pub num_ctx: Option<u64>,
+    pub seed: Option<i32>,
+    /// How a conversation that outgrows `num_ctx` is cut down, see
+    /// `context::Overflow`. Kept by the app, never sent to the server.
+    pub context_overflow: Option<String>,
+}
+
+/// Everything the UI needs from an inference server. The UI code only talks
+/// to `dyn ChatBackend`, so another local server can be plugged in by adding
+/// an implementation here.
+pub trait ChatBackend: Send + Sync {
+    /// Identifies the server for per-endpoint settings such as rate limits.
+    fn endpoint(&self) -> String;
+
+    fn list_models(&self) -> BoxFuture<'_, Result<Vec<ModelEntry>, String>>;
+
+    fn chat_stream(
+        &self,
+        model: String,
+        messages: Vec<ChatMessage>,
+        options: SamplingOptions,
+    ) -> BoxFuture<'_, Result<ChatStream, String>>;
+
+    fn embeddings(&self, model: String, input: String) -> BoxFuture<'_, Result<Vec<f32>, String>>;
+
+    /// Asks the server to load `model` into memory ahead of the first real
+    /// request. Servers that load models up front don't need to do anything.
+    fn warm_up(&self, _model: String) -> BoxFuture<'_, Result<(), String>> {
+        async { Ok(()) }.boxed()
+    }
+
+    /// Downloads `model` onto the server, reporting progress as it goes.
+    /// Servers that can't fetch models themselves say so as the error.
+    fn pull_model(&self, _model: String) -> BoxFuture<'_, Result<PullStream, String>> {
+        async { Err("This server can't download models".to_string()) }.boxed()
+    }
+
+    fn show_model_info(&self, _model: String) -> BoxFuture<'_, Result<ModelDetails, String>> {
+        async { Ok(ModelDetails::default()) }.boxed()
+    }
+
+    /// Removes an installed model from the server.
+    fn delete_model(&self, _model: String) -> BoxFuture<'_, Result<(), String>> {
+        async { Err("This server can't delete models".to_string()) }.boxed()
+    }
+}
+
+#[derive(Clone)]
+pub struct OllamaBackend {
+    client: Ollama,
+    // The same client `client` sends through, for the calls ollama-rs
+    // doesn't cover, so they carry the same headers
+    http: reqwest::Client,
+    endpoint: String,
+}
+
+impl OllamaBackend {
+    pub fn new(client: Ollama, http: reqwest::Client, endpoint: &str) -> Self {
+        Self {
+            client,
+            http,
+            endpoint: endpoint.to_string(),
+        }
+    }
+
+    /// A daemon at `host` (with or without a scheme) and `port`. A non-empty
+    /// `token` is sent as a bearer token, for daemons behind a proxy that
+    /// checks one.
+    pub fn connect(host: &str, port: u16, token: &str) -> Self {
+        let host = host.trim().trim_end_matches('/');
+        let host = if host.contains("://") {
+            host.to_string()
+        } else {
+            format!("http://{}", host)
+        };
+        let mut headers = reqwest::header::HeaderMap::new();
+        let token = token.trim();
+        if !token.is_empty() {
+            if let Ok(value) = format!("Bearer {}", token).parse() {
+                headers.insert(reqwest::header::AUTHORIZATION, value);
+            }
+        }
+        let http = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap_or_default();
+        let endpoint = format!("{}:{}", host, port);
+        Self::new(
+            Ollama::new_with_client(host, port, http.clone()),
+            http,
+            &endpoint,
+        )
+    }
+}
+
+impl ChatBackend for OllamaBackend {
+    fn endpoint(&self) -> String {
+        self.endpoint.clone()
+    }
+
+    fn list_models(&self) -> BoxFuture<'_, Result<Vec<ModelEntry>, String>> {
+        async move {
+            let models = self
+                .client
+                .list_local_models()
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(models
+                .into_iter()
+                .map(|m| ModelEntry {
+                    name: m.name,
+                    size: m.size,
+                    modified_at: m.modified_at,
+                })
+                .collect())
+        }
+        .boxed()
+    }
+
+    fn chat_stream(
+        &self,
+        model: String,
+        messages: Vec<ChatMessage>,
+        options: SamplingOptions,
+    ) -> BoxFuture<'_, Result<ChatStream, String>> {
+        async move {
+            let mut req = ChatMessageRequest::new(model, messages);
+            if options != SamplingOptions::default() {
+                req = req.options(generation_options(&options));
+            }
+            let stream = self
+                .client
+                .send_chat_messages_stream(req)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(stream
+                .map(|res| {
+                    res.map(|r| ChatChunk {
+                        prompt_tokens: r.final_data.as_ref().map(|d| d.prompt_eval_count as u64),
+                        response_tokens: r.final_data.as_ref().map(|d| d.eval_count as u64),
+                        prompt_eval_ms: r
+                            .final_data
+                            .as_ref()
+                            .map(|d| d.prompt_eval_duration / 1_000_000),
+                        eval_ms: r.final_data.as_ref().map(|d| d.eval_duration / 1_000_000),
+                        content: r.message.content,
+                        done: r.done,
+                    })
+                    .map_err(|_| "stream error".to_string())
+                })
+                .boxed())
+        }
+        .boxed()
+    }
+
+    fn embeddings(&self, model: String, input: String) -> BoxFuture<'_, Result<Vec<f32>, String>> {
+        async move {
+            let req = GenerateEmbeddingsRequest::new(model, input.into());
+            let res = self
+                .client
+                .generate_embeddings(req)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(res.embeddings.into_iter().next().unwrap_or_default())
+        }
+        .boxed()
+    }
+
+    fn warm_up(&self, model: String) -> BoxFuture<'_, Result<(), String>> {
+        async move {
+            // A generate request without a prompt only loads the model
+            self.http
+                .post(format!("{}/api/generate", self.endpoint))
+                .json(&serde_json::json!({ "model": model, "keep_alive": "10m" }))
+                .send()
+                .await
+                .map_err(|e| e.to_string())?
+                .error_for_status()
+                .map_err(|e| e.to_string())?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn pull_model(&self, model: String) -> BoxFuture<'_, Result<PullStream, String>> {
+        async move {
+            let stream = self
+                .client
+                .pull_model_stream(model, false)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(stream
+                .map(|res| {
+                    res.map(|p| PullProgress {
+                        status: p.message,
+                        completed: p.completed.unwrap_or(0),
+                        total: p.total.unwrap_or(0),
+                    })
+                    .map_err(|e| e.to_string())
+                })
+                .boxed())
+        }
+        .boxed()
+    }
+
+    fn show_model_info(&self, model: String) -> BoxFuture<'_, Result<ModelDetails, String>> {
+        async move {
+            // ollama-rs's `show_model_info` leaves out the `details` object,
+            // which is where the size and quantization are
+            let body: serde_json::Value = self
+                .http
+                .post(format!("{}/api/show", self.endpoint))
+                .json(&serde_json::json!({ "name": model }))
+                .send()
+                .await
+                .map_err(|e| e.to_string())?
+                .error_for_status()
+                .map_err(|e| e.to_string())?
+                .json()
+                .await
+                .map_err(|e| e.to_string())?;
+            let details = &body["details"];
+            let field = |key: &str| details[key].as_str().unwrap_or("").to_string();
+            Ok(ModelDetails {
+                parameter_size: field("parameter_size"),
+                quantization: field("quantization_level"),
+                family: field("family"),
+            })
+        }
+        .boxed()
+    }
+
+    fn delete_model(&self, model: String) -> BoxFuture<'_, Result<(), String>> {
+        async move {
+            self.client
+                .delete_model(model)
+                .await
+                .map_err(|e| e.to_string())
+        }
+        .boxed()
+    }
+}
+
+/// Talks to any server exposing the OpenAI `/v1` API (LM Studio, the
+/// llama.cpp server, vLLM, ...). `base_url` includes the `/v1` suffix.
+#[derive(Clone)]
+pub struct OpenAiBackend {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+}
+
+impl OpenAiBackend {
+    pub fn new(base_url: &str, api_key: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let req = self
+            .http
+            .request(method, format!("{}/{}", self.base_url, path));
+        if self.api_key.is_empty() {
+            req
+        } else {
+            req.bearer_auth(&self.api_key)
+        }
+    }
+}
+
+fn generation_options(options: &SamplingOptions) -> GenerationOptions {
+    let mut out = GenerationOptions::default();
+    if let Some(v) = options.temperature {
+        out = out.temperature(v);
+    }
+    if let Some(v) = options.top_p {
+        out = out.top_p(v);
+    }
+    if let Some(v) = options.top_k {
+        out = out.top_k(v);
+    }
+    if let Some(v) = options.repeat_penalty {
+        out = out.repeat_penalty(v);
+    }
+    if let Some(v) = options.num_ctx {
+        out = out.num_ctx(v);
+    }
+    if let Some(v) = options.seed {
+        out = out.seed(v);
+    }
+    out
+}
+
+/// Whether a failed request was rejected for not fitting the model's
+/// context window. Servers only report this as free text, so this matches
+/// the wordings of Ollama, llama.cpp and the OpenAI API.
+pub fn is_context_overflow(error: &str) -> bool {
+    let error = error.to_lowercase();
+    [
+        "context length",
+        "context window",
+        "context_length_exceeded",
+        "maximum context",
+        "exceeds the available context",
+        "num_ctx",
+        "prompt is too long",
+        "too many tokens",
+    ]
+    .iter()
+    .any(|pattern| error.contains(pattern))
+}
+
+/// Like `error_for_status`, but keeps the response body in the error since
+/// that is where servers explain what was wrong with the request.
+async fn check_status(resp: reqwest::Response) -> Result<reqwest::Response, String> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let body = resp.text().await.unwrap_or_default();
+    Err(format!("{}: {}", status, body.trim()))
+}
+
+fn openai_role(role: &MessageRole) -> &'static str {
+    match role {
+        MessageRole::User => "user",
+        MessageRole::Assistant => "assistant",
+        MessageRole::System => "system",
+        #[allow(unreachable_patterns)]
+        _ => "user",
+    }
+}
+
+/// Images go along as data URLs in a list of content parts; messages
+/// without any keep the plain string form every server accepts.
+fn openai_message(m: &ChatMessage) -> serde_json::Value {
+    let images = m.images.as_deref().unwrap_or_default();
+    if images.is_empty() {
+        return serde_json::json!({ "role": openai_role(&m.role), "content": m.content });
+    }
+    let mut parts = vec![serde_json::json!({ "type": "text", "text": m.content })];
+    for image in images {
+        let data = image.to_base64();
+        // Only PNG and JPEG get attached; JPEG data always encodes to "/9j/"
+        let mime = if data.starts_with("/9j/") {
+            "image/jpeg"
+        } else {
+            "image/png"
+        };
+        parts.push(serde_json::json!({
+            "type": "image_url",
+            "image_url": { "url": format!("data:{};base64,{}", mime, data) },
+        }));
+    }
+    serde_json::json!({ "role": openai_role(&m.role), "content": parts })
+}
+
+/// Parses one line of the SSE stream. Lines without a `data:` field
+/// (comments, keep-alives, blank separators) yield `None`.
+fn parse_sse_line(line: &str) -> Option<Result<ChatChunk, String>> {
+    let data = line.strip_prefix("data:")?.trim();
+    if data == "[DONE]" {
+        return Some(Ok(ChatChunk {
+            done: true,
+            ..Default::default()
+        }));
+    }
+    match serde_json::from_str::<serde_json::Value>(data) {
+        Ok(v) => Some(Ok(ChatChunk {
+            content: v["choices"][0]["delta"]["content"]
+                .as_str()
+                .unwrap_or("")
+                .to_string(),
+            // With include_usage the token counts arrive in an extra chunk
+            // after finish_reason, so only [DONE] ends the stream.
+            done: false,
+            prompt_tokens: v["usage"]["prompt_tokens"].as_u64(),
+            response_tokens: v["usage"]["completion_tokens"].as_u64(),
+            ..Default::default()
+        })),
+        Err(e) => Some(Err(e.to_string())),
+    }
+}
+
+impl ChatBackend for OpenAiBackend {
+    fn endpoint(&self) -> String {
+        self.base_url.clone()
+    }
+
+    fn list_models(&self) -> BoxFuture<'_, Result<Vec<ModelEntry>, String>> {
+        async move {
+            let body: serde_json::Value = self
+                .request(reqwest::Method::GET, "models")
+                .send()
+                .await
+                .map_err(|e| e.to_string())?
+                .error_for_status()
+                .map_err(|e| e.to_string())?
+                .json()
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(body["data"]
+                .as_array()
+                .map(|models| {
+                    models
+                        .iter()
+                        .filter_map(|m| m["id"].as_str())
+                        .map(|id| ModelEntry {
+                            name: id.to_string(),
+                            size: 0,
+                            modified_at: String::new(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default())
+        }
+        .boxed()
+    }
+
+    fn chat_stream(
+        &self,
+        model: String,
+        messages: Vec<ChatMessage>,
+        options: SamplingOptions,
+    ) -> BoxFuture<'_, Result<ChatStream, String>> {
+        async move {
+            let messages: Vec<serde_json::Value> = messages.iter().map(openai_message).collect();
+            let mut body = serde_json::json!({
+                "model": model,
+                "messages": messages,
+                "stream": true,
+                "stream_options": { "include_usage": true },
+            });
+            // top_k and repeat_penalty aren't in the OpenAI API but the local
+            // servers speaking it (llama.cpp, vLLM) take them; the context
+            // size is fixed when such a server loads the model
+            let fields = [
+                (
+                    "temperature",
+                    options.temperature.map(serde_json::Value::from),
+                ),
+                ("top_p", options.top_p.map(serde_json::Value::from)),
+                ("top_k", options.top_k.map(serde_json::Value::from)),
+                (
+                    "repeat_penalty",
+                    options.repeat_penalty.map(serde_json::Value::from),
+                ),
+                ("seed", options.seed.map(serde_json::Value::from)),
+            ];
+            for (key, value) in fields {
+                if let Some(value) = value {
+                    body[key] = value;
+                }
+            }
+            let resp = self
+                .request(reqwest::Method::POST, "chat/completions")
+                .json(&body)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            let resp = check_status(resp).await?;
+
+            // SSE events can be split across network chunks, so buffer raw
+            // bytes and only decode complete lines.
+            let stream = futures::stream::unfold(
+                (resp.bytes_stream(), Vec::<u8>::new(), false),
+                |(mut bytes, mut buf, finished)| async move {
+                    if finished {
+                        return None;
+                    }
+                    loop {
+                        if let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                            let line: Vec<u8> = buf.drain(..=pos).collect();
+                            let line = String::from_utf8_lossy(&line);
+                            if let Some(item) = parse_sse_line(line.trim()) {
+                                let finished = matches!(&item, Ok(c) if c.done) || item.is_err();
+                                return Some((item, (bytes, buf, finished)));
+                            }
+                            continue;
+                        }
+                        match bytes.next().await {
+                            Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+                            Some(Err(e)) => return Some((Err(e.to_string()), (bytes, buf, true))),
+                            None => return None,
+                        }
+                    }
+                },
+            );
+            Ok(stream.boxed())
+        }
+        .boxed()
+    }
+
+    fn embeddings(&self, model: String, input: String) -> BoxFuture<'_, Result<Vec<f32>, String>> {
+        async move {
+            let body: serde_json::Value = self
+                .request(reqwest::Method::POST, "embeddings")
+                .json(&serde_json::json!({ "model": model, "input": input }))
+                .send()
+                .await
+                .map_err(|e| e.to_string())?
+                .error_for_status()
+                .map_err(|e| e.to_string())?
+                .json()
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(body["data"][0]["embedding"]
+                .as_array()
+                .map(|v| {
+                    v.iter()
+                        .filter_map(|x| x.as_f64())
+                        .map(|x| x as f32)
+                        .collect()
+                })
+                .unwrap_or_default())
+        }
+        .boxed()
+    }
+}
+
+/// Builds the backend selected in the config (`backend`, `backend_url`,
+/// `backend_api_key` for OpenAI-compatible servers, `ollama_host`,
+/// `ollama_port` and `ollama_token` for Ollama). Falls back to the local
+/// Ollama daemon.
+pub fn from_config(cfg: &serde_json::Value) -> Arc<dyn ChatBackend> {
+    match cfg["backend"].as_str().unwrap_or("ollama") {
+        "openai" => Arc::new(OpenAiBackend::new(
+            cfg["backend_url"]
+                .as_str()
+                .unwrap_or("http://localhost:8080/v1"),
+            cfg["backend_api_key"].as_str().unwrap_or(""),
+        )),
+        _ => Arc::new(OllamaBackend::connect(
+            cfg["ollama_host"].as_str().unwrap_or("http://localhost"),
+            cfg["ollama_port"]
+                .as_u64()
+                .and_then(|p| u16::try_from(p).ok())
+                .unwrap_or(11434),
+            cfg["ollama_token"].as_str().unwrap_or(""),
+        )),
+    }
+}
+
+/// Runs a request to completion and returns the whole reply. For short
+/// background jobs where nothing is shown while it streams.
+pub async fn collect_reply(
+    backend: &dyn ChatBackend,
+    model: String,
+    messages: Vec<ChatMessage>,
+) -> Result<String, String> {
+    let mut stream = backend
+        .chat_stream(model, messages, SamplingOptions::default())
+        .await?;
+    let mut reply = String::new();
+    while let Some(chunk) = stream.next().await {
+        reply.push_str(&chunk?.content);
+    }
+    Ok(reply)
+}
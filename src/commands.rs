@@ -0,0 +1,176 @@
+/// An action the command palette lists and a shortcut can trigger.
+pub struct Command {
+    pub id: &'static str,
+    pub label: &'static str,
+    /// Written as the keymap reports it, e.g. `ctrl+shift+n`
+    pub shortcut: Option<&'static str>,
+}
+
+/// Every fixed action. Models, conversation templates and attachment presets
+/// are added to the palette at runtime as `model:<name>`, `template:<id>`
+/// and `preset:<id>` entries.
+pub const COMMANDS: &[Command] = &[
+    Command {
+        id: "palette",
+        label: "Command palette",
+        shortcut: Some("ctrl+p"),
+    },
+    Command {
+        id: "new_chat",
+        label: "New chat",
+        shortcut: Some("ctrl+n"),
+    },
+    Command {
+        id: "undo",
+        label: "Undo delete",
+        shortcut: None,
+    },
+    Command {
+        id: "attach",
+        label: "Attach file…",
+        shortcut: Some("ctrl+o"),
+    },
+    Command {
+        id: "files",
+        label: "Session files",
+        shortcut: None,
+    },
+    Command {
+        id: "settings",
+        label: "Open settings",
+        shortcut: Some("ctrl+,"),
+    },
+    Command {
+        id: "stop",
+        label: "Stop generating",
+        shortcut: None,
+    },
+    Command {
+        id: "lock",
+        label: "Toggle read-only for this chat",
+        shortcut: None,
+    },
+    Command {
+        id: "summarize",
+        label: "Summarize this chat",
+        shortcut: None,
+    },
+    Command {
+        id: "info",
+        label: "Chat info",
+        shortcut: Some("ctrl+i"),
+    },
+    Command {
+        id: "journal",
+        label: "Export chat to journal",
+        shortcut: None,
+    },
+    Command {
+        id: "export_markdown",
+        label: "Export chat as Markdown…",
+        shortcut: None,
+    },
+    Command {
+        id: "export_json",
+        label: "Export chat as JSON…",
+        shortcut: None,
+    },
+    Command {
+        id: "share",
+        label: "Share chat as file…",
+        shortcut: None,
+    },
+    Command {
+        id: "copy_link",
+        label: "Copy link to this chat",
+        shortcut: None,
+    },
+    Command {
+        id: "register_links",
+        label: "Open chat links with this app",
+        shortcut: None,
+    },
+    Command {
+        id: "open_database",
+        label: "Open database…",
+        shortcut: None,
+    },
+    Command {
+        id: "open_shared",
+        label: "Open shared chat…",
+        shortcut: None,
+    },
+    Command {
+        id: "import_chats",
+        label: "Import chats…",
+        shortcut: None,
+    },
+    Command {
+        id: "export_settings",
+        label: "Export settings…",
+        shortcut: None,
+    },
+    Command {
+        id: "stats",
+        label: "Usage statistics",
+        shortcut: None,
+    },
+    Command {
+        id: "storage",
+        label: "Storage",
+        shortcut: None,
+    },
+    Command {
+        id: "models",
+        label: "Manage models",
+        shortcut: None,
+    },
+    Command {
+        id: "pull_model",
+        label: "Download model…",
+        shortcut: None,
+    },
+    Command {
+        id: "prompts",
+        label: "Prompt library",
+        shortcut: None,
+    },
+    Command {
+        id: "templates",
+        label: "Conversation templates",
+        shortcut: None,
+    },
+    Command {
+        id: "tools",
+        label: "Manage tools",
+        shortcut: None,
+    },
+];
+
+pub fn for_shortcut(chord: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.shortcut == Some(chord))
+}
+
+/// Scores `label` against `query` as a fuzzy subsequence match, `None` if
+/// some query character doesn't appear in order. Consecutive characters
+/// and matches at word starts score higher.
+pub fn fuzzy_score(query: &str, label: &str) -> Option<i32> {
+    let label: Vec<char> = label.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut last_match: Option<usize> = None;
+    for q in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = (pos..label.len()).find(|&i| label[i] == q)?;
+        score += 1;
+        if last_match == Some(found.wrapping_sub(1)) {
+            score += 3;
+        }
+        if found == 0 || !label[found - 1].is_alphanumeric() {
+            score += 2;
+        }
+        last_match = Some(found);
+        pos = found + 1;
+    }
+    // Prefer shorter labels among equal matches
+    Some(score * 100 - label.len() as i32)
+}
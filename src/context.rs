@@ -0,0 +1,158 @@
+use ollama_rs::generation::chat::{ChatMessage, MessageRole};
+
+use crate::tokens::TokenCounter;
+
+// What a message costs beyond its text: role markers and separators
+const MESSAGE_OVERHEAD: usize = 4;
+// Part of the context window kept free for the reply, as a divisor
+const REPLY_SHARE: usize = 4;
+
+/// What to do when a conversation outgrows the model's context window.
+/// Chosen per session with the sampling options.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Leave out the oldest turns
+    #[default]
+    Trim,
+    /// Leave them out and send the session summary in their place
+    Summarize,
+    /// Send nothing, so no turn is ever silently left out
+    Fail,
+}
+
+impl Overflow {
+    pub fn parse(name: &str) -> Self {
+        match name {
+            "summarize" => Self::Summarize,
+            "fail" => Self::Fail,
+            _ => Self::Trim,
+        }
+    }
+}
+
+/// Estimated prompt tokens of `messages`.
+pub fn estimate(messages: &[ChatMessage], counter: &TokenCounter) -> usize {
+    messages
+        .iter()
+        .map(|m| counter.count(&m.content) + MESSAGE_OVERHEAD)
+        .sum()
+}
+
+/// Leaves out the oldest turns of `messages` until the estimate leaves room
+/// for a reply within `window` tokens. System messages and the last message,
+/// the prompt being answered, always stay. Returns how many were left out,
+/// or an explanation if the request can't be made to fit under `overflow`.
+pub fn fit(
+    messages: &mut Vec<ChatMessage>,
+    counter: &TokenCounter,
+    window: usize,
+    overflow: Overflow,
+) -> Result<usize, String> {
+    let budget = window - window / REPLY_SHARE;
+    let mut total = estimate(messages, counter);
+    if total <= budget {
+        return Ok(0);
+    }
+    if overflow == Overflow::Fail {
+        return Err(format!(
+            "The conversation needs about {} tokens but the context window is {}. Raise the context size or start a new chat.",
+            total, window
+        ));
+    }
+    let mut dropped = 0;
+    while total > budget {
+        let last = messages.len().saturating_sub(1);
+        let Some(oldest) = messages[..last]
+            .iter()
+            .position(|m| m.role != MessageRole::System)
+        else {
+            return Err(format!(
+                "The prompt alone needs about {} tokens but the context window is {}.",
+                total, window
+            ));
+        };
+        let removed = messages.remove(oldest);
+        total -= counter.count(&removed.content) + MESSAGE_OVERHEAD;
+        dropped += 1;
+    }
+    Ok(dropped)
+}
+
+/// Puts `summary` after the leading system messages of `messages`, where
+/// the turns it stands in for used to start.
+pub fn insert_summary(messages: &mut Vec<ChatMessage>, summary: ChatMessage) {
+    let at = messages
+        .iter()
+        .position(|m| m.role != MessageRole::System)
+        .unwrap_or(messages.len());
+    messages.insert(at, summary);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation(turn: &str) -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::system(turn.to_string()),
+            ChatMessage::user(turn.to_string()),
+            ChatMessage::assistant(turn.to_string()),
+            ChatMessage::user(turn.to_string()),
+            ChatMessage::assistant(turn.to_string()),
+            ChatMessage::user(turn.to_string()),
+        ]
+    }
+
+    #[test]
+    fn leaves_fitting_requests_alone() {
+        let counter = TokenCounter::new();
+        let mut messages = conversation("hello");
+        assert_eq!(fit(&mut messages, &counter, 4096, Overflow::Trim), Ok(0));
+        assert_eq!(messages.len(), 6);
+    }
+
+    #[test]
+    fn trims_oldest_turns_to_budget() {
+        let counter = TokenCounter::new();
+        let turn = "word ".repeat(50);
+        let mut messages = conversation(&turn);
+        let per_message = estimate(&messages[..1], &counter);
+        // Leaves room for three messages once a quarter is kept for the reply
+        let window = per_message * 4;
+
+        assert_eq!(fit(&mut messages, &counter, window, Overflow::Trim), Ok(3));
+        let roles: Vec<_> = messages.iter().map(|m| m.role.clone()).collect();
+        assert_eq!(
+            roles,
+            [
+                MessageRole::System,
+                MessageRole::Assistant,
+                MessageRole::User
+            ]
+        );
+        assert!(estimate(&messages, &counter) <= window - window / REPLY_SHARE);
+    }
+
+    #[test]
+    fn fail_keeps_every_turn() {
+        let counter = TokenCounter::new();
+        let mut messages = conversation(&"word ".repeat(50));
+        assert!(fit(&mut messages, &counter, 100, Overflow::Fail).is_err());
+        assert_eq!(messages.len(), 6);
+    }
+
+    #[test]
+    fn prompt_that_can_never_fit_is_an_error() {
+        let counter = TokenCounter::new();
+        let mut messages = vec![ChatMessage::user("word ".repeat(500))];
+        assert!(fit(&mut messages, &counter, 100, Overflow::Trim).is_err());
+    }
+
+    #[test]
+    fn summary_goes_after_system_messages() {
+        let mut messages = conversation("hello");
+        insert_summary(&mut messages, ChatMessage::system("summary".to_string()));
+        assert_eq!(messages[1].content, "summary");
+        assert_eq!(messages[2].role, MessageRole::User);
+    }
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::sync::Mutex;
+
+const CRASH_FILE: &str = "crash_state.json";
+
+/// What we know about the user's work at any moment, written out by the
+/// panic hook so the next launch can put them back where they were.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub session_id: String,
+    pub draft: String,
+    pub last_error: String,
+}
+
+static SNAPSHOT: Mutex<Snapshot> = Mutex::new(Snapshot {
+    session_id: String::new(),
+    draft: String::new(),
+    last_error: String::new(),
+});
+
+pub fn set_session(id: &str) {
+    if let Ok(mut snap) = SNAPSHOT.lock() {
+        snap.session_id = id.to_string();
+    }
+}
+
+pub fn set_draft(text: &str) {
+    if let Ok(mut snap) = SNAPSHOT.lock() {
+        snap.draft = text.to_string();
+    }
+}
+
+pub fn set_error(msg: &str) {
+    if let Ok(mut snap) = SNAPSHOT.lock() {
+        snap.last_error = msg.to_string();
+    }
+}
+
+/// Chains onto the default hook; the snapshot is written first so it
+/// survives even if the default hook aborts.
+pub fn install_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // try_lock: the panic may have happened while the snapshot was held
+        let mut snap = SNAPSHOT.try_lock().map(|s| s.clone()).unwrap_or_default();
+        snap.last_error = info.to_string();
+        if let Ok(json) = serde_json::to_string_pretty(&snap) {
+            let _ = fs::write(CRASH_FILE, json);
+        }
+        default_hook(info);
+    }));
+}
+
+/// Returns the snapshot left behind by a crashed run, if any.
+pub fn pending_recovery() -> Option<Snapshot> {
+    let json = fs::read_to_string(CRASH_FILE).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+pub fn clear_recovery() {
+    let _ = fs::remove_file(CRASH_FILE);
+}
@@ -0,0 +1,930 @@
+use ollama_rs::generation::chat::ChatMessage;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params, Connection};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use crate::backend::SamplingOptions;
+
+// Deleting at least this many rows at once reclaims the freed pages
+const AUTO_VACUUM_THRESHOLD: usize = 200;
+// Messages longer than this keep their text in `blobs`
+const BLOB_THRESHOLD: usize = 32 * 1024;
+
+// Stored text at least this long is compressed when `compress_history` is on
+const COMPRESS_MIN_BYTES: usize = 512;
+const COMPRESS_LEVEL: i32 = 3;
+// Start of every zstd frame; text never starts with it
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// SQL for a message's text, wherever it's stored and whether or not it's
+/// compressed. Use in place of `messages.content` when reading.
+pub const MESSAGE_TEXT: &str =
+    "unpack(COALESCE((SELECT b.content FROM blobs b WHERE b.hash = messages.blob), messages.content))";
+
+/// The history database, opened and migrated the first time it's used so
+/// the window doesn't wait for it. It has a lock of its own rather than
+/// living in the app state, so background tasks can work on it without
+/// holding up everything else. It lives as long as the app, so handles are
+/// plain references that copy freely.
+#[derive(Clone, Copy)]
+pub struct Db(&'static LazyConnection);
+
+struct LazyConnection {
+    path: PathBuf,
+    setup: fn(&Connection),
+    conn: OnceLock<Mutex<Connection>>,
+}
+
+impl Db {
+    /// `setup` runs once on the fresh connection, before anything else.
+    pub fn new(path: impl Into<PathBuf>, setup: fn(&Connection)) -> Self {
+        Self(Box::leak(Box::new(LazyConnection {
+            path: path.into(),
+            setup,
+            conn: OnceLock::new(),
+        })))
+    }
+
+    /// The connection, waiting for whoever is using it. For the UI thread
+    /// and code that's already off the async runtime; tasks use `call`.
+    pub fn get(&self) -> MutexGuard<'static, Connection> {
+        let lazy: &'static LazyConnection = self.0;
+        let conn = lazy.conn.get_or_init(|| {
+            let db = Connection::open(&lazy.path).expect("Failed to open DB");
+            (lazy.setup)(&db);
+            Mutex::new(db)
+        });
+        // A panic mid-statement leaves nothing half done that SQLite
+        // wouldn't roll back itself
+        conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Runs `f` on the connection from the blocking thread pool, so a task
+    /// waiting on SQLite doesn't stall the runtime.
+    pub async fn call<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Connection) -> R + Send + 'static,
+    ) -> R {
+        let db = *self;
+        tokio::task::spawn_blocking(move || f(&db.get()))
+            .await
+            .expect("Database task panicked")
+    }
+}
+
+/// Creates the core tables and adds any columns introduced since the
+/// database was first created.
+pub fn init(db: &Connection) {
+    register_functions(db);
+    // Only affects new databases until the next full VACUUM converts them
+    let _ = db.execute_batch("PRAGMA auto_vacuum = INCREMENTAL;");
+    db.execute("CREATE TABLE IF NOT EXISTS sessions (id TEXT PRIMARY KEY, title TEXT, created_at DATETIME)", []).unwrap();
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS messages (id INTEGER PRIMARY KEY, session_id TEXT, role TEXT, content TEXT, created_at DATETIME)",
+        [],
+    )
+    .unwrap();
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS drafts (session_id TEXT PRIMARY KEY, content TEXT)",
+        [],
+    )
+    .unwrap();
+
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS recent_files (path TEXT PRIMARY KEY, name TEXT, last_used DATETIME)",
+        [],
+    )
+    .unwrap();
+    // Files attached to each session; `active` ones go out with the next
+    // prompt, `pinned` ones stay active after it
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS attachments (session_id TEXT, name TEXT, path TEXT, pinned INTEGER DEFAULT 0, active INTEGER DEFAULT 1, added_at DATETIME, PRIMARY KEY (session_id, name))",
+        [],
+    )
+    .unwrap();
+
+    // Text of very large messages, stored once however often it's sent
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS blobs (hash TEXT PRIMARY KEY, content TEXT)",
+        [],
+    )
+    .unwrap();
+
+    ensure_column(db, "sessions", "icon", "TEXT");
+    ensure_column(db, "sessions", "summary", "TEXT");
+    ensure_column(db, "sessions", "synced_rev", "TEXT");
+    ensure_column(db, "messages", "partial", "INTEGER DEFAULT 0");
+    ensure_column(db, "messages", "created_at", "DATETIME");
+    ensure_column(db, "messages", "model", "TEXT");
+    ensure_column(db, "messages", "blob", "TEXT");
+    ensure_column(db, "messages", "prompt_tokens", "INTEGER");
+    ensure_column(db, "messages", "response_tokens", "INTEGER");
+    ensure_column(db, "messages", "duration_ms", "INTEGER");
+    ensure_column(db, "messages", "ttft_ms", "INTEGER");
+    ensure_column(db, "messages", "budget_system", "INTEGER");
+    ensure_column(db, "messages", "budget_history", "INTEGER");
+    ensure_column(db, "messages", "budget_attachments", "INTEGER");
+    ensure_column(db, "messages", "budget_prompt", "INTEGER");
+    ensure_column(db, "sessions", "updated_at", "DATETIME");
+    ensure_column(db, "sessions", "locked", "INTEGER DEFAULT 0");
+    ensure_column(db, "sessions", "system_prompt", "TEXT");
+    ensure_column(db, "sessions", "reply_format", "TEXT");
+    ensure_column(db, "sessions", "reply_language", "TEXT");
+    ensure_column(db, "sessions", "options", "TEXT");
+    ensure_column(db, "messages", "language", "TEXT");
+    ensure_column(db, "sessions", "title_locked", "INTEGER DEFAULT 0");
+    ensure_column(db, "messages", "excluded", "INTEGER DEFAULT 0");
+    // Deleted this run and still undoable, see `purge_deleted`
+    ensure_column(db, "messages", "deleted", "INTEGER DEFAULT 0");
+    ensure_column(db, "sessions", "deleted", "INTEGER DEFAULT 0");
+    migrate_message_ids(db);
+    let _ = db.execute(
+        "CREATE INDEX IF NOT EXISTS messages_by_session ON messages (session_id, id)",
+        [],
+    );
+
+    // Every message insert counts as activity, whichever code path adds it
+    let _ = db.execute_batch(
+        "UPDATE sessions SET updated_at =
+             (SELECT MAX(created_at) FROM messages WHERE session_id = sessions.id)
+         WHERE updated_at IS NULL;
+         CREATE TRIGGER IF NOT EXISTS touch_session_on_message AFTER INSERT ON messages
+         BEGIN
+             UPDATE sessions
+             SET updated_at = MAX(COALESCE(updated_at, ''), COALESCE(NEW.created_at, datetime('now')))
+             WHERE id = NEW.session_id;
+         END;",
+    );
+}
+
+/// Databases from before messages had an explicit key only had the implicit
+/// rowid, which VACUUM may renumber. Rebuilds the table with `id INTEGER
+/// PRIMARY KEY` set to the old rowids, keeping every other column.
+fn migrate_message_ids(db: &Connection) {
+    let columns: Vec<(String, String, Option<String>, i64)> = db
+        .prepare("PRAGMA table_info(messages)")
+        .and_then(|mut stmt| {
+            Ok(stmt
+                .query_map([], |row| {
+                    Ok((row.get(1)?, row.get(2)?, row.get(4)?, row.get(5)?))
+                })?
+                .flatten()
+                .collect())
+        })
+        .unwrap_or_default();
+    if columns.iter().any(|(_, _, _, pk)| *pk > 0) {
+        return;
+    }
+
+    let names: Vec<&str> = columns.iter().map(|(name, ..)| name.as_str()).collect();
+    let decls: Vec<String> = columns
+        .iter()
+        .map(|(name, kind, default, _)| match default {
+            Some(default) => format!("{} {} DEFAULT {}", name, kind, default),
+            None => format!("{} {}", name, kind),
+        })
+        .collect();
+    let result = db.execute_batch(&format!(
+        "BEGIN;
+         DROP TRIGGER IF EXISTS touch_session_on_message;
+         CREATE TABLE messages_migrated (id INTEGER PRIMARY KEY, {decls});
+         INSERT INTO messages_migrated (id, {names}) SELECT rowid, {names} FROM messages ORDER BY rowid;
+         DROP TABLE messages;
+         ALTER TABLE messages_migrated RENAME TO messages;
+         COMMIT;",
+        decls = decls.join(", "),
+        names = names.join(", "),
+    ));
+    if let Err(e) = result {
+        eprintln!("Failed to migrate messages table: {}", e);
+        let _ = db.execute_batch("ROLLBACK;");
+    }
+}
+
+/// `ALTER TABLE ... ADD COLUMN` unless the column already exists.
+pub fn ensure_column(db: &Connection, table: &str, column: &str, decl: &str) {
+    let exists = db
+        .prepare(&format!("PRAGMA table_info({})", table))
+        .and_then(|mut stmt| {
+            let names: Vec<String> = stmt
+                .query_map([], |row| row.get::<usize, String>(1))?
+                .flatten()
+                .collect();
+            Ok(names.iter().any(|n| n == column))
+        })
+        .unwrap_or(false);
+    if !exists {
+        db.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+            [],
+        )
+        .unwrap();
+    }
+}
+
+pub fn save_draft(db: &Connection, session_id: &str, content: &str) {
+    if content.trim().is_empty() {
+        let _ = db.execute(
+            "DELETE FROM drafts WHERE session_id = ?1",
+            params![session_id],
+        );
+    } else {
+        let _ = db.execute(
+            "INSERT OR REPLACE INTO drafts (session_id, content) VALUES (?1, ?2)",
+            params![session_id, content],
+        );
+    }
+}
+
+/// Returns and forgets the saved draft for a session.
+pub fn take_draft(db: &Connection, session_id: &str) -> Option<String> {
+    let draft = db
+        .query_row(
+            "SELECT content FROM drafts WHERE session_id = ?1",
+            params![session_id],
+            |row| row.get(0),
+        )
+        .ok();
+    let _ = db.execute(
+        "DELETE FROM drafts WHERE session_id = ?1",
+        params![session_id],
+    );
+    draft
+}
+
+/// A draft left in a new chat that never got its first message sent.
+pub fn unsent_draft(db: &Connection) -> Option<(String, String)> {
+    let found: (String, String) = db
+        .query_row(
+            "SELECT session_id, content FROM drafts WHERE session_id NOT IN (SELECT id FROM sessions) LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .ok()?;
+    let _ = db.execute("DELETE FROM drafts WHERE session_id = ?1", params![found.0]);
+    Some(found)
+}
+
+pub struct StoredMessage {
+    pub row_id: i64,
+    pub message: ChatMessage,
+    pub partial: bool,
+}
+
+/// Adds a finished message to a session, dated now unless `created_at` is
+/// given. Text over `BLOB_THRESHOLD` goes into `blobs` under its SHA-256, so
+/// pasting the same document into several prompts stores it once.
+pub fn insert_message(
+    db: &Connection,
+    session_id: &str,
+    role: &str,
+    content: &str,
+    created_at: Option<&str>,
+) -> i64 {
+    let blob = store_blob(db, content);
+    let _ = db.execute(
+        "INSERT INTO messages (session_id, role, content, blob, created_at) VALUES (?1, ?2, ?3, ?4, COALESCE(?5, datetime('now')))",
+        params![session_id, role, if blob.is_some() { "" } else { content }, blob, created_at],
+    );
+    db.last_insert_rowid()
+}
+
+/// Replaces a message's text, stored the way `insert_message` stores it.
+pub fn set_message_text(db: &Connection, row_id: i64, content: &str) {
+    let old: Option<String> = db
+        .query_row(
+            "SELECT blob FROM messages WHERE id = ?1",
+            params![row_id],
+            |row| row.get(0),
+        )
+        .unwrap_or(None);
+    let blob = store_blob(db, content);
+    let _ = db.execute(
+        "UPDATE messages SET content = ?1, blob = ?2 WHERE id = ?3",
+        params![if blob.is_some() { "" } else { content }, blob, row_id],
+    );
+    // A reply still streaming in gets a new blob on every flush
+    if let Some(old) = old.filter(|old| blob.as_ref() != Some(old)) {
+        let _ = db.execute(
+            "DELETE FROM blobs WHERE hash = ?1 AND NOT EXISTS (SELECT 1 FROM messages WHERE blob = ?1)",
+            params![old],
+        );
+    }
+}
+
+/// Puts text over `BLOB_THRESHOLD` into `blobs`, returning the hash the
+/// message row refers to it by.
+fn store_blob(db: &Connection, content: &str) -> Option<String> {
+    (content.len() > BLOB_THRESHOLD).then(|| {
+        let hash: String = Sha256::digest(content.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let _ = db.execute(
+            "INSERT OR IGNORE INTO blobs (hash, content) VALUES (?1, ?2)",
+            params![hash, content],
+        );
+        hash
+    })
+}
+
+pub fn load_messages(db: &Connection, session_id: &str) -> Vec<StoredMessage> {
+    let mut stmt = db
+        .prepare(&format!(
+            "SELECT id, role, {}, partial FROM messages WHERE session_id = ?1 AND deleted = 0 ORDER BY id",
+            MESSAGE_TEXT
+        ))
+        .unwrap();
+    stmt.query_map([session_id], |row| {
+        let role: String = row.get(1)?;
+        let content: String = row.get(2)?;
+        Ok(StoredMessage {
+            row_id: row.get(0)?,
+            message: if role == "user" {
+                ChatMessage::user(content)
+            } else {
+                ChatMessage::assistant(content)
+            },
+            partial: row.get::<usize, Option<i64>>(3)?.unwrap_or(0) != 0,
+        })
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
+
+/// Hides one message, leaving the rest of the session as it was. It stays
+/// in the database, restorable with `restore_message`, until the next start.
+pub fn delete_message(db: &Connection, row_id: i64) {
+    let _ = db.execute(
+        "UPDATE messages SET deleted = 1 WHERE id = ?1",
+        params![row_id],
+    );
+}
+
+/// Hides a session's messages from `row_id` onwards the way `delete_message`
+/// hides one, returning the ids it hid so they can be restored.
+pub fn delete_messages_from(db: &Connection, session_id: &str, row_id: i64) -> Vec<i64> {
+    let ids: Vec<i64> = db
+        .prepare("SELECT id FROM messages WHERE session_id = ?1 AND id >= ?2 AND deleted = 0")
+        .and_then(|mut stmt| {
+            let ids = stmt
+                .query_map(params![session_id, row_id], |row| row.get(0))?
+                .flatten()
+                .collect();
+            Ok(ids)
+        })
+        .unwrap_or_default();
+    for id in &ids {
+        delete_message(db, *id);
+    }
+    ids
+}
+
+pub fn restore_message(db: &Connection, row_id: i64) {
+    let _ = db.execute(
+        "UPDATE messages SET deleted = 0 WHERE id = ?1",
+        params![row_id],
+    );
+}
+
+/// Copies a session up to and including message `row_id` into a new one
+/// with the same prompt and settings, and returns its id.
+pub fn fork_session(db: &Connection, session_id: &str, row_id: i64) -> String {
+    let id = uuid::Uuid::new_v4().to_string();
+    let _ = db.execute(
+        "INSERT INTO sessions (id, title, created_at, updated_at, icon, system_prompt, reply_format, reply_language, options)
+         SELECT ?1, COALESCE(title, 'New chat') || ' (fork)', datetime('now'), datetime('now'), icon, system_prompt, reply_format, reply_language, options
+         FROM sessions WHERE id = ?2",
+        params![id, session_id],
+    );
+    // Stored text is copied as is, so blobs are shared rather than duplicated
+    let _ = db.execute(
+        "INSERT INTO messages (session_id, role, content, blob, created_at, model, language, excluded)
+         SELECT ?1, role, content, blob, created_at, model, language, excluded
+         FROM messages WHERE session_id = ?2 AND id <= ?3 AND COALESCE(partial, 0) = 0 AND deleted = 0 ORDER BY id",
+        params![id, session_id, row_id],
+    );
+    id
+}
+
+/// Whether each message of a session, in order, is left out of what's sent
+/// to the model.
+pub fn excluded_messages(db: &Connection, session_id: &str) -> Vec<bool> {
+    db.prepare("SELECT COALESCE(excluded, 0) != 0 FROM messages WHERE session_id = ?1 AND deleted = 0 ORDER BY id")
+        .and_then(|mut stmt| {
+            let flags: Vec<bool> = stmt
+                .query_map(params![session_id], |row| row.get(0))?
+                .flatten()
+                .collect();
+            Ok(flags)
+        })
+        .unwrap_or_default()
+}
+
+pub fn set_message_excluded(db: &Connection, row_id: i64, excluded: bool) {
+    let _ = db.execute(
+        "UPDATE messages SET excluded = ?1 WHERE rowid = ?2",
+        params![excluded as i64, row_id],
+    );
+}
+
+/// Active attachments of a session as (name, path, pinned). Files in the
+/// session folder from before attachments were tracked are adopted as
+/// pinned, which is how they used to behave.
+pub fn session_attachments(db: &Connection, session_id: &str) -> Vec<(String, PathBuf, bool)> {
+    if let Ok(entries) = fs::read_dir(Path::new("./attachments").join(session_id)) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_file() {
+                let _ = db.execute(
+                    "INSERT OR IGNORE INTO attachments (session_id, name, path, pinned, active, added_at) VALUES (?1, ?2, ?3, 1, 1, datetime('now'))",
+                    params![
+                        session_id,
+                        entry.file_name().to_string_lossy(),
+                        path.to_string_lossy()
+                    ],
+                );
+            }
+        }
+    }
+    let mut stmt = db
+        .prepare("SELECT name, path, pinned FROM attachments WHERE session_id = ?1 AND active = 1 ORDER BY added_at")
+        .unwrap();
+    stmt.query_map(params![session_id], |row| {
+        Ok((
+            row.get::<usize, String>(0)?,
+            PathBuf::from(row.get::<usize, String>(1)?),
+            row.get::<usize, i64>(2)? != 0,
+        ))
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
+
+/// Every file ever attached to a session, newest first, as
+/// (name, path, active, pinned).
+pub fn attachment_library(db: &Connection, session_id: &str) -> Vec<(String, PathBuf, bool, bool)> {
+    let mut stmt = db
+        .prepare("SELECT name, path, active, pinned FROM attachments WHERE session_id = ?1 ORDER BY added_at DESC")
+        .unwrap();
+    stmt.query_map(params![session_id], |row| {
+        Ok((
+            row.get::<usize, String>(0)?,
+            PathBuf::from(row.get::<usize, String>(1)?),
+            row.get::<usize, i64>(2)? != 0,
+            row.get::<usize, i64>(3)? != 0,
+        ))
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
+
+/// Drops a file from the session's library along with its stored copy.
+pub fn forget_attachment(db: &Connection, session_id: &str, name: &str) {
+    let path: Option<String> = db
+        .query_row(
+            "SELECT path FROM attachments WHERE session_id = ?1 AND name = ?2",
+            params![session_id, name],
+            |row| row.get(0),
+        )
+        .ok();
+    let _ = db.execute(
+        "DELETE FROM attachments WHERE session_id = ?1 AND name = ?2",
+        params![session_id, name],
+    );
+    if let Some(path) = path {
+        let _ = fs::remove_file(path);
+    }
+}
+
+pub fn add_attachment(db: &Connection, session_id: &str, name: &str, path: &Path) {
+    let _ = db.execute(
+        "INSERT INTO attachments (session_id, name, path, active, added_at) VALUES (?1, ?2, ?3, 1, datetime('now'))
+         ON CONFLICT (session_id, name) DO UPDATE SET path = excluded.path, active = 1, added_at = excluded.added_at",
+        params![session_id, name, path.to_string_lossy()],
+    );
+}
+
+pub fn set_attachment_pinned(db: &Connection, session_id: &str, name: &str, pinned: bool) {
+    let _ = db.execute(
+        "UPDATE attachments SET pinned = ?1 WHERE session_id = ?2 AND name = ?3",
+        params![pinned, session_id, name],
+    );
+}
+
+pub fn detach(db: &Connection, session_id: &str, name: &str) {
+    let _ = db.execute(
+        "UPDATE attachments SET active = 0 WHERE session_id = ?1 AND name = ?2",
+        params![session_id, name],
+    );
+}
+
+/// After a send: unpinned attachments have been delivered and are dropped
+/// from the next prompt.
+pub fn detach_unpinned(db: &Connection, session_id: &str) {
+    let _ = db.execute(
+        "UPDATE attachments SET active = 0 WHERE session_id = ?1 AND pinned = 0",
+        params![session_id],
+    );
+}
+
+/// Hides a session from the history, restorable with `restore_session`
+/// until the next start, when `purge_deleted` removes it for good.
+pub fn trash_session(db: &Connection, session_id: &str) -> bool {
+    db.execute(
+        "UPDATE sessions SET deleted = 1 WHERE id = ?1",
+        params![session_id],
+    )
+    .map(|n| n > 0)
+    .unwrap_or(false)
+}
+
+pub fn restore_session(db: &Connection, session_id: &str) {
+    let _ = db.execute(
+        "UPDATE sessions SET deleted = 0 WHERE id = ?1",
+        params![session_id],
+    );
+}
+
+/// Removes whatever was deleted in an earlier run and so can no longer be
+/// undone.
+pub fn purge_deleted(db: &Connection) {
+    let trashed: Vec<String> = db
+        .prepare("SELECT id FROM sessions WHERE deleted = 1")
+        .and_then(|mut stmt| {
+            let ids: Vec<String> = stmt.query_map([], |row| row.get(0))?.flatten().collect();
+            Ok(ids)
+        })
+        .unwrap_or_default();
+    for id in trashed {
+        if let Err(e) = delete_session(db, &id) {
+            eprintln!("Error deleting session {}: {}", id, e);
+        }
+    }
+    let removed = db
+        .execute("DELETE FROM messages WHERE deleted = 1", [])
+        .unwrap_or(0);
+    if removed > 0 {
+        after_delete(db, removed);
+    }
+}
+
+/// Removes a session with its messages and everything keyed on it, then
+/// its attachment folder. Returns the number of messages deleted.
+pub fn delete_session(db: &Connection, session_id: &str) -> rusqlite::Result<usize> {
+    let tx = db.unchecked_transaction()?;
+    let removed = tx.execute(
+        "DELETE FROM messages WHERE session_id = ?1",
+        params![session_id],
+    )?;
+    for table in [
+        "drafts",
+        "attachments",
+        "reminders",
+        "schedules",
+        "sync_conflicts",
+        "session_digests",
+    ] {
+        tx.execute(
+            &format!("DELETE FROM {} WHERE session_id = ?1", table),
+            params![session_id],
+        )?;
+    }
+    tx.execute("DELETE FROM sessions WHERE id = ?1", params![session_id])?;
+    tx.commit()?;
+
+    let _ = fs::remove_dir_all(Path::new("./attachments").join(session_id));
+    after_delete(db, removed);
+    Ok(removed)
+}
+
+/// `unpack(x)` gives back the text of a zstd-compressed blob and passes any
+/// other value through, so queries read compressed and plain rows alike.
+pub fn register_functions(db: &Connection) {
+    let _ = db.create_scalar_function(
+        "unpack",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| match ctx.get_raw(0) {
+            ValueRef::Blob(bytes) if bytes.starts_with(&ZSTD_MAGIC) => zstd::decode_all(bytes)
+                .map(|text| Value::Text(String::from_utf8_lossy(&text).into_owned()))
+                .map_err(|e| rusqlite::Error::UserFunctionError(e.into())),
+            other => Ok(Value::from(other)),
+        },
+    );
+}
+
+/// Compresses up to `batch` stored message texts, or with `compress` false
+/// restores them to plain text. Returns how many were rewritten, so callers
+/// repeat until it's 0. Messages still streaming in are left alone.
+pub fn repack_history(db: &Connection, compress: bool, batch: usize) -> usize {
+    let Ok(tx) = db.unchecked_transaction() else {
+        return 0;
+    };
+    let mut changed = 0;
+    for (table, filter) in [("messages", "partial = 0"), ("blobs", "1")] {
+        if !compress {
+            changed += tx
+                .execute(
+                    &format!(
+                        "UPDATE {table} SET content = unpack(content) WHERE rowid IN
+                         (SELECT rowid FROM {table} WHERE typeof(content) = 'blob' LIMIT ?1)"
+                    ),
+                    params![batch as i64],
+                )
+                .unwrap_or(0);
+            continue;
+        }
+        let rows: Vec<(i64, String)> = tx
+            .prepare(&format!(
+                "SELECT rowid, content FROM {table} WHERE typeof(content) = 'text'
+                 AND length(CAST(content AS BLOB)) >= ?1 AND {filter} LIMIT ?2"
+            ))
+            .and_then(|mut stmt| {
+                stmt.query_map(params![COMPRESS_MIN_BYTES as i64, batch as i64], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?
+                .collect()
+            })
+            .unwrap_or_default();
+        for (row_id, text) in rows {
+            let Ok(packed) = zstd::encode_all(text.as_bytes(), COMPRESS_LEVEL) else {
+                continue;
+            };
+            let _ = tx.execute(
+                &format!("UPDATE {table} SET content = ?1 WHERE rowid = ?2"),
+                params![packed, row_id],
+            );
+            changed += 1;
+        }
+    }
+    let _ = tx.commit();
+    changed
+}
+
+/// Called after bulk deletions; returns freed pages to the filesystem when
+/// enough rows went away to make it worthwhile.
+pub fn after_delete(db: &Connection, removed_rows: usize) {
+    let _ = db.execute(
+        "DELETE FROM blobs WHERE hash NOT IN (SELECT blob FROM messages WHERE blob IS NOT NULL)",
+        [],
+    );
+    for table in ["message_stats", "message_context", "message_variants"] {
+        let _ = db.execute(
+            &format!(
+                "DELETE FROM {} WHERE message_id NOT IN (SELECT id FROM messages)",
+                table
+            ),
+            [],
+        );
+    }
+    if removed_rows >= AUTO_VACUUM_THRESHOLD {
+        let _ = db.execute_batch("PRAGMA incremental_vacuum;");
+    }
+}
+
+/// Integrity check, VACUUM and ANALYZE on a dedicated connection, reporting
+/// each step through `progress`. Returns the integrity check verdict.
+pub fn run_maintenance(path: &str, progress: impl Fn(&str)) -> Result<String, String> {
+    let db = Connection::open(path).map_err(|e| e.to_string())?;
+
+    progress("Checking integrity (1/3)…");
+    let verdict: String = db
+        .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if verdict != "ok" {
+        return Err(format!("Integrity check failed: {}", verdict));
+    }
+
+    progress("Compacting database (2/3)…");
+    db.execute_batch("PRAGMA auto_vacuum = INCREMENTAL; VACUUM;")
+        .map_err(|e| e.to_string())?;
+
+    progress("Updating query statistics (3/3)…");
+    db.execute_batch("ANALYZE;").map_err(|e| e.to_string())?;
+
+    Ok(verdict)
+}
+
+/// Longest title kept in the sidebar, in characters.
+const MAX_TITLE_CHARS: usize = 60;
+
+/// Collapses whitespace and caps the length, cutting at a word boundary
+/// where there is one.
+fn clean_title(title: &str) -> String {
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    if title.chars().count() <= MAX_TITLE_CHARS {
+        return title;
+    }
+    let cut: String = title.chars().take(MAX_TITLE_CHARS).collect();
+    let cut = match cut.rfind(' ') {
+        Some(pos) if pos > MAX_TITLE_CHARS / 2 => &cut[..pos],
+        _ => cut.as_str(),
+    };
+    format!(
+        "{}…",
+        cut.trim_end_matches(|c: char| c.is_ascii_punctuation())
+    )
+}
+
+/// Title for a new session, taken from the first non-empty line of its
+/// opening prompt with leading markdown markers stripped.
+pub fn title_from_prompt(prompt: &str) -> String {
+    let line = prompt
+        .lines()
+        .map(|l| {
+            l.trim()
+                .trim_start_matches(['#', '>', '-', '*', '`'])
+                .trim()
+        })
+        .find(|l| !l.is_empty())
+        .unwrap_or("New chat");
+    clean_title(line)
+}
+
+/// Single write path for session titles (inline edit and rename).
+pub fn update_session_title(db: &Connection, session_id: &str, title: &str) -> bool {
+    let title = clean_title(title);
+    if title.is_empty() {
+        return false;
+    }
+    db.execute(
+        "UPDATE sessions SET title = ?1 WHERE id = ?2",
+        params![title, session_id],
+    )
+    .map(|n| n > 0)
+    .unwrap_or(false)
+}
+
+pub fn set_session_summary(db: &Connection, session_id: &str, summary: &str) {
+    let _ = db.execute(
+        "UPDATE sessions SET summary = ?1 WHERE id = ?2",
+        params![summary.trim(), session_id],
+    );
+}
+
+pub fn session_exists(db: &Connection, session_id: &str) -> bool {
+    db.query_row(
+        "SELECT COUNT(*) > 0 FROM sessions WHERE id = ?1 AND deleted = 0",
+        params![session_id],
+        |row| row.get(0),
+    )
+    .unwrap_or(false)
+}
+
+pub fn session_system_prompt(db: &Connection, session_id: &str) -> String {
+    db.query_row(
+        "SELECT system_prompt FROM sessions WHERE id = ?1",
+        params![session_id],
+        |row| row.get::<usize, Option<String>>(0),
+    )
+    .ok()
+    .flatten()
+    .unwrap_or_default()
+}
+
+pub fn set_session_system_prompt(db: &Connection, session_id: &str, prompt: &str) {
+    let _ = db.execute(
+        "UPDATE sessions SET system_prompt = ?1 WHERE id = ?2",
+        params![prompt, session_id],
+    );
+}
+
+/// Output format ("", "json" or "bullets") and answer language ("" for any)
+/// toggled for a session.
+pub fn session_reply_style(db: &Connection, session_id: &str) -> (String, String) {
+    db.query_row(
+        "SELECT reply_format, reply_language FROM sessions WHERE id = ?1",
+        params![session_id],
+        |row| {
+            Ok((
+                row.get::<usize, Option<String>>(0)?.unwrap_or_default(),
+                row.get::<usize, Option<String>>(1)?.unwrap_or_default(),
+            ))
+        },
+    )
+    .unwrap_or_default()
+}
+
+pub fn set_session_reply_style(db: &Connection, session_id: &str, format: &str, language: &str) {
+    let _ = db.execute(
+        "UPDATE sessions SET reply_format = ?1, reply_language = ?2 WHERE id = ?3",
+        params![format, language, session_id],
+    );
+}
+
+/// Sampling options set for a session, stored as JSON.
+pub fn session_options(db: &Connection, session_id: &str) -> SamplingOptions {
+    db.query_row(
+        "SELECT options FROM sessions WHERE id = ?1",
+        params![session_id],
+        |row| row.get::<usize, Option<String>>(0),
+    )
+    .ok()
+    .flatten()
+    .and_then(|json| serde_json::from_str(&json).ok())
+    .unwrap_or_default()
+}
+
+pub fn set_session_options(db: &Connection, session_id: &str, options: &SamplingOptions) {
+    let _ = db.execute(
+        "UPDATE sessions SET options = ?1 WHERE id = ?2",
+        params![
+            serde_json::to_string(options).unwrap_or_default(),
+            session_id
+        ],
+    );
+}
+
+pub fn set_session_locked(db: &Connection, session_id: &str, locked: bool) {
+    let _ = db.execute(
+        "UPDATE sessions SET locked = ?1 WHERE id = ?2",
+        params![locked, session_id],
+    );
+}
+
+/// Titles the user gave a session themselves are kept when its opening
+/// prompt is edited and don't get replaced by a model suggestion.
+pub fn set_title_locked(db: &Connection, session_id: &str, locked: bool) {
+    let _ = db.execute(
+        "UPDATE sessions SET title_locked = ?1 WHERE id = ?2",
+        params![locked, session_id],
+    );
+}
+
+pub fn is_title_locked(db: &Connection, session_id: &str) -> bool {
+    db.query_row(
+        "SELECT title_locked FROM sessions WHERE id = ?1",
+        params![session_id],
+        |row| row.get::<usize, Option<i64>>(0),
+    )
+    .ok()
+    .flatten()
+    .unwrap_or(0)
+        != 0
+}
+
+/// Read-only sessions take no new messages, renames or attachment changes.
+pub fn is_session_locked(db: &Connection, session_id: &str) -> bool {
+    db.query_row(
+        "SELECT locked FROM sessions WHERE id = ?1",
+        params![session_id],
+        |row| row.get::<usize, Option<i64>>(0),
+    )
+    .ok()
+    .flatten()
+    .unwrap_or(0)
+        != 0
+}
+
+/// The stored summary if the session's last reply is more than `days` old,
+/// i.e. the model has probably lost the thread along with the user.
+pub fn stale_session_summary(db: &Connection, session_id: &str, days: f64) -> Option<String> {
+    db.query_row(
+        "SELECT summary FROM sessions WHERE id = ?1 AND summary IS NOT NULL AND summary != ''
+         AND julianday('now') - (SELECT julianday(MAX(created_at)) FROM messages
+                                 WHERE session_id = ?1 AND role = 'assistant') > ?2",
+        params![session_id, days],
+        |row| row.get(0),
+    )
+    .ok()
+}
+
+const SESSION_ICONS: [&str; 24] = [
+    "💬", "🧠", "📚", "🛠", "🧪", "🎨", "🚀", "🌱", "🔍", "📝", "🎧", "🧩", "🗺", "⚙", "📊", "🔥",
+    "🌙", "🍀", "🐙", "🦊", "🐝", "🎯", "💡", "🧭",
+];
+
+/// Stable fallback icon derived from the session id (FNV-1a).
+pub fn default_session_icon(session_id: &str) -> &'static str {
+    let hash = session_id.bytes().fold(0xcbf29ce484222325u64, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    SESSION_ICONS[(hash % SESSION_ICONS.len() as u64) as usize]
+}
+
+pub fn touch_recent_file(db: &Connection, path: &Path, name: &str) {
+    let _ = db.execute(
+        "INSERT OR REPLACE INTO recent_files (path, name, last_used) VALUES (?1, ?2, datetime('now'))",
+        params![path.to_string_lossy(), name],
+    );
+}
+
+/// Most recently attached source paths that still exist, newest first.
+pub fn recent_files(db: &Connection, limit: usize) -> Vec<(String, String)> {
+    let mut stmt = db
+        .prepare("SELECT path, name FROM recent_files ORDER BY last_used DESC LIMIT ?1")
+        .unwrap();
+    stmt.query_map(params![limit as i64], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .flatten()
+        .filter(|(path, _): &(String, String)| Path::new(path).is_file())
+        .collect()
+}
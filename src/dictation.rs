@@ -0,0 +1,101 @@
+use enigo::{Enigo, Keyboard, Settings};
+use global_hotkey::hotkey::HotKey;
+use global_hotkey::{GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+/// Used unless `dictation_hotkey` names another chord.
+pub const DEFAULT_HOTKEY: &str = "ctrl+alt+Space";
+
+/// The system-wide shortcut that answers the text selected in another app.
+/// The manager has to be made on the main thread and kept there.
+pub struct Hotkey {
+    manager: GlobalHotKeyManager,
+    registered: Option<HotKey>,
+}
+
+impl Hotkey {
+    pub fn new() -> Result<Self, String> {
+        let manager = GlobalHotKeyManager::new().map_err(|e| e.to_string())?;
+        Ok(Self {
+            manager,
+            registered: None,
+        })
+    }
+
+    /// Registers `chord`, e.g. "ctrl+alt+Space", in place of the earlier
+    /// one. `None` only unregisters.
+    pub fn set(&mut self, chord: Option<&str>) -> Result<(), String> {
+        if let Some(old) = self.registered.take() {
+            let _ = self.manager.unregister(old);
+        }
+        let Some(chord) = chord else {
+            return Ok(());
+        };
+        let hotkey: HotKey = chord.parse().map_err(|e| format!("{}: {}", chord, e))?;
+        self.manager.register(hotkey).map_err(|e| e.to_string())?;
+        self.registered = Some(hotkey);
+        Ok(())
+    }
+}
+
+/// Calls `pressed` from a thread of its own whenever the hotkey goes down,
+/// whichever app has the focus.
+pub fn on_pressed(pressed: impl Fn() + Send + 'static) {
+    std::thread::spawn(move || {
+        while let Ok(event) = GlobalHotKeyEvent::receiver().recv() {
+            if event.state == HotKeyState::Pressed {
+                pressed();
+            }
+        }
+    });
+}
+
+/// Types text into whichever window has the keyboard focus, as if the user
+/// were typing it. The system paces synthetic keystrokes, so they go out
+/// from a thread of their own while the reply keeps streaming in.
+pub struct Typist {
+    sender: mpsc::Sender<String>,
+    // Set by `stop`; otherwise queued text is still typed after a drop
+    stopped: Arc<AtomicBool>,
+}
+
+impl Typist {
+    pub fn start() -> Result<Self, String> {
+        let (sender, receiver) = mpsc::channel::<String>();
+        let (ready, started) = mpsc::channel();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let t_stopped = stopped.clone();
+        std::thread::spawn(move || {
+            let mut enigo = match Enigo::new(&Settings::default()) {
+                Ok(enigo) => {
+                    let _ = ready.send(Ok(()));
+                    enigo
+                }
+                Err(e) => {
+                    let _ = ready.send(Err(e.to_string()));
+                    return;
+                }
+            };
+            for text in receiver {
+                if t_stopped.load(Ordering::Relaxed) || enigo.text(&text).is_err() {
+                    break;
+                }
+            }
+        });
+        started
+            .recv()
+            .map_err(|_| "keyboard input unavailable".to_string())??;
+        Ok(Self { sender, stopped })
+    }
+
+    /// Queues `text` to be typed. False once typing has failed.
+    pub fn type_text(&self, text: &str) -> bool {
+        self.sender.send(text.to_string()).is_ok()
+    }
+
+    /// Drops whatever is still waiting to be typed.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
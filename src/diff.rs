@@ -0,0 +1,148 @@
+/// Where a piece of text ended up when comparing two versions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    Same,
+    Removed,
+    Added,
+}
+
+// Past this many unit pairs the comparison table gets too big to build, and
+// the versions are shown as wholly replaced
+const MAX_CELLS: usize = 4_000_000;
+
+/// Splits text into the pieces a diff compares: lines, and the sentences of
+/// prose lines, so one reworded sentence doesn't mark a whole paragraph as
+/// changed. Lines in code blocks are kept whole.
+pub fn units(text: &str) -> Vec<String> {
+    let mut units = Vec::new();
+    let mut in_code = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            units.push(line.to_string());
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        if in_code {
+            units.push(line.to_string());
+            continue;
+        }
+        let mut start = 0;
+        let mut chars = line.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            // A single word before the stop is a list number or an
+            // abbreviation, not a sentence
+            let ends_sentence = matches!(c, '.' | '!' | '?')
+                && chars.peek().is_some_and(|(_, next)| next.is_whitespace())
+                && line[start..i].trim().contains(' ');
+            if ends_sentence {
+                units.push(line[start..=i].trim().to_string());
+                start = i + 1;
+            }
+        }
+        if !line[start..].trim().is_empty() {
+            units.push(line[start..].trim().to_string());
+        }
+    }
+    units
+}
+
+/// `old` and `new` as one sequence of units, each marked as in both, only
+/// in `old` or only in `new`. Removed units come before the ones replacing
+/// them.
+pub fn diff(old: &str, new: &str) -> Vec<(Change, String)> {
+    let a = units(old);
+    let b = units(new);
+    if a.len().saturating_mul(b.len()) > MAX_CELLS {
+        return a
+            .into_iter()
+            .map(|u| (Change::Removed, u))
+            .chain(b.into_iter().map(|u| (Change::Added, u)))
+            .collect();
+    }
+    // lcs[i][j]: longest common run of units in a[i..] and b[j..]
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = if a[i] == b[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+    let mut out = Vec::with_capacity(a.len().max(b.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            out.push((Change::Same, a[i].clone()));
+            i += 1;
+            j += 1;
+        } else if j == b.len()
+            || (i < a.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1])
+        {
+            out.push((Change::Removed, a[i].clone()));
+            i += 1;
+        } else {
+            out.push((Change::Added, b[j].clone()));
+            j += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn side(diff: &[(Change, String)], skip: Change) -> Vec<String> {
+        diff.iter()
+            .filter(|(change, _)| *change != skip)
+            .map(|(_, unit)| unit.clone())
+            .collect()
+    }
+
+    #[test]
+    fn diff_rebuilds_both_versions() {
+        let old = "First sentence here. Second one stays.\n\n```\nlet x = 1;\n```\nLast line";
+        let new =
+            "First sentence changed. Second one stays.\n\n```\nlet x = 2;\n```\nLast line\nExtra";
+        let diff = diff(old, new);
+        assert_eq!(side(&diff, Change::Added), units(old));
+        assert_eq!(side(&diff, Change::Removed), units(new));
+    }
+
+    #[test]
+    fn reworded_sentence_leaves_the_rest_alone() {
+        let diff = diff(
+            "The cat sat down. The dog ran off.",
+            "The cat stood up. The dog ran off.",
+        );
+        assert_eq!(
+            diff,
+            vec![
+                (Change::Removed, "The cat sat down.".to_string()),
+                (Change::Added, "The cat stood up.".to_string()),
+                (Change::Same, "The dog ran off.".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn list_numbers_and_code_are_not_split() {
+        assert_eq!(
+            units("1. Do this. Then that.\n```\na. b. c d.\n```"),
+            vec!["1. Do this.", "Then that.", "```", "a. b. c d.", "```"]
+        );
+    }
+
+    #[test]
+    fn identical_texts_are_all_same() {
+        let diff = diff("One two three. Four five.", "One two three. Four five.");
+        assert!(diff.iter().all(|(change, _)| *change == Change::Same));
+        assert_eq!(diff.len(), 2);
+    }
+}
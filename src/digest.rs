@@ -0,0 +1,63 @@
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// Summary sent to the model in place of a long session's older turns. The
+/// turns themselves stay in `messages` and on screen; only requests are
+/// built from the digest.
+pub struct Digest {
+    pub content: String,
+    /// Last message the summary covers
+    pub through_id: i64,
+}
+
+pub fn init_table(db: &Connection) {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS session_digests (session_id TEXT PRIMARY KEY, content TEXT, through_id INTEGER, created_at DATETIME)",
+        [],
+    )
+    .unwrap();
+}
+
+pub fn load(db: &Connection, session_id: &str) -> Option<Digest> {
+    db.query_row(
+        "SELECT content, through_id FROM session_digests WHERE session_id = ?1",
+        params![session_id],
+        |row| {
+            Ok(Digest {
+                content: row.get(0)?,
+                through_id: row.get(1)?,
+            })
+        },
+    )
+    .optional()
+    .ok()
+    .flatten()
+}
+
+pub fn save(db: &Connection, session_id: &str, content: &str, through_id: i64) {
+    let _ = db.execute(
+        "INSERT OR REPLACE INTO session_digests (session_id, content, through_id, created_at)
+         VALUES (?1, ?2, ?3, datetime('now'))",
+        params![session_id, content, through_id],
+    );
+}
+
+/// Drops the digest once message `row_id` or one before it is rewritten,
+/// since it summarizes a conversation that no longer happened.
+pub fn forget_from(db: &Connection, session_id: &str, row_id: i64) {
+    let _ = db.execute(
+        "DELETE FROM session_digests WHERE session_id = ?1 AND through_id >= ?2",
+        params![session_id, row_id],
+    );
+}
+
+/// How many of the messages a request is built from the digest stands in
+/// for: those up to `through_id` that weren't deleted or left out.
+pub fn covered(db: &Connection, session_id: &str, through_id: i64) -> usize {
+    db.query_row(
+        "SELECT COUNT(*) FROM messages
+         WHERE session_id = ?1 AND id <= ?2 AND deleted = 0 AND COALESCE(excluded, 0) = 0",
+        params![session_id, through_id],
+        |row| row.get::<usize, i64>(0),
+    )
+    .unwrap_or(0) as usize
+}
@@ -0,0 +1,87 @@
+use rusqlite::{params, Connection};
+use serde_json::json;
+
+use crate::{db, instance, sync};
+
+struct SessionHeader {
+    title: String,
+    created_at: String,
+}
+
+fn header(db: &Connection, session_id: &str) -> SessionHeader {
+    db.query_row(
+        "SELECT title, created_at FROM sessions WHERE id = ?1",
+        params![session_id],
+        |row| {
+            Ok(SessionHeader {
+                title: row.get::<usize, Option<String>>(0)?.unwrap_or_default(),
+                created_at: row.get::<usize, Option<String>>(1)?.unwrap_or_default(),
+            })
+        },
+    )
+    .unwrap_or(SessionHeader {
+        title: "New chat".into(),
+        created_at: String::new(),
+    })
+}
+
+fn attachment_names(db: &Connection, session_id: &str) -> Vec<String> {
+    db::attachment_library(db, session_id)
+        .into_iter()
+        .map(|(name, ..)| name)
+        .collect()
+}
+
+/// The session as one JSON document: title, system prompt, the files that
+/// were attached and every finished message with its time and model.
+pub fn to_json(db: &Connection, session_id: &str) -> serde_json::Value {
+    let header = header(db, session_id);
+    json!({
+        "title": header.title,
+        "created_at": header.created_at,
+        "system_prompt": db::session_system_prompt(db, session_id),
+        "attachments": attachment_names(db, session_id),
+        "messages": sync::export_messages(db, session_id),
+    })
+}
+
+/// The session as a Markdown document with a heading per message.
+pub fn to_markdown(db: &Connection, session_id: &str) -> String {
+    let header = header(db, session_id);
+    let mut out = format!("# {}\n\n", header.title);
+    if !header.created_at.is_empty() {
+        out.push_str(&format!("Started {}\n\n", header.created_at));
+    }
+    out.push_str(&format!(
+        "[Open in Ollama Native]({})\n\n",
+        instance::session_link(session_id, None)
+    ));
+    let attachments = attachment_names(db, session_id);
+    if !attachments.is_empty() {
+        out.push_str(&format!("Attachments: {}\n\n", attachments.join(", ")));
+    }
+    let system_prompt = db::session_system_prompt(db, session_id);
+    if !system_prompt.trim().is_empty() {
+        out.push_str("## System prompt\n\n");
+        out.push_str(system_prompt.trim());
+        out.push_str("\n\n");
+    }
+    for m in sync::export_messages(db, session_id) {
+        let speaker = match m["role"].as_str().unwrap_or("") {
+            "user" => "You".to_string(),
+            "assistant" => match m["model"].as_str() {
+                Some(model) => format!("Assistant ({})", model),
+                None => "Assistant".to_string(),
+            },
+            "system" => "System".to_string(),
+            _ => "Tool".to_string(),
+        };
+        match m["created_at"].as_str() {
+            Some(time) => out.push_str(&format!("## {} · {}\n\n", speaker, time)),
+            None => out.push_str(&format!("## {}\n\n", speaker)),
+        }
+        out.push_str(m["content"].as_str().unwrap_or("").trim());
+        out.push_str("\n\n");
+    }
+    out
+}
@@ -0,0 +1,274 @@
+use rusqlite::{params, Connection};
+use std::fs;
+use std::path::Path;
+
+use crate::tokens::TokenCounter;
+
+/// Text pulled out of an attached file and its token count.
+pub struct Extracted {
+    pub text: String,
+    pub tokens: usize,
+}
+
+/// Put where text was cut from an oversized attachment; also how a
+/// truncated copy is recognised later.
+pub const TRUNCATION_MARKER: &str = "\n[… truncated to fit the attachment size limit …]\n";
+
+/// Size limits for attached files, from `attach_max_kb`, `attach_total_kb`
+/// and `attach_truncate`.
+pub struct Limits {
+    pub max_file: u64,
+    pub max_total: u64,
+    // Keep the end of the file as well as the start; None rejects big files
+    pub truncate: Option<bool>,
+}
+
+impl Limits {
+    pub fn from_config(cfg: &serde_json::Value) -> Self {
+        Limits {
+            max_file: cfg["attach_max_kb"].as_u64().unwrap_or(256) * 1024,
+            max_total: cfg["attach_total_kb"].as_u64().unwrap_or(1024) * 1024,
+            truncate: match cfg["attach_truncate"].as_str().unwrap_or("head") {
+                "reject" => None,
+                "head_tail" => Some(true),
+                _ => Some(false),
+            },
+        }
+    }
+}
+
+fn floor_char_boundary(text: &str, mut at: usize) -> usize {
+    while !text.is_char_boundary(at) {
+        at -= 1;
+    }
+    at
+}
+
+/// Cuts `text` down to roughly `max_bytes`, keeping the start and, with
+/// `keep_tail`, the last third as well.
+pub fn truncate(text: &str, max_bytes: usize, keep_tail: bool) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+    let (head_len, tail_len) = if keep_tail {
+        (max_bytes * 2 / 3, max_bytes / 3)
+    } else {
+        (max_bytes, 0)
+    };
+    let mut out = text[..floor_char_boundary(text, head_len)].to_string();
+    out.push_str(TRUNCATION_MARKER);
+    if tail_len > 0 {
+        let mut start = text.len() - tail_len;
+        while !text.is_char_boundary(start) {
+            start += 1;
+        }
+        out.push_str(&text[start..]);
+    }
+    out
+}
+
+/// PNG and JPEG files, which multimodal models take as images rather than
+/// as prompt text. Checked by content so a misnamed file still works.
+pub fn is_image(path: &Path) -> bool {
+    let mut header = [0u8; 4];
+    let read =
+        fs::File::open(path).and_then(|mut f| std::io::Read::read_exact(&mut f, &mut header));
+    read.is_ok() && (header == [0x89, b'P', b'N', b'G'] || header[..3] == [0xff, 0xd8, 0xff])
+}
+
+/// An image attachment in the base64 form the chat APIs expect.
+pub fn load_image(path: &Path) -> Option<String> {
+    use base64::Engine;
+    let bytes = fs::read(path).ok()?;
+    Some(base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+pub fn is_pdf(path: &Path) -> bool {
+    let mut header = [0u8; 5];
+    let read =
+        fs::File::open(path).and_then(|mut f| std::io::Read::read_exact(&mut f, &mut header));
+    read.is_ok() && &header == b"%PDF-"
+}
+
+/// Text of each page of a PDF. Fails for PDFs without a text layer, such as
+/// scanned documents.
+pub fn pdf_pages(bytes: &[u8]) -> Result<Vec<String>, String> {
+    // The parser panics on some malformed files instead of returning an error
+    let pages = std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem_by_pages(bytes))
+        .map_err(|_| "the PDF couldn't be parsed".to_string())?
+        .map_err(|e| e.to_string())?;
+    if pages.iter().all(|p| p.trim().is_empty()) {
+        return Err("the PDF has no text layer".into());
+    }
+    Ok(pages)
+}
+
+/// Joins PDF pages under page headings, stopping at the first page that
+/// doesn't fit in `max_bytes` so the prompt gets whole pages rather than a
+/// cut through the middle of one.
+pub fn join_pages(pages: &[String], max_bytes: usize) -> String {
+    let mut out = String::new();
+    for (i, page) in pages.iter().enumerate() {
+        let page = page.trim();
+        if page.is_empty() {
+            continue;
+        }
+        let chunk = format!("--- Page {} ---\n{}\n\n", i + 1, page);
+        if out.len() + chunk.len() <= max_bytes {
+            out.push_str(&chunk);
+            continue;
+        }
+        let next = if out.is_empty() {
+            // Not even the first page fits; keep what does of it
+            out = truncate(&chunk, max_bytes, false);
+            i + 2
+        } else {
+            out.push_str(TRUNCATION_MARKER);
+            i + 1
+        };
+        if next <= pages.len() {
+            out.push_str(&format!(
+                "[pages {}–{} of {} left out]\n",
+                next,
+                pages.len(),
+                pages.len()
+            ));
+        }
+        break;
+    }
+    out
+}
+
+pub fn is_truncated(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .map(|text| text.contains(TRUNCATION_MARKER))
+        .unwrap_or(false)
+}
+
+/// How attachment text is wrapped into the prompt, from `attachment_format`.
+/// Some models take the legacy bracketed names for part of the question.
+pub enum InjectFormat {
+    Plain,
+    Xml,
+    Markdown,
+    // `{name}` and `{content}` are filled in per file
+    Custom(String),
+}
+
+impl InjectFormat {
+    pub fn from_config(cfg: &serde_json::Value) -> Self {
+        match cfg["attachment_format"].as_str().unwrap_or("plain") {
+            "xml" => InjectFormat::Xml,
+            "markdown" => InjectFormat::Markdown,
+            "custom" => match cfg["attachment_template"].as_str() {
+                Some(t) if t.contains("{content}") => InjectFormat::Custom(t.to_string()),
+                _ => InjectFormat::Plain,
+            },
+            _ => InjectFormat::Plain,
+        }
+    }
+
+    /// The block placed ahead of the prompt for `files` as (name, text).
+    pub fn render(&self, files: &[(&str, &str)]) -> String {
+        if files.is_empty() {
+            return String::new();
+        }
+        let mut out = String::new();
+        match self {
+            InjectFormat::Plain => {
+                out.push_str("Context from files:\n");
+                for (name, text) in files {
+                    out.push_str(&format!("[{}]\n{}\n", name, text));
+                }
+            }
+            InjectFormat::Xml => {
+                out.push_str("<documents>\n");
+                for (name, text) in files {
+                    out.push_str(&format!(
+                        "<document name=\"{}\">\n{}\n</document>\n",
+                        name.replace('"', "&quot;"),
+                        text
+                    ));
+                }
+                out.push_str("</documents>\n\n");
+            }
+            InjectFormat::Markdown => {
+                for (name, text) in files {
+                    let lang = Path::new(name)
+                        .extension()
+                        .and_then(|e| e.to_str())
+                        .unwrap_or("");
+                    // A fence longer than any backtick run inside the file
+                    let mut fence = "```".to_string();
+                    while text.contains(fence.as_str()) {
+                        fence.push('`');
+                    }
+                    out.push_str(&format!(
+                        "### {}\n{}{}\n{}\n{}\n\n",
+                        name, fence, lang, text, fence
+                    ));
+                }
+            }
+            InjectFormat::Custom(template) => {
+                for (name, text) in files {
+                    out.push_str(&template.replace("{name}", name).replace("{content}", text));
+                    out.push('\n');
+                }
+            }
+        }
+        out
+    }
+}
+
+pub fn init_table(db: &Connection) {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS attachment_cache (hash TEXT PRIMARY KEY, text TEXT, tokens INTEGER, last_used DATETIME)",
+        [],
+    )
+    .unwrap();
+}
+
+/// FNV-1a over the content plus its length; collisions would need two files
+/// of the same size with the same 64-bit hash.
+fn content_key(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf29ce484222325u64, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}-{}", hash, bytes.len())
+}
+
+/// Reads an attachment, reusing the cached extraction and token count when a
+/// file with the same content was seen before. Returns `None` for files that
+/// aren't text.
+pub fn load(db: &Connection, counter: &TokenCounter, path: &Path) -> Option<Extracted> {
+    let bytes = fs::read(path).ok()?;
+    let key = content_key(&bytes);
+
+    let cached = db
+        .query_row(
+            "SELECT text, tokens FROM attachment_cache WHERE hash = ?1",
+            params![key],
+            |row| {
+                Ok(Extracted {
+                    text: row.get(0)?,
+                    tokens: row.get::<usize, i64>(1)? as usize,
+                })
+            },
+        )
+        .ok();
+    if let Some(hit) = cached {
+        let _ = db.execute(
+            "UPDATE attachment_cache SET last_used = datetime('now') WHERE hash = ?1",
+            params![key],
+        );
+        return Some(hit);
+    }
+
+    let text = String::from_utf8(bytes).ok()?;
+    let tokens = counter.count(&text);
+    let _ = db.execute(
+        "INSERT OR REPLACE INTO attachment_cache (hash, text, tokens, last_used) VALUES (?1, ?2, ?3, datetime('now'))",
+        params![key, text, tokens as i64],
+    );
+    Some(Extracted { text, tokens })
+}
@@ -0,0 +1,72 @@
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+// Below this much free space a full disk is close enough to warn about,
+// whatever the growth rate
+const LOW_SPACE_BYTES: u64 = 512 * 1024 * 1024;
+// Warn when the history, growing at its recent rate, fills the disk within
+// this many days
+const RUNWAY_DAYS: f64 = 30.0;
+// Growth is measured across the daily size samples of this many days
+const GROWTH_WINDOW_DAYS: i64 = 14;
+
+/// Why writes to the history may soon start failing.
+pub enum Warning {
+    LowSpace { free: u64 },
+    FastGrowth { per_day: u64, days_left: u64 },
+}
+
+/// One size of the database file per day, to tell how fast it grows.
+pub fn init_table(db: &Connection) {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS db_size_samples (day TEXT PRIMARY KEY, bytes INTEGER)",
+        [],
+    )
+    .unwrap();
+}
+
+/// Bytes free on the disk holding `path`, if the system says.
+pub fn free_space(path: impl AsRef<Path>) -> Option<u64> {
+    fs2::available_space(path).ok()
+}
+
+/// Notes today's size of the database file and, once there are a few
+/// days of samples, returns its average growth in bytes per day.
+pub fn record_size(db: &Connection, bytes: u64) -> Option<f64> {
+    let _ = db.execute(
+        "INSERT OR REPLACE INTO db_size_samples (day, bytes) VALUES (date('now', 'localtime'), ?1)",
+        params![bytes as i64],
+    );
+    let _ = db.execute(
+        "DELETE FROM db_size_samples WHERE day < date('now', 'localtime', ?1)",
+        params![format!("-{} days", GROWTH_WINDOW_DAYS)],
+    );
+    let ((first_day, first_bytes), (last_day, last_bytes)): ((f64, i64), (f64, i64)) = db
+        .query_row(
+            "SELECT
+                 (SELECT julianday(day) FROM db_size_samples ORDER BY day LIMIT 1),
+                 (SELECT bytes FROM db_size_samples ORDER BY day LIMIT 1),
+                 (SELECT julianday(day) FROM db_size_samples ORDER BY day DESC LIMIT 1),
+                 (SELECT bytes FROM db_size_samples ORDER BY day DESC LIMIT 1)",
+            [],
+            |row| Ok(((row.get(0)?, row.get(1)?), (row.get(2)?, row.get(3)?))),
+        )
+        .ok()?;
+    let days = last_day - first_day;
+    // A day or two of samples is mostly noise from a single long session
+    (days >= 3.0).then(|| (last_bytes - first_bytes) as f64 / days)
+}
+
+/// The most pressing reason to warn about storage, if any.
+pub fn check(free: Option<u64>, growth_per_day: Option<f64>) -> Option<Warning> {
+    let free = free?;
+    if free < LOW_SPACE_BYTES {
+        return Some(Warning::LowSpace { free });
+    }
+    let per_day = growth_per_day.filter(|g| *g > 0.0)?;
+    let days_left = free as f64 / per_day;
+    (days_left < RUNWAY_DAYS).then_some(Warning::FastGrowth {
+        per_day: per_day as u64,
+        days_left: days_left as u64,
+    })
+}
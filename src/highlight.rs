@@ -0,0 +1,70 @@
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+/// A run of code drawn in one colour.
+#[derive(Clone, Debug)]
+pub struct Span {
+    pub text: String,
+    pub color: (u8, u8, u8),
+}
+
+const PLAIN: (u8, u8, u8) = (0xe0, 0xe0, 0xe0);
+
+/// Loading the bundled syntaxes takes a while, so it happens once.
+fn assets() -> &'static (SyntaxSet, Theme) {
+    static ASSETS: OnceLock<(SyntaxSet, Theme)> = OnceLock::new();
+    ASSETS.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults();
+        let theme = themes
+            .themes
+            .remove("base16-ocean.dark")
+            .unwrap_or_default();
+        (SyntaxSet::load_defaults_newlines(), theme)
+    })
+}
+
+/// Highlights `code` for the language named in a fence's info string
+/// (`rust`, `py`, `js`, ...), one list of spans per line. Unknown or missing
+/// languages come back as uncoloured text.
+pub fn lines(code: &str, lang: &str) -> Vec<Vec<Span>> {
+    let (syntaxes, theme) = assets();
+    let token = lang.split_whitespace().next().unwrap_or("");
+    let syntax = syntaxes
+        .find_syntax_by_token(token)
+        .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    LinesWithEndings::from(code)
+        .map(|line| match highlighter.highlight_line(line, syntaxes) {
+            Ok(ranges) => ranges
+                .into_iter()
+                .map(|(style, text)| Span {
+                    text: text.trim_end_matches(['\n', '\r']).replace('\t', "    "),
+                    color: (style.foreground.r, style.foreground.g, style.foreground.b),
+                })
+                .filter(|span| !span.text.is_empty())
+                .collect(),
+            Err(_) => vec![Span {
+                text: line.trim_end().replace('\t', "    "),
+                color: PLAIN,
+            }],
+        })
+        .collect()
+}
+
+/// Fence language for a shebang or first-line marker like `<?php`, when
+/// syntect knows one.
+pub fn language_from_first_line(code: &str) -> Option<String> {
+    let (syntaxes, _) = assets();
+    let first = code.lines().next()?;
+    let syntax = syntaxes.find_syntax_by_first_line(first)?;
+    Some(
+        syntax
+            .file_extensions
+            .first()
+            .cloned()
+            .unwrap_or_else(|| syntax.name.to_lowercase()),
+    )
+}
@@ -0,0 +1,384 @@
+use rusqlite::{params, Connection};
+use serde_json::json;
+use std::collections::HashMap;
+
+use crate::{db, sync};
+
+/// A session read from an export file that matches one already in the
+/// database, waiting for the user to pick what happens to it.
+pub struct Conflict {
+    /// In the sync bundle's session shape, see `sync::export`
+    pub session: serde_json::Value,
+    pub existing_id: String,
+    /// Same messages as the existing session, not just the same start
+    pub identical: bool,
+}
+
+/// How an import conflict is settled.
+pub enum Resolution {
+    Skip,
+    /// Messages past the part both copies share are appended to the existing
+    /// session
+    Merge,
+    /// Imported as a separate session next to the existing one
+    Duplicate,
+}
+
+/// Outcome of reading an export file.
+pub struct ImportReport {
+    pub imported: usize,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Unix seconds as the `datetime('now')` format used by the messages table.
+fn sql_datetime(db: &Connection, secs: Option<f64>) -> Option<String> {
+    db.query_row("SELECT datetime(?1, 'unixepoch')", params![secs?], |row| {
+        row.get(0)
+    })
+    .ok()
+    .flatten()
+}
+
+/// Text of a ChatGPT message, whose content is a list of parts that are
+/// strings for text and objects for images and other attachments.
+fn chatgpt_text(content: &serde_json::Value) -> String {
+    content["parts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|p| p.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// ChatGPT's `conversations.json` stores each conversation as a tree of
+/// edits; the shown branch is found by walking up from `current_node`.
+fn parse_chatgpt(db: &Connection, conversation: &serde_json::Value) -> serde_json::Value {
+    let mapping = &conversation["mapping"];
+    let mut node = conversation["current_node"].as_str();
+    let mut messages = Vec::new();
+    while let Some(id) = node {
+        let message = &mapping[id]["message"];
+        let role = message["author"]["role"].as_str().unwrap_or("");
+        let content = chatgpt_text(&message["content"]);
+        if (role == "user" || role == "assistant") && !content.trim().is_empty() {
+            messages.push(json!({
+                "role": role,
+                "content": content,
+                "created_at": sql_datetime(db, message["create_time"].as_f64()),
+            }));
+        }
+        node = mapping[id]["parent"].as_str();
+    }
+    messages.reverse();
+    json!({
+        "title": conversation["title"].as_str().unwrap_or("Imported chat"),
+        "created_at": sql_datetime(db, conversation["create_time"].as_f64()),
+        "messages": messages,
+    })
+}
+
+fn parse_open_webui(db: &Connection, entry: &serde_json::Value) -> serde_json::Value {
+    let chat = &entry["chat"];
+    let messages: Vec<serde_json::Value> = chat["messages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|m| matches!(m["role"].as_str(), Some("user" | "assistant")))
+        .map(|m| {
+            json!({
+                "role": m["role"],
+                "content": m["content"].as_str().unwrap_or(""),
+                "created_at": sql_datetime(db, m["timestamp"].as_f64()),
+                "model": m["model"],
+            })
+        })
+        .collect();
+    json!({
+        "title": entry["title"].as_str().or(chat["title"].as_str()).unwrap_or("Imported chat"),
+        "created_at": sql_datetime(db, entry["created_at"].as_f64()),
+        "messages": messages,
+    })
+}
+
+/// Sessions from one of the supported exports: this app's sync bundle, a
+/// single chat exported as JSON or shared as a file, a ChatGPT
+/// `conversations.json` or an Open WebUI chat export.
+pub fn parse(
+    db: &Connection,
+    export: &serde_json::Value,
+) -> Result<Vec<serde_json::Value>, String> {
+    if let Some(sessions) = export["sessions"].as_array() {
+        return Ok(sessions.clone());
+    }
+    // Already in the session shape, see `export::to_json` and `share::export`
+    if export["messages"].is_array() {
+        return Ok(vec![export.clone()]);
+    }
+    if export["session"]["messages"].is_array() {
+        return Ok(vec![export["session"].clone()]);
+    }
+    let entries = export
+        .as_array()
+        .ok_or("not a chat export this app can read")?;
+    let sessions: Vec<serde_json::Value> = entries
+        .iter()
+        .filter_map(|entry| {
+            if entry["messages"].is_array() {
+                Some(entry.clone())
+            } else if entry.get("mapping").is_some() {
+                Some(parse_chatgpt(db, entry))
+            } else if entry["chat"]["messages"].is_array() {
+                Some(parse_open_webui(db, entry))
+            } else {
+                None
+            }
+        })
+        .collect();
+    if sessions.is_empty() && !entries.is_empty() {
+        return Err("not a chat export this app can read".into());
+    }
+    Ok(sessions)
+}
+
+/// Content hashes of the local sessions: of all their messages, and of just
+/// the opening message, which a continued copy of a conversation shares.
+struct LocalIndex {
+    by_rev: HashMap<String, String>,
+    by_opening: HashMap<String, String>,
+}
+
+impl LocalIndex {
+    fn build(db: &Connection) -> Self {
+        let mut index = LocalIndex {
+            by_rev: HashMap::new(),
+            by_opening: HashMap::new(),
+        };
+        let mut stmt = db.prepare("SELECT id FROM sessions").unwrap();
+        let ids: Vec<String> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .flatten()
+            .collect();
+        for id in ids {
+            index.add(&id, &sync::export_messages(db, &id));
+        }
+        index
+    }
+
+    fn add(&mut self, id: &str, messages: &[serde_json::Value]) {
+        if messages.is_empty() {
+            return;
+        }
+        self.by_rev.insert(sync::revision(messages), id.to_string());
+        self.by_opening
+            .entry(sync::revision(&messages[..1]))
+            .or_insert_with(|| id.to_string());
+    }
+
+    fn find(&self, messages: &[serde_json::Value]) -> Option<(String, bool)> {
+        if let Some(id) = self.by_rev.get(&sync::revision(messages)) {
+            return Some((id.clone(), true));
+        }
+        let opening = sync::revision(messages.get(..1)?);
+        self.by_opening.get(&opening).map(|id| (id.clone(), false))
+    }
+}
+
+/// A local session with exactly these messages, if there is one.
+pub fn identical_session(db: &Connection, messages: &[serde_json::Value]) -> Option<String> {
+    match LocalIndex::build(db).find(messages)? {
+        (id, true) => Some(id),
+        _ => None,
+    }
+}
+
+fn insert_copy(db: &Connection, session: &serde_json::Value) -> String {
+    let id = uuid::Uuid::new_v4().to_string();
+    let mut session = session.clone();
+    // A fresh copy, not the revision it had wherever it came from
+    session["rev"] = serde_json::Value::Null;
+    sync::replace_session(db, &id, &session);
+    id
+}
+
+/// Imports every session that isn't in the database yet and returns the
+/// ones that are, matched by content rather than id so re-importing the
+/// same export or a later one doesn't pile up copies.
+pub fn import(db: &Connection, sessions: &[serde_json::Value]) -> ImportReport {
+    let mut index = LocalIndex::build(db);
+    let mut report = ImportReport {
+        imported: 0,
+        conflicts: Vec::new(),
+    };
+    for session in sessions {
+        let messages = session["messages"].as_array().cloned().unwrap_or_default();
+        if messages.is_empty() {
+            continue;
+        }
+        match index.find(&messages) {
+            Some((existing_id, identical)) => report.conflicts.push(Conflict {
+                session: session.clone(),
+                existing_id,
+                identical,
+            }),
+            None => {
+                let id = insert_copy(db, session);
+                index.add(&id, &messages);
+                report.imported += 1;
+            }
+        }
+    }
+    report
+}
+
+pub fn resolve(db: &Connection, conflict: &Conflict, resolution: Resolution) {
+    match resolution {
+        Resolution::Skip => {}
+        Resolution::Duplicate => {
+            insert_copy(db, &conflict.session);
+        }
+        Resolution::Merge => {
+            let local = sync::export_messages(db, &conflict.existing_id);
+            let incoming = conflict.session["messages"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default();
+            let shared = local
+                .iter()
+                .zip(&incoming)
+                .take_while(|(a, b)| a["role"] == b["role"] && a["content"] == b["content"])
+                .count();
+            for m in &incoming[shared..] {
+                let row_id = db::insert_message(
+                    db,
+                    &conflict.existing_id,
+                    m["role"].as_str().unwrap_or("user"),
+                    m["content"].as_str().unwrap_or(""),
+                    m["created_at"].as_str(),
+                );
+                let _ = db.execute(
+                    "UPDATE messages SET model = ?1 WHERE rowid = ?2",
+                    params![m["model"].as_str(), row_id],
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_db() -> Connection {
+        let db = Connection::open_in_memory().unwrap();
+        crate::init_db(&db);
+        db
+    }
+
+    #[test]
+    fn parses_chatgpt_export_along_current_branch() {
+        let db = open_db();
+        let export = json!([{
+            "title": "Trip",
+            "create_time": 0.0,
+            "current_node": "c",
+            "mapping": {
+                "root": { "message": null, "parent": null },
+                "sys": {
+                    "message": { "author": { "role": "system" }, "content": { "parts": ["Be brief"] } },
+                    "parent": "root"
+                },
+                "a": {
+                    "message": {
+                        "author": { "role": "user" },
+                        "content": { "parts": ["Where to?", { "asset_pointer": "file-1" }] },
+                        "create_time": 60.0
+                    },
+                    "parent": "sys"
+                },
+                "b": {
+                    "message": { "author": { "role": "assistant" }, "content": { "parts": ["Paris"] } },
+                    "parent": "a"
+                },
+                "c": {
+                    "message": { "author": { "role": "assistant" }, "content": { "parts": ["Rome"] } },
+                    "parent": "a"
+                }
+            }
+        }]);
+        let sessions = parse(&db, &export).unwrap();
+        assert_eq!(sessions.len(), 1);
+        let session = &sessions[0];
+        assert_eq!(session["title"], "Trip");
+        assert_eq!(session["created_at"], "1970-01-01 00:00:00");
+        let messages = session["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["role"], "user");
+        assert_eq!(messages[0]["content"], "Where to?");
+        assert_eq!(messages[0]["created_at"], "1970-01-01 00:01:00");
+        assert_eq!(messages[1]["content"], "Rome");
+    }
+
+    #[test]
+    fn parses_open_webui_export() {
+        let db = open_db();
+        let export = json!([{
+            "title": "Tea",
+            "chat": { "messages": [
+                { "role": "user", "content": "Green or black?", "timestamp": 0 },
+                { "role": "assistant", "content": "Green", "model": "llama3" },
+                { "role": "tool", "content": "ignored" },
+            ] }
+        }]);
+        let sessions = parse(&db, &export).unwrap();
+        let messages = sessions[0]["messages"].as_array().unwrap();
+        assert_eq!(sessions[0]["title"], "Tea");
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1]["model"], "llama3");
+    }
+
+    #[test]
+    fn rejects_unknown_files() {
+        let db = open_db();
+        assert!(parse(&db, &json!({ "hello": "world" })).is_err());
+        assert!(parse(&db, &json!([{ "hello": "world" }])).is_err());
+    }
+
+    #[test]
+    fn reimport_conflicts_and_merges_new_turns() {
+        let db = open_db();
+        let session = json!({ "title": "Tea", "messages": [
+            { "role": "user", "content": "Green or black?" },
+            { "role": "assistant", "content": "Green" },
+        ] });
+        let report = import(&db, std::slice::from_ref(&session));
+        assert_eq!((report.imported, report.conflicts.len()), (1, 0));
+
+        let report = import(&db, std::slice::from_ref(&session));
+        assert_eq!((report.imported, report.conflicts.len()), (0, 1));
+        assert!(report.conflicts[0].identical);
+
+        let mut continued = session.clone();
+        continued["messages"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({ "role": "user", "content": "Why?" }));
+        let report = import(&db, std::slice::from_ref(&continued));
+        let conflict = &report.conflicts[0];
+        assert!(!conflict.identical);
+        resolve(&db, conflict, Resolution::Merge);
+        let merged = sync::export_messages(&db, &conflict.existing_id);
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[2]["content"], "Why?");
+    }
+
+    #[test]
+    fn reads_single_chat_files() {
+        let db = open_db();
+        let chat = json!({ "title": "One", "messages": [{ "role": "user", "content": "hi" }] });
+        assert_eq!(parse(&db, &chat).unwrap(), [chat.clone()]);
+        let shared = json!({ "version": 1, "session": chat.clone() });
+        assert_eq!(parse(&db, &shared).unwrap(), [chat]);
+    }
+}
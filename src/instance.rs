@@ -0,0 +1,158 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+/// URI scheme of links to sessions and messages, see `session_link`
+pub const SCHEME: &str = "ollama-native";
+// Where the running instance writes the port it listens on
+const PORT_FILE: &str = "instance.port";
+// First line the running instance sends, so a stale port file pointing at
+// some other program's socket isn't mistaken for it
+const GREETING: &str = "ollama-native";
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Link that opens `session_id` in the app, scrolled to `message_id` if
+/// given.
+pub fn session_link(session_id: &str, message_id: Option<i64>) -> String {
+    match message_id {
+        Some(id) => format!("{}://session/{}/message/{}", SCHEME, session_id, id),
+        None => format!("{}://session/{}", SCHEME, session_id),
+    }
+}
+
+/// Session id and message row id of a link made by `session_link`.
+pub fn parse_link(link: &str) -> Option<(String, Option<i64>)> {
+    let rest = link
+        .trim()
+        .strip_prefix(SCHEME)?
+        .strip_prefix("://session/")?
+        .trim_end_matches('/');
+    let (session_id, message) = match rest.split_once('/') {
+        Some((session_id, message)) => {
+            let id = message.strip_prefix("message/")?.parse().ok()?;
+            (session_id, Some(id))
+        }
+        None => (rest, None),
+    };
+    (!session_id.is_empty()).then(|| (session_id.to_string(), message))
+}
+
+/// Hands `arg` to an instance that's already running, if there is one, in
+/// which case this process should exit. An empty `arg` just asks it to come
+/// to the front.
+pub fn forward(arg: &str) -> bool {
+    let send = || -> std::io::Result<()> {
+        let port: u16 = std::fs::read_to_string(PORT_FILE)?
+            .trim()
+            .parse()
+            .map_err(std::io::Error::other)?;
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        let mut greeting = String::new();
+        BufReader::new(&stream).read_line(&mut greeting)?;
+        if greeting.trim_end() != GREETING {
+            return Err(std::io::Error::other("not an instance of this app"));
+        }
+        writeln!(stream, "{}", arg.replace('\n', " "))
+    };
+    send().is_ok()
+}
+
+/// Makes this the instance later launches forward to, handing what they
+/// pass to `on_arg` on a thread of its own. Without a socket the app still
+/// works, it just opens a window per launch.
+pub fn listen(on_arg: impl Fn(String) + Send + 'static) {
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, 0)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to listen for other instances: {}", e);
+            return;
+        }
+    };
+    if let Ok(addr) = listener.local_addr() {
+        if let Err(e) = std::fs::write(PORT_FILE, addr.port().to_string()) {
+            eprintln!("Failed to write {}: {}", PORT_FILE, e);
+        }
+    }
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = stream.set_read_timeout(Some(CONNECT_TIMEOUT));
+            let mut line = String::new();
+            let received = writeln!(&stream, "{}", GREETING)
+                .and_then(|_| BufReader::new(&stream).read_line(&mut line));
+            if received.is_ok() {
+                on_arg(line.trim_end().to_string());
+            }
+        }
+    });
+}
+
+/// Makes the system open `ollama-native://` links with this executable,
+/// started in the current directory so it finds the same history.
+pub fn register_scheme() -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let dir = std::env::current_dir().map_err(|e| e.to_string())?;
+    register_for(&exe.to_string_lossy(), &dir.to_string_lossy())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn register_for(exe: &str, dir: &str) -> Result<(), String> {
+    let home = std::env::var("HOME").map_err(|_| "HOME isn't set".to_string())?;
+    let apps = std::path::Path::new(&home).join(".local/share/applications");
+    let desktop = format!("{}-links.desktop", SCHEME);
+    std::fs::create_dir_all(&apps).map_err(|e| e.to_string())?;
+    std::fs::write(
+        apps.join(&desktop),
+        format!(
+            "[Desktop Entry]\nType=Application\nName=Ollama Native\nExec=\"{}\" %u\nPath={}\nNoDisplay=true\nMimeType=x-scheme-handler/{};\n",
+            exe, dir, SCHEME
+        ),
+    )
+    .map_err(|e| e.to_string())?;
+    run(
+        "xdg-mime",
+        &["default", &desktop, &format!("x-scheme-handler/{}", SCHEME)],
+    )
+}
+
+#[cfg(target_os = "windows")]
+fn register_for(exe: &str, dir: &str) -> Result<(), String> {
+    let key = format!(r"HKCU\Software\Classes\{}", SCHEME);
+    // cmd starts in `dir` so the app finds its history there
+    let command = format!(r#"cmd /c start "" /d "{}" "{}" "%1""#, dir, exe);
+    run(
+        "reg",
+        &["add", &key, "/ve", "/d", "URL:Ollama Native", "/f"],
+    )?;
+    run("reg", &["add", &key, "/v", "URL Protocol", "/d", "", "/f"])?;
+    run(
+        "reg",
+        &[
+            "add",
+            &format!(r"{}\shell\open\command", key),
+            "/ve",
+            "/d",
+            &command,
+            "/f",
+        ],
+    )
+}
+
+#[cfg(not(any(all(unix, not(target_os = "macos")), target_os = "windows")))]
+fn register_for(_exe: &str, _dir: &str) -> Result<(), String> {
+    Err("links are registered by the app bundle on this system".into())
+}
+
+#[cfg(any(all(unix, not(target_os = "macos")), target_os = "windows"))]
+fn run(program: &str, args: &[&str]) -> Result<(), String> {
+    let status = std::process::Command::new(program)
+        .args(args)
+        .status()
+        .map_err(|e| format!("{}: {}", program, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} failed ({})", program, status))
+    }
+}
@@ -0,0 +1,39 @@
+use ollama_rs::generation::chat::{ChatMessage, MessageRole};
+use rusqlite::Connection;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// Renders messages as a Markdown section headed by the local time and the
+/// session title, linked to `link` so the note leads back to the chat.
+pub fn format_entry(db: &Connection, title: &str, link: &str, messages: &[ChatMessage]) -> String {
+    let time: String = db
+        .query_row("SELECT strftime('%H:%M', 'now', 'localtime')", [], |row| {
+            row.get(0)
+        })
+        .unwrap_or_default();
+    let mut out = format!("## {} — [{}]({})\n\n", time, title, link);
+    for m in messages {
+        let speaker = match m.role {
+            MessageRole::User => "You",
+            MessageRole::System => "System",
+            _ => "Assistant",
+        };
+        out.push_str(&format!("**{}:** {}\n\n", speaker, m.content.trim()));
+    }
+    out
+}
+
+/// Appends `entry` to today's `YYYY-MM-DD.md` in `dir`, creating both as
+/// needed.
+pub fn append(db: &Connection, dir: &Path, entry: &str) -> std::io::Result<()> {
+    let date: String = db
+        .query_row("SELECT date('now', 'localtime')", [], |row| row.get(0))
+        .map_err(std::io::Error::other)?;
+    fs::create_dir_all(dir)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(format!("{}.md", date)))?;
+    file.write_all(entry.as_bytes())
+}
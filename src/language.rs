@@ -0,0 +1,12 @@
+/// Prompts shorter than this don't carry enough text to tell languages apart
+const MIN_CHARS: usize = 12;
+
+/// English name of the language `text` is written in, when the guess is
+/// confident.
+pub fn detect(text: &str) -> Option<&'static str> {
+    if text.trim().chars().count() < MIN_CHARS {
+        return None;
+    }
+    let info = whatlang::detect(text)?;
+    info.is_reliable().then(|| info.lang().eng_name())
+}
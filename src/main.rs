@@ -1,44 +1,327 @@
 slint::include_modules!();
+mod archive;
+mod backend;
+mod commands;
+mod context;
+mod crash;
+mod db;
+mod dictation;
+mod diff;
+mod digest;
+mod export;
+mod extract;
+mod health;
+mod highlight;
+mod import;
+mod instance;
+mod journal;
+mod language;
+mod markdown;
+mod postprocess;
+mod presets;
+mod prompts;
+mod reminders;
+mod schedule;
+mod search;
+mod settings;
+mod share;
+mod speech;
+mod stats;
+mod sync;
+mod templates;
+mod tokens;
+mod tools;
+mod variants;
+
+use backend::ChatBackend;
 use futures::StreamExt;
-use ollama_rs::generation::chat::{request::ChatMessageRequest, ChatMessage};
-use ollama_rs::Ollama;
+use ollama_rs::generation::chat::{ChatMessage, MessageRole};
+use ollama_rs::generation::images::Image;
 use rusqlite::{params, Connection};
 use slint::{ComponentHandle, Model, SharedString, VecModel};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::AbortHandle;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+const MAX_TOOL_ROUNDS: usize = 5;
+const RECENT_FILES_LIMIT: usize = 10;
+// Message matches listed under the sidebar filter
+const SEARCH_RESULTS: usize = 30;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+const SCHEDULE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const REMINDER_POLL_INTERVAL: Duration = Duration::from_secs(60);
+const HEALTH_POLL_INTERVAL: Duration = Duration::from_secs(30 * 60);
+const DEFAULT_STARTER_PROMPTS: [&str; 3] = [
+    "Explain this concept like I'm new to it: ",
+    "Review this code and point out bugs: ",
+    "Draft a short, friendly email about ",
+];
+const STARTER_RECENT_LIMIT: usize = 3;
+const PARTIAL_FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+// How often a reply being read aloud is checked for having finished
+const SPEECH_POLL_INTERVAL: Duration = Duration::from_millis(300);
+// Chunks arriving in between are shown together, so a long reply isn't
+// copied and re-rendered once per token
+const STREAM_REPAINT_INTERVAL: Duration = Duration::from_millis(40);
+// Longer messages show only their start until expanded; laying out the
+// full text of a pasted document makes the transcript crawl
+const ELIDE_BYTES: usize = 8 * 1024;
+const TOAST_DURATION: Duration = Duration::from_millis(1500);
+// Toasts with an Undo button stay up long enough to reach for it
+const UNDO_TOAST_DURATION: Duration = Duration::from_secs(6);
+// Deletions remembered for undo; older ones stay restorable only until the
+// next start anyway
+const UNDO_LIMIT: usize = 50;
+// Appended to a reply the user stopped, so it isn't mistaken for a full answer
+const STOPPED_MARKER: &str = "\n\n(stopped)";
+// Heads the session summary when it's sent in place of earlier turns
+const SUMMARY_INTRO: &str = "Summary of this conversation so far:\n";
+// Estimated tokens of not yet summarized turns that start a background
+// summary with `auto_summarize` on, unless `auto_summarize_tokens` is set
+const AUTO_SUMMARIZE_TOKENS: u64 = 3000;
+// Latest messages always sent as they are rather than summarized
+const DIGEST_KEEP_RECENT: usize = 6;
+// Replies typed into other apps stand in for what the user would write
+const DICTATION_SYSTEM_PROMPT: &str =
+    "Reply with only the text to insert where the user is writing, without any preamble or Markdown.";
+const CONTINUE_PROMPT: &str =
+    "Your previous reply was cut off. Continue exactly where it stopped, without repeating anything.";
+
+/// What the chat area should explain to the user. Connection problems
+/// take precedence over the state of the open session.
+#[derive(Clone, Debug, PartialEq)]
+enum ViewState {
+    Connecting,
+    Offline(String),
+    NoModels,
+    LoadingSession,
+    EmptySession,
+    Chat,
+}
+
+impl ViewState {
+    fn key(&self) -> &'static str {
+        match self {
+            ViewState::Connecting => "connecting",
+            ViewState::Offline(_) => "offline",
+            ViewState::NoModels => "no-models",
+            ViewState::LoadingSession => "loading",
+            ViewState::EmptySession => "empty",
+            ViewState::Chat => "chat",
+        }
+    }
+
+    fn is_backend_problem(&self) -> bool {
+        matches!(
+            self,
+            ViewState::Connecting | ViewState::Offline(_) | ViewState::NoModels
+        )
+    }
+}
+
+struct ActiveStream {
+    row_id: i64,
+    text: String,
+}
+
+/// What opening a session reads from the database, gathered in one go off
+/// the async runtime.
+struct StoredSession {
+    messages: Vec<db::StoredMessage>,
+    system_prompt: String,
+    reply_style: (String, String),
+    options: backend::SamplingOptions,
+    attachments: Vec<(String, PathBuf, bool)>,
+    draft: String,
+    locked: bool,
+    /// A reminder for it went off, and is now cleared
+    had_reminder: bool,
+}
+
+impl StoredSession {
+    fn read(db: &Connection, session_id: &str) -> Self {
+        let had_reminder = reminders::fired_sessions(db).contains(session_id);
+        if had_reminder {
+            reminders::clear(db, session_id);
+        }
+        Self {
+            messages: db::load_messages(db, session_id),
+            system_prompt: db::session_system_prompt(db, session_id),
+            reply_style: db::session_reply_style(db, session_id),
+            options: db::session_options(db, session_id),
+            attachments: db::session_attachments(db, session_id),
+            draft: db::take_draft(db, session_id).unwrap_or_default(),
+            locked: db::is_session_locked(db, session_id),
+            had_reminder,
+        }
+    }
+}
+
+/// A destructive action that can still be taken back, see `undo_last`.
+enum UndoStep {
+    /// A message hidden from its session
+    Message { session_id: String, row_id: i64 },
+    /// Turns hidden from the end of a session when a reply was retried or
+    /// a prompt edited. Undoing an edit also hides the turns it started
+    Turns {
+        session_id: String,
+        row_ids: Vec<i64>,
+        edited: bool,
+    },
+    /// A session hidden from the history, and whether it was on screen
+    Session { id: String, was_open: bool },
+    /// The chat that was open before "new chat" cleared the view
+    Clear(String),
+}
+
 struct AppState {
-    db: Connection,
+    db: db::Db,
     current_session_id: String,
     chat_history: Vec<ChatMessage>,
     attachments: Vec<(String, PathBuf)>,
+    // Names of attachments that stay on after a send
+    pinned_attachments: HashSet<String>,
+    // System prompt of the displayed session; held here until the session's
+    // first message creates its row
+    system_prompt: String,
+    // Reply format and language toggles of the displayed session, kept the
+    // same way as the system prompt
+    reply_format: String,
+    reply_language: String,
+    // Sampling options of the displayed session, kept the same way too
+    options: backend::SamplingOptions,
+    // Imported sessions that match existing ones, until the user decides
+    import_conflicts: Vec<import::Conflict>,
+    // Other history database being browsed for chats to import
+    archive: Option<archive::Archive>,
+    // Stops the reply being typed into another app, see `dictate`
+    dictation: Option<CancellationToken>,
+    // Message to bring into view once the session being opened has loaded,
+    // set when a history search hit is clicked
+    jump_to_message: Option<i64>,
+    tools: Vec<tools::ToolDef>,
+    presets: Vec<presets::Preset>,
+    token_counter: Arc<tokens::TokenCounter>,
+    // Kept alive for the whole run; on X11 the copied text disappears when
+    // the owning clipboard handle is dropped.
+    clipboard: Option<arboard::Clipboard>,
+    backend: Arc<dyn ChatBackend>,
+    config: serde_json::Value,
+    // In-progress replies keyed by session id, one entry per running stream
+    streams: HashMap<String, ActiveStream>,
+    tasks: HashMap<String, AbortHandle>,
+    // The model download running in the background, if any
+    pull_task: Option<AbortHandle>,
+    // Reply being read aloud and its bubble
+    speech: Option<(speech::Speech, i32)>,
+    // Lets the Stop button end a stream cleanly, unlike aborting the task
+    cancels: HashMap<String, CancellationToken>,
+    // Sessions with a generation task in flight (including tool round trips)
+    generating: HashSet<String>,
+    // Sessions whose reply finished while another chat was on screen
+    unread: HashSet<String>,
+    // Row id of an interrupted reply at the end of the displayed session
+    resumable: Option<i64>,
+    queue: VecDeque<QueuedPrompt>,
+    next_queue_id: u64,
+    // Dispatch times per endpoint within the last RATE_LIMIT_WINDOW
+    rate_log: HashMap<String, VecDeque<Instant>>,
+    rate_retry_pending: bool,
+    view_state: ViewState,
+    // Newest last
+    undo: Vec<UndoStep>,
+    // Sessions whose older turns are being summarized in the background
+    digesting: HashSet<String>,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), slint::PlatformError> {
-    let ui = AppWindow::new()?;
-    let ollama = Ollama::default();
+impl AppState {
+    /// History of the displayed session plus the partial reply if that
+    /// session is still generating.
+    fn visible_history(&self) -> Vec<ChatMessage> {
+        let mut history = self.chat_history.clone();
+        if let Some(partial) = self.streams.get(&self.current_session_id) {
+            history.push(ChatMessage::assistant(partial.text.clone()));
+        }
+        history
+    }
 
-    let db = Connection::open("history.db").expect("Failed to open DB");
-    db.execute("CREATE TABLE IF NOT EXISTS sessions (id TEXT PRIMARY KEY, title TEXT, created_at DATETIME)", []).unwrap();
-    db.execute(
-        "CREATE TABLE IF NOT EXISTS messages (session_id TEXT, role TEXT, content TEXT)",
-        [],
-    )
-    .unwrap();
+    fn push_undo(&mut self, step: UndoStep) {
+        if self.undo.len() == UNDO_LIMIT {
+            self.undo.remove(0);
+        }
+        self.undo.push(step);
+    }
 
-    let state = Arc::new(Mutex::new(AppState {
-        db,
-        current_session_id: Uuid::new_v4().to_string(),
-        chat_history: Vec::new(),
-        attachments: Vec::new(),
-    }));
+    fn is_generating(&self) -> bool {
+        self.generating.contains(&self.current_session_id)
+    }
 
-    let ui_handle = ui.as_weak();
-    refresh_history(&ui_handle, &state.lock().unwrap().db);
+    /// A fresh stop signal for the generation about to start in `session_id`.
+    fn cancel_token(&mut self, session_id: &str) -> CancellationToken {
+        let token = CancellationToken::new();
+        self.cancels.insert(session_id.to_string(), token.clone());
+        token
+    }
+
+    fn max_concurrent(&self) -> usize {
+        // A single GPU serves one model at a time well; more just thrashes VRAM
+        self.config["max_concurrent"].as_u64().unwrap_or(1).max(1) as usize
+    }
+
+    /// Requests per minute allowed for the active endpoint, 0 = unlimited.
+    fn rate_limit(&self) -> usize {
+        self.config["rate_limits"][self.backend.endpoint()]
+            .as_u64()
+            .unwrap_or(0) as usize
+    }
+
+    /// How long until the active endpoint may take another request.
+    fn rate_wait(&mut self) -> Option<Duration> {
+        let limit = self.rate_limit();
+        if limit == 0 {
+            return None;
+        }
+        let log = self.rate_log.entry(self.backend.endpoint()).or_default();
+        while log
+            .front()
+            .is_some_and(|t| t.elapsed() >= RATE_LIMIT_WINDOW)
+        {
+            log.pop_front();
+        }
+        if log.len() < limit {
+            return None;
+        }
+        log.front()
+            .map(|oldest| RATE_LIMIT_WINDOW.saturating_sub(oldest.elapsed()))
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), slint::PlatformError> {
+    crash::install_hook();
+    // A shared chat file or session link the app was opened with, handed to
+    // the window that's already open if there is one
+    let launch_arg = std::env::args_os()
+        .nth(1)
+        .map(|arg| {
+            let path = PathBuf::from(arg);
+            fs::canonicalize(&path)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .to_string()
+        })
+        .unwrap_or_default();
+    if instance::forward(&launch_arg) {
+        return Ok(());
+    }
+    let ui = AppWindow::new()?;
 
     let cfg: serde_json::Value = match confy::load("ollama-native", None) {
         Ok(config) => config,
@@ -51,19 +334,462 @@ async fn main() -> Result<(), slint::PlatformError> {
         }
     };
 
-    ui.set_default_model_setting(cfg["default_model"].as_str().unwrap_or("llama3").into());
-    ui.set_selected_model(cfg["default_model"].as_str().unwrap_or("llama3").into());
-    ui.set_scroll_lock(cfg["scroll_lock"].as_bool().unwrap_or(true));
+    let chat_backend = backend::from_config(&cfg);
 
-    let o_models = ollama.clone();
-    let u_models = ui_handle.clone();
+    let state = Arc::new(Mutex::new(AppState {
+        db: db::Db::new("history.db", init_db),
+        current_session_id: Uuid::new_v4().to_string(),
+        chat_history: Vec::new(),
+        attachments: Vec::new(),
+        pinned_attachments: HashSet::new(),
+        system_prompt: String::new(),
+        reply_format: String::new(),
+        reply_language: String::new(),
+        options: backend::SamplingOptions::default(),
+        import_conflicts: Vec::new(),
+        archive: None,
+        dictation: None,
+        jump_to_message: None,
+        tools: Vec::new(),
+        presets: Vec::new(),
+        token_counter: Arc::new(tokens::TokenCounter::new()),
+        clipboard: arboard::Clipboard::new()
+            .map_err(|e| eprintln!("Clipboard unavailable: {}", e))
+            .ok(),
+        backend: chat_backend.clone(),
+        config: cfg.clone(),
+        streams: HashMap::new(),
+        tasks: HashMap::new(),
+        pull_task: None,
+        speech: None,
+        cancels: HashMap::new(),
+        generating: HashSet::new(),
+        unread: HashSet::new(),
+        resumable: None,
+        queue: VecDeque::new(),
+        next_queue_id: 1,
+        rate_log: HashMap::new(),
+        rate_retry_pending: false,
+        view_state: ViewState::Connecting,
+        undo: Vec::new(),
+        digesting: HashSet::new(),
+    }));
+
+    crash::set_session(&state.lock().unwrap().current_session_id);
+    if let Some(snapshot) = crash::pending_recovery() {
+        ui.set_recovery_session(snapshot.session_id.into());
+        ui.set_recovery_draft(snapshot.draft.into());
+        ui.set_recovery_error(snapshot.last_error.into());
+        ui.set_recovery_available(true);
+    }
+    ui.on_dismiss_recovery(crash::clear_recovery);
+    let s_restore = state.clone();
+    let u_restore = ui.as_weak();
+    ui.on_restore_recovery(move || {
+        let Some(ui) = u_restore.upgrade() else {
+            return;
+        };
+        let session_id = ui.get_recovery_session();
+        let draft = ui.get_recovery_draft();
+        if session_id.is_empty() {
+            ui.set_draft_text(draft);
+        } else {
+            // Loading happens in the background and restores the session's
+            // saved draft, so hand the recovered text over that way
+            db::save_draft(&s_restore.lock().unwrap().db.get(), &session_id, &draft);
+            ui.invoke_load_session(session_id);
+        }
+        crash::clear_recovery();
+    });
+    ui.on_draft_changed(|text| crash::set_draft(&text));
+
+    let ui_handle = ui.as_weak();
+    // Nothing that touches the database runs before the window is up: it's
+    // opened here in the background and the sidebar filled in afterwards
+    let s_startup = state.clone();
+    let u_startup = ui_handle.clone();
+    tokio::task::spawn_blocking(move || {
+        // Opening and migrating the database can take a while; the state
+        // stays unlocked meanwhile so the window keeps responding
+        let history_db = s_startup.lock().unwrap().db;
+        let (tool_defs, presets, draft) = {
+            let conn = history_db.get();
+            (
+                tools::load_tools(&conn),
+                presets::load_presets(&conn),
+                db::unsent_draft(&conn),
+            )
+        };
+        let counter = {
+            let mut s = s_startup.lock().unwrap();
+            s.tools = tool_defs.clone();
+            s.presets = presets;
+            s.token_counter.clone()
+        };
+        // A draft typed into a chat that was never sent has no session row
+        // yet; reopen that chat so the draft lands where it was written,
+        // unless a new one was started in the meantime
+        let draft = draft.filter(|(session_id, _)| {
+            let mut s = s_startup.lock().unwrap();
+            if !s.chat_history.is_empty() || !s.generating.is_empty() {
+                return false;
+            }
+            s.current_session_id = session_id.clone();
+            crash::set_session(session_id);
+            true
+        });
+        // Each refresh locks the state on its own, so callbacks get a turn
+        // in between
+        refresh_history(&u_startup, &s_startup.lock().unwrap());
+        refresh_recent_files(&u_startup, &s_startup.lock().unwrap());
+        refresh_presets(&u_startup, &s_startup.lock().unwrap());
+        refresh_schedules(&u_startup, &s_startup.lock().unwrap());
+        refresh_conflicts(&u_startup, &s_startup.lock().unwrap());
+        refresh_starters(&u_startup, &s_startup.lock().unwrap());
+        refresh_templates(&u_startup, &s_startup.lock().unwrap());
+        refresh_prompts(&u_startup, &s_startup.lock().unwrap());
+        let _ = u_startup.upgrade_in_event_loop(move |ui| {
+            if let Some((_, text)) = draft {
+                if ui.get_draft_text().is_empty() {
+                    ui.set_draft_text(text.into());
+                }
+            }
+            refresh_tools(&ui, &tool_defs);
+            ui.set_history_loading(false);
+        });
+        // Loads the tokenizer tables before the first send needs them
+        counter.count("");
+    });
+    let (history_db, compress) = {
+        let s = state.lock().unwrap();
+        let compress = s.config["compress_history"].as_bool().unwrap_or(false);
+        (s.db, compress)
+    };
+    if compress {
+        // Picks up messages written since the last run
+        repack_history(history_db, true);
+    }
+
+    let s_scheduler = state.clone();
+    let u_scheduler = ui_handle.clone();
     tokio::spawn(async move {
-        if let Ok(models) = o_models.list_local_models().await {
-            let names: Vec<SharedString> = models.into_iter().map(|m| m.name.into()).collect();
-            let _ = u_models.upgrade_in_event_loop(move |ui| {
-                ui.set_model_list(Rc::new(VecModel::from(names)).into());
-            });
+        loop {
+            tokio::time::sleep(SCHEDULE_POLL_INTERVAL).await;
+            let db = s_scheduler.lock().unwrap().db;
+            let due = db.call(schedule::take_due).await;
+            if due.is_empty() {
+                continue;
+            }
+            let mut s = s_scheduler.lock().unwrap();
+            for job in due {
+                let id = s.next_queue_id;
+                s.next_queue_id += 1;
+                let attachments = job
+                    .attachment
+                    .iter()
+                    .map(|path| {
+                        let name = path.file_name().unwrap_or_default().to_string_lossy();
+                        (name.to_string(), path.clone())
+                    })
+                    .collect();
+                s.queue.push_back(QueuedPrompt {
+                    id,
+                    session_id: job.session_id,
+                    prompt: job.prompt,
+                    model_name: job.model,
+                    attachments,
+                });
+            }
+            dispatch_queue(&s_scheduler, &u_scheduler, &mut s);
+        }
+    });
+
+    let s_reminders = state.clone();
+    let u_reminders = ui_handle.clone();
+    tokio::spawn(async move {
+        loop {
+            let db = s_reminders.lock().unwrap().db;
+            let due = db.call(reminders::take_due).await;
+            if !due.is_empty() {
+                refresh_history_async(&s_reminders, &u_reminders).await;
+            }
+            for reminder in due {
+                let body = if reminder.note.is_empty() {
+                    reminder.title
+                } else {
+                    format!("{}\n{}", reminder.title, reminder.note)
+                };
+                if let Err(e) = notify_rust::Notification::new()
+                    .summary("Time to revisit a conversation")
+                    .body(&body)
+                    .show()
+                {
+                    eprintln!("Failed to show notification: {}", e);
+                }
+            }
+            tokio::time::sleep(REMINDER_POLL_INTERVAL).await;
+        }
+    });
+
+    // Failed writes are mostly ignored, so warn while the disk still has room
+    let s_health = state.clone();
+    let u_health = ui_handle.clone();
+    tokio::spawn(async move {
+        let mut shown = String::new();
+        loop {
+            let db = s_health.lock().unwrap().db;
+            let warning = db
+                .call(|conn| {
+                    let growth = health::record_size(conn, stats::file_size("history.db"));
+                    health::check(health::free_space("."), growth)
+                })
+                .await;
+            let text = match warning {
+                Some(health::Warning::LowSpace { free }) => format!(
+                    "Only {} left on the disk holding your chats. Once it runs out, new messages can't be saved. Free up space, or shrink the history under Storage.",
+                    format_bytes(free)
+                ),
+                Some(health::Warning::FastGrowth { per_day, days_left }) => format!(
+                    "Chat history is growing by about {} a day, enough to fill the disk in {} days. Compress history or run maintenance under Storage.",
+                    format_bytes(per_day),
+                    days_left
+                ),
+                None => String::new(),
+            };
+            if text != shown {
+                shown = text.clone();
+                let _ = u_health.upgrade_in_event_loop(move |ui| {
+                    ui.set_storage_warning(text.into());
+                    ui.set_storage_warning_dismissed(false);
+                });
+            }
+            tokio::time::sleep(HEALTH_POLL_INTERVAL).await;
+        }
+    });
+
+    ui.set_selected_model(cfg["default_model"].as_str().unwrap_or("llama3").into());
+    apply_settings(&ui, &cfg);
+    refresh_models(&state, chat_backend, &ui_handle);
+
+    let s_icons = state.clone();
+    ui.on_set_model_icons(move |enabled| {
+        let mut s = s_icons.lock().unwrap();
+        s.config["model_icons"] = enabled.into();
+        save_config(&s.config);
+    });
+
+    let s_titles = state.clone();
+    ui.on_set_model_titles(move |enabled| {
+        let mut s = s_titles.lock().unwrap();
+        s.config["model_titles"] = enabled.into();
+        save_config(&s.config);
+    });
+
+    let s_match_language = state.clone();
+    ui.on_set_match_language(move |enabled| {
+        let mut s = s_match_language.lock().unwrap();
+        s.config["match_language"] = enabled.into();
+        save_config(&s.config);
+    });
+
+    let s_input = state.clone();
+    ui.on_set_input_behavior(move |enter_sends, auto_fence| {
+        let mut s = s_input.lock().unwrap();
+        s.config["enter_sends"] = enter_sends.into();
+        s.config["auto_fence"] = auto_fence.into();
+        save_config(&s.config);
+    });
+
+    let s_retention = state.clone();
+    ui.on_set_send_retention(move |keep_prompt, keep_attachments| {
+        let mut s = s_retention.lock().unwrap();
+        s.config["keep_prompt_on_send"] = keep_prompt.into();
+        s.config["keep_attachments_on_send"] = keep_attachments.into();
+        save_config(&s.config);
+    });
+
+    let s_paste = state.clone();
+    let u_paste = ui_handle.clone();
+    ui.on_paste_code(move || {
+        let Some(ui) = u_paste.upgrade() else {
+            return false;
+        };
+        if !ui.get_auto_fence() {
+            return false;
+        }
+        let pasted = {
+            let mut s = s_paste.lock().unwrap();
+            s.clipboard.as_mut().and_then(|c| c.get_text().ok())
+        };
+        let Some(fenced) = pasted.as_deref().and_then(markdown::fence_pasted_code) else {
+            return false;
+        };
+        // The editor's cursor isn't reachable from here, so the block goes
+        // at the end of the draft on a line of its own
+        let mut draft = ui.get_draft_text().to_string();
+        if !draft.is_empty() && !draft.ends_with('\n') {
+            draft.push('\n');
+        }
+        draft.push_str(&fenced);
+        draft.push('\n');
+        ui.set_draft_text(draft.clone().into());
+        crash::set_draft(&draft);
+        true
+    });
+
+    let s_compress = state.clone();
+    ui.on_set_compress_history(move |enabled| {
+        let mut s = s_compress.lock().unwrap();
+        s.config["compress_history"] = enabled.into();
+        save_config(&s.config);
+        repack_history(s.db, enabled);
+    });
+
+    let s_resume_summary = state.clone();
+    ui.on_set_resume_with_summary(move |enabled| {
+        let mut s = s_resume_summary.lock().unwrap();
+        s.config["resume_with_summary"] = enabled.into();
+        save_config(&s.config);
+    });
+
+    let s_auto_summarize = state.clone();
+    ui.on_set_auto_summarize(move |enabled| {
+        let mut s = s_auto_summarize.lock().unwrap();
+        s.config["auto_summarize"] = enabled.into();
+        save_config(&s.config);
+    });
+
+    let s_warm_up = state.clone();
+    ui.on_set_warm_up_on_select(move |enabled| {
+        let mut s = s_warm_up.lock().unwrap();
+        s.config["warm_up_on_select"] = enabled.into();
+        save_config(&s.config);
+    });
+
+    let s_select_model = state.clone();
+    ui.on_model_selected(move |model| {
+        let backend = {
+            let s = s_select_model.lock().unwrap();
+            if !s.config["warm_up_on_select"].as_bool().unwrap_or(false) {
+                return;
+            }
+            s.backend.clone()
+        };
+        let model = model.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = backend.warm_up(model.clone()).await {
+                eprintln!("Failed to warm up {}: {}", model, e);
+            }
+        });
+    });
+
+    let s_retry = state.clone();
+    let u_retry = ui_handle.clone();
+    ui.on_retry_connection(move || {
+        let mut s = s_retry.lock().unwrap();
+        set_view_state(&u_retry, &mut s, ViewState::Connecting);
+        refresh_models(&s_retry, s.backend.clone(), &u_retry);
+    });
+
+    let s_attach_limits = state.clone();
+    ui.on_set_attachment_limits(move |max_kb, total_kb, truncate| {
+        let mut s = s_attach_limits.lock().unwrap();
+        s.config["attach_max_kb"] = max_kb.into();
+        s.config["attach_total_kb"] = total_kb.into();
+        s.config["attach_truncate"] = truncate.to_string().into();
+        save_config(&s.config);
+    });
+
+    let s_inject = state.clone();
+    ui.on_set_attachment_format(move |format, template| {
+        let mut s = s_inject.lock().unwrap();
+        s.config["attachment_format"] = format.to_string().into();
+        s.config["attachment_template"] = template.to_string().into();
+        save_config(&s.config);
+    });
+
+    let s_long_reply = state.clone();
+    ui.on_set_long_reply_words(move |words| {
+        let mut s = s_long_reply.lock().unwrap();
+        s.config["long_reply_words"] = words.into();
+        save_config(&s.config);
+    });
+
+    let s_auto_copy = state.clone();
+    ui.on_set_auto_copy(move |enabled| {
+        let mut s = s_auto_copy.lock().unwrap();
+        s.config["auto_copy"] = enabled.into();
+        save_config(&s.config);
+    });
+
+    let s_preview_setting = state.clone();
+    ui.on_set_preview_context(move |enabled| {
+        let mut s = s_preview_setting.lock().unwrap();
+        s.config["preview_context"] = enabled.into();
+        save_config(&s.config);
+    });
+    ui.set_rate_limit(state.lock().unwrap().rate_limit() as i32);
+    let s_concurrency = state.clone();
+    let u_concurrency = ui_handle.clone();
+    ui.on_set_max_concurrent(move |value| {
+        let mut s = s_concurrency.lock().unwrap();
+        s.config["max_concurrent"] = value.max(1).into();
+        save_config(&s.config);
+        dispatch_queue(&s_concurrency, &u_concurrency, &mut s);
+    });
+
+    let s_rate = state.clone();
+    let u_rate = ui_handle.clone();
+    ui.on_set_rate_limit(move |value| {
+        let mut s = s_rate.lock().unwrap();
+        let endpoint = s.backend.endpoint();
+        s.config["rate_limits"][endpoint] = value.max(0).into();
+        save_config(&s.config);
+        dispatch_queue(&s_rate, &u_rate, &mut s);
+    });
+
+    let s_backend = state.clone();
+    let u_backend = ui_handle.clone();
+    ui.on_apply_backend(move || {
+        let Some(ui) = u_backend.upgrade() else {
+            return;
+        };
+        let mut s = s_backend.lock().unwrap();
+        let previous = s.backend.endpoint();
+        if let Some(form) = backend_form(&ui).as_object() {
+            for (key, value) in form {
+                s.config[key] = value.clone();
+            }
         }
+        save_config(&s.config);
+        s.backend = backend::from_config(&s.config);
+        // Same server and it was reachable: the model list still stands
+        let unreachable = matches!(s.view_state, ViewState::Offline(_) | ViewState::NoModels);
+        if s.backend.endpoint() != previous || unreachable {
+            set_view_state(&u_backend, &mut s, ViewState::Connecting);
+            refresh_models(&s_backend, s.backend.clone(), &u_backend);
+        }
+        ui.set_rate_limit(s.rate_limit() as i32);
+        ui.set_backend_test_status("".into());
+    });
+
+    let u_test_backend = ui_handle.clone();
+    ui.on_test_backend(move || {
+        let Some(ui) = u_test_backend.upgrade() else {
+            return;
+        };
+        let backend = backend::from_config(&backend_form(&ui));
+        ui.set_backend_test_status("Connecting…".into());
+        let u_result = u_test_backend.clone();
+        tokio::spawn(async move {
+            let status = match backend.list_models().await {
+                Ok(models) => format!("Connected, {} models available", models.len()),
+                Err(e) => format!("Couldn't connect: {}", e),
+            };
+            let _ = u_result.upgrade_in_event_loop(move |ui| {
+                ui.set_backend_test_status(status.into());
+            });
+        });
     });
 
     let s_pick = state.clone();
@@ -71,101 +797,546 @@ async fn main() -> Result<(), slint::PlatformError> {
     ui.on_pick_attachment(move || {
         if let Some(path) = rfd::FileDialog::new().pick_file() {
             let mut s = s_pick.lock().unwrap();
-            let session_id = s.current_session_id.clone();
-            let filename = path
-                .file_name()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .to_string();
+            attach_file(&mut s, &u_pick, &path);
+        }
+    });
 
-            let mut dest_dir = PathBuf::from("./attachments");
-            dest_dir.push(&session_id);
-            let _ = fs::create_dir_all(&dest_dir);
-
-            let dest_path = dest_dir.join(&filename);
-            if let Ok(_) = fs::copy(&path, &dest_path) {
-                s.attachments.push((filename, dest_path));
-                let names: Vec<SharedString> =
-                    s.attachments.iter().map(|(n, _)| n.into()).collect();
-                let _ = u_pick.upgrade_in_event_loop(move |ui| {
-                    ui.set_attachment_list(Rc::new(VecModel::from(names)).into());
-                });
-            } else {
-                let err = fs::copy(&path, &dest_path).err().unwrap();
-                eprintln!("Error copying attachment: {}", err);
-            }
+    let s_recent = state.clone();
+    let u_recent = ui_handle.clone();
+    ui.on_attach_recent(move |path| {
+        let mut s = s_recent.lock().unwrap();
+        attach_file(&mut s, &u_recent, Path::new(path.as_str()));
+    });
+
+    let s_apply_preset = state.clone();
+    let u_apply_preset = ui_handle.clone();
+    ui.on_apply_preset(move |id| {
+        let mut s = s_apply_preset.lock().unwrap();
+        let files = s
+            .presets
+            .iter()
+            .find(|p| p.id == id as i64)
+            .map(|p| p.files.clone())
+            .unwrap_or_default();
+        for path in files {
+            attach_file(&mut s, &u_apply_preset, &path);
         }
     });
 
+    let s_save_preset = state.clone();
+    let u_save_preset = ui_handle.clone();
+    ui.on_save_preset(move |name| {
+        let mut s = s_save_preset.lock().unwrap();
+        let name = name.trim().to_string();
+        if name.is_empty() || s.attachments.is_empty() {
+            return;
+        }
+        let files: Vec<PathBuf> = s.attachments.iter().map(|(_, p)| p.clone()).collect();
+        if let Err(e) = presets::create_preset(&s.db.get(), &name, &files) {
+            eprintln!("Error saving preset: {}", e);
+            return;
+        }
+        s.presets = presets::load_presets(&s.db.get());
+        refresh_presets(&u_save_preset, &s);
+    });
+
+    let s_auto_preset = state.clone();
+    let u_auto_preset = ui_handle.clone();
+    ui.on_set_preset_auto(move |id, auto_attach| {
+        let mut s = s_auto_preset.lock().unwrap();
+        presets::set_auto_attach(&s.db.get(), id as i64, auto_attach);
+        s.presets = presets::load_presets(&s.db.get());
+        refresh_presets(&u_auto_preset, &s);
+    });
+
+    let s_delete_preset = state.clone();
+    let u_delete_preset = ui_handle.clone();
+    ui.on_delete_preset(move |id| {
+        let mut s = s_delete_preset.lock().unwrap();
+        presets::delete_preset(&s.db.get(), id as i64);
+        s.presets = presets::load_presets(&s.db.get());
+        refresh_presets(&u_delete_preset, &s);
+    });
+
     let s_remove = state.clone();
     let u_remove = ui_handle.clone();
     ui.on_remove_attachment(move |index| {
         let mut s = s_remove.lock().unwrap();
         if index >= 0 && (index as usize) < s.attachments.len() {
-            s.attachments.remove(index as usize);
-            let names: Vec<SharedString> = s.attachments.iter().map(|(n, _)| n.into()).collect();
+            let (name, _) = s.attachments.remove(index as usize);
+            let session_id = s.current_session_id.clone();
+            db::detach(&s.db.get(), &session_id, &name);
+            s.pinned_attachments.remove(&name);
+            let chips = attachment_chips(&s);
             let _ = u_remove.upgrade_in_event_loop(move |ui| {
-                ui.set_attachment_list(Rc::new(VecModel::from(names)).into());
+                show_attachment_chips(&ui, chips);
             });
         }
     });
 
+    let s_pin = state.clone();
+    let u_pin = ui_handle.clone();
+    ui.on_toggle_attachment_pin(move |index| {
+        let mut s = s_pin.lock().unwrap();
+        let Some((name, _)) = s.attachments.get(index as usize).cloned() else {
+            return;
+        };
+        let pinned = !s.pinned_attachments.remove(&name);
+        if pinned {
+            s.pinned_attachments.insert(name.clone());
+        }
+        let session_id = s.current_session_id.clone();
+        db::set_attachment_pinned(&s.db.get(), &session_id, &name, pinned);
+        let chips = attachment_chips(&s);
+        let _ = u_pin.upgrade_in_event_loop(move |ui| {
+            show_attachment_chips(&ui, chips);
+        });
+    });
+
+    let s_library = state.clone();
+    let u_library = ui_handle.clone();
+    ui.on_open_library(move || {
+        refresh_library(&u_library, &s_library.lock().unwrap());
+        let _ = u_library.upgrade_in_event_loop(|ui| ui.set_library_open(true));
+    });
+
+    let s_reattach = state.clone();
+    let u_reattach = ui_handle.clone();
+    ui.on_reattach_file(move |name| {
+        let mut s = s_reattach.lock().unwrap();
+        let session_id = s.current_session_id.clone();
+        let Some((_, path, ..)) = db::attachment_library(&s.db.get(), &session_id)
+            .into_iter()
+            .find(|(n, ..)| *n == name.as_str())
+        else {
+            return;
+        };
+        // Already a copy in the session folder, so no limits or copying
+        db::add_attachment(&s.db.get(), &session_id, &name, &path);
+        s.attachments.retain(|(n, _)| *n != name.as_str());
+        s.attachments.push((name.to_string(), path));
+        let chips = attachment_chips(&s);
+        let _ = u_reattach.upgrade_in_event_loop(move |ui| {
+            show_attachment_chips(&ui, chips);
+        });
+        refresh_library(&u_reattach, &s);
+    });
+
+    let s_preview_file = state.clone();
+    let u_preview_file = ui_handle.clone();
+    ui.on_preview_file(move |name| {
+        let s = s_preview_file.lock().unwrap();
+        let Some((_, path, ..)) = db::attachment_library(&s.db.get(), &s.current_session_id)
+            .into_iter()
+            .find(|(n, ..)| *n == name.as_str())
+        else {
+            return;
+        };
+        let text = extract::load(&s.db.get(), &s.token_counter, &path)
+            .map(|file| file.text)
+            .unwrap_or_else(|| "(No text could be read from this file.)".into());
+        let _ = u_preview_file.upgrade_in_event_loop(move |ui| {
+            ui.set_file_preview_title(name);
+            ui.set_file_preview_text(text.into());
+            ui.set_file_preview_open(true);
+        });
+    });
+
+    let s_forget = state.clone();
+    let u_forget = ui_handle.clone();
+    ui.on_forget_file(move |name| {
+        let mut s = s_forget.lock().unwrap();
+        let session_id = s.current_session_id.clone();
+        db::forget_attachment(&s.db.get(), &session_id, &name);
+        s.attachments.retain(|(n, _)| *n != name.as_str());
+        s.pinned_attachments.remove(name.as_str());
+        let chips = attachment_chips(&s);
+        let _ = u_forget.upgrade_in_event_loop(move |ui| {
+            show_attachment_chips(&ui, chips);
+        });
+        refresh_library(&u_forget, &s);
+    });
+
     let s_load = state.clone();
     let u_load = ui_handle.clone();
     ui.on_load_session(move |id| {
-        let mut s = s_load.lock().unwrap();
-        let id_str = id.to_string();
-        let mut history_to_load = Vec::new();
-
+        let Some(ui) = u_load.upgrade() else {
+            return;
+        };
+        let draft_text = ui.get_draft_text().to_string();
+        // Opened from a sidebar search: point out where the query occurs
+        let search = ui.get_history_filter().to_string();
         {
-            let mut stmt =
-                s.db.prepare("SELECT role, content FROM messages WHERE session_id = ?1")
-                    .unwrap();
-            let rows = stmt
-                .query_map([&id_str], |row| {
-                    let role: String = row.get(0)?;
-                    let content: String = row.get(1)?;
-                    Ok(if role == "user" {
-                        ChatMessage::user(content)
-                    } else {
-                        ChatMessage::assistant(content)
-                    })
+            let mut s = s_load.lock().unwrap();
+            if !s.view_state.is_backend_problem() {
+                set_view_state(&u_load, &mut s, ViewState::LoadingSession);
+            }
+        }
+        // Long transcripts take a while to read and render, so keep the
+        // event loop free to show the loading state meanwhile
+        let s_load = s_load.clone();
+        let u_load = u_load.clone();
+        tokio::spawn(async move {
+            let id_str = id.to_string();
+            let (db, leaving) = {
+                let s = s_load.lock().unwrap();
+                (s.db, s.current_session_id.clone())
+            };
+            let (session, labels) = {
+                let id_str = id_str.clone();
+                db.call(move |conn| {
+                    db::save_draft(conn, &leaving, &draft_text);
+                    (
+                        StoredSession::read(conn, &id_str),
+                        load_message_labels(conn, &id_str),
+                    )
                 })
-                .unwrap();
+                .await
+            };
+            let stored = session.messages;
+            let mut s = s_load.lock().unwrap();
+            let jump_to = s
+                .jump_to_message
+                .take()
+                .and_then(|row_id| stored.iter().position(|m| m.row_id == row_id));
+            let last_partial = stored.last().and_then(|m| m.partial.then_some(m.row_id));
+            let last_row = stored.last().map(|m| m.row_id);
+            let history_to_load: Vec<ChatMessage> = stored.into_iter().map(|m| m.message).collect();
+
+            s.chat_history = history_to_load;
+            s.current_session_id = id_str.clone();
+            s.system_prompt = session.system_prompt;
+            (s.reply_format, s.reply_language) = session.reply_style;
+            s.options = session.options;
+            crash::set_session(&id_str);
+            s.attachments.clear();
+            // Bubble indexes refer to the session being left
+            if let Some((speech, _)) = s.speech.take() {
+                speech.stop();
+                let _ = u_load.upgrade_in_event_loop(|ui| ui.set_speaking_index(-1));
+            }
+            // A partial row that isn't being streamed right now was interrupted
+            s.resumable = if s.is_generating() {
+                None
+            } else {
+                last_partial
+            };
+            // The live stream is rendered from `streams`, not the flushed
+            // row, which may already be marked finished when the stream ends
+            if last_row.is_some() && s.streams.get(&id_str).map(|p| p.row_id) == last_row {
+                s.chat_history.pop();
+            }
+            let history_changed = s.unread.remove(&id_str) || session.had_reminder;
+
+            s.pinned_attachments.clear();
+            for (name, path, pinned) in session.attachments {
+                if pinned {
+                    s.pinned_attachments.insert(name.clone());
+                }
+                s.attachments.push((name, path));
+            }
 
-            for r in rows {
-                if let Ok(msg) = r {
-                    history_to_load.push(msg);
+            let history_copy = s.visible_history();
+            let (mut search_counts, mut search_hits) = search_transcript(&history_copy, &search);
+            // The hit that was clicked matched its words, which needn't
+            // appear together as the query does
+            let focus = match jump_to.filter(|&i| i < search_counts.len()) {
+                Some(i) => {
+                    let pos = search_hits.partition_point(|&hit| hit < i as i32);
+                    if search_hits.get(pos) != Some(&(i as i32)) {
+                        search_hits.insert(pos, i as i32);
+                        search_counts[i] = 1;
+                    }
+                    pos as i32
                 }
+                None => 0,
+            };
+            let generating = s.is_generating();
+            let can_continue = s.resumable.is_some();
+            let draft = session.draft;
+            let locked = session.locked;
+            let chips = attachment_chips(&s);
+            let system_prompt = s.system_prompt.clone();
+            let reply_format = s.reply_format.clone();
+            let reply_language = s.reply_language.clone();
+            let options = s.options.clone();
+            show_message_labels(&u_load, labels);
+            let _ = u_load.upgrade_in_event_loop(move |ui| {
+                ui.set_session_system_prompt(system_prompt.into());
+                ui.set_generation_options(options_form(&options));
+                ui.set_context_notice("".into());
+                ui.set_reply_format(reply_format.into());
+                ui.set_reply_language(reply_language.into());
+                ui.set_generating(generating);
+                ui.set_can_continue(can_continue);
+                ui.set_session_locked(locked);
+                ui.set_selecting(false);
+                ui.set_editing_index(-1);
+                ui.set_search_query(search.trim().into());
+                ui.set_search_counts(Rc::new(VecModel::from(search_counts)).into());
+                ui.set_search_hits(Rc::new(VecModel::from(search_hits)).into());
+                ui.set_search_pos(-1);
+                ui.set_session_info_open(false);
+                ui.set_diff_open(false);
+                ui.set_draft_text(draft.into());
+                show_attachment_chips(&ui, chips);
+                update_ui_model(&ui, &history_copy);
+            });
+            // Focused only once the bubbles exist, so the first match is
+            // scrolled into view
+            let _ = u_load.upgrade_in_event_loop(move |ui| ui.set_search_pos(focus));
+            settle_view_state(&u_load, &mut s);
+            drop(s);
+            if history_changed {
+                refresh_history_async(&s_load, &u_load).await;
             }
+        });
+    });
+
+    let s_delete = state.clone();
+    let u_delete = ui_handle.clone();
+    ui.on_delete_session(move |id| {
+        let mut s = s_delete.lock().unwrap();
+        let id = id.to_string();
+        if s.generating.contains(&id) || db::is_session_locked(&s.db.get(), &id) {
+            return;
+        }
+        if !db::trash_session(&s.db.get(), &id) {
+            return;
+        }
+        let was_open = s.current_session_id == id;
+        s.push_undo(UndoStep::Session {
+            id: id.clone(),
+            was_open,
+        });
+        show_undo_toast(&u_delete, "Chat deleted");
+        s.queue.retain(|q| q.session_id != id);
+        s.unread.remove(&id);
+        refresh_queue(&u_delete, &s);
+        refresh_history(&u_delete, &s);
+        if was_open {
+            // An empty draft keeps clear_chat from saving one for the
+            // session that no longer exists
+            let _ = u_delete.upgrade_in_event_loop(|ui| {
+                ui.set_draft_text("".into());
+                ui.invoke_clear_chat();
+            });
         }
+    });
 
-        s.chat_history = history_to_load;
-        s.current_session_id = id_str.clone();
-        s.attachments.clear();
+    let s_title = state.clone();
+    let u_title = ui_handle.clone();
+    ui.on_rename_session(move |id, title| {
+        let s = s_title.lock().unwrap();
+        if db::is_session_locked(&s.db.get(), &id) {
+            refresh_history(&u_title, &s);
+            return;
+        }
+        if db::update_session_title(&s.db.get(), &id, &title) {
+            db::set_title_locked(&s.db.get(), &id, true);
+            refresh_history(&u_title, &s);
+        }
+    });
+
+    let s_filter = state.clone();
+    let u_filter = ui_handle.clone();
+    ui.on_filter_history(move || {
+        let Some(ui) = u_filter.upgrade() else {
+            return;
+        };
+        let needle = ui.get_history_filter().trim().to_string();
+        // Single letters would match nearly every transcript
+        let hits: Vec<SharedString> = if needle.chars().count() < 2 {
+            Vec::new()
+        } else {
+            search::sessions_matching(&s_filter.lock().unwrap().db.get(), &needle)
+                .into_iter()
+                .map(Into::into)
+                .collect()
+        };
+        ui.set_history_content_hits(Rc::new(VecModel::from(hits)).into());
+        apply_history_filter(&ui);
+    });
 
-        let mut attach_dir = PathBuf::from("./attachments");
-        attach_dir.push(&id_str);
-        if let Ok(entries) = fs::read_dir(attach_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_file() {
-                    let name = path
-                        .file_name()
-                        .unwrap_or_default()
-                        .to_string_lossy()
-                        .to_string();
-                    s.attachments.push((name, path));
+    let s_search = state.clone();
+    ui.on_search_history(move |query| {
+        let query = query.trim();
+        let hits: Vec<SearchHit> = if query.chars().count() < 2 {
+            Vec::new()
+        } else {
+            let s = s_search.lock().unwrap();
+            search::search(&s.db.get(), query, SEARCH_RESULTS)
+                .into_iter()
+                .map(|hit| SearchHit {
+                    session_id: hit.session_id.into(),
+                    title: hit.title.into(),
+                    message_id: hit.message_id as i32,
+                    snippet: Rc::new(VecModel::from(
+                        hit.snippet
+                            .into_iter()
+                            .map(|(text, matched)| SnippetRun {
+                                text: text.into(),
+                                matched,
+                            })
+                            .collect::<Vec<_>>(),
+                    ))
+                    .into(),
+                })
+                .collect()
+        };
+        Rc::new(VecModel::from(hits)).into()
+    });
+
+    let s_hit = state.clone();
+    let u_hit = ui_handle.clone();
+    ui.on_open_search_hit(move |session_id, message_id| {
+        s_hit.lock().unwrap().jump_to_message = Some(message_id as i64);
+        if let Some(ui) = u_hit.upgrade() {
+            ui.invoke_load_session(session_id);
+        }
+    });
+
+    let s_pull = state.clone();
+    let u_pull = ui_handle.clone();
+    ui.on_pull_model(move |name| {
+        let name = name.trim().to_string();
+        let mut s = s_pull.lock().unwrap();
+        if name.is_empty() || s.pull_task.is_some() {
+            return;
+        }
+        if let Some(ui) = u_pull.upgrade() {
+            ui.set_pulling(true);
+            ui.set_pull_progress(-1.0);
+            ui.set_pull_status(format!("Starting download of {}…", name).into());
+        }
+        let backend = s.backend.clone();
+        let state = s_pull.clone();
+        let ui_weak = u_pull.clone();
+        // The task can't finish before its handle is stored: it needs the
+        // state lock held here to clear it
+        let task = tokio::spawn(async move {
+            let result = async {
+                let mut stream = backend.pull_model(name.clone()).await?;
+                while let Some(progress) = stream.next().await {
+                    let progress = progress?;
+                    let (fraction, status) = if progress.total > 0 {
+                        let fraction = progress.completed as f32 / progress.total as f32;
+                        let status = format!(
+                            "{}: {:.0}% ({} of {})",
+                            progress.status,
+                            fraction * 100.0,
+                            format_bytes(progress.completed),
+                            format_bytes(progress.total)
+                        );
+                        (fraction, status)
+                    } else {
+                        (-1.0, progress.status)
+                    };
+                    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+                        ui.set_pull_progress(fraction);
+                        ui.set_pull_status(status.into());
+                    });
                 }
+                Ok::<(), String>(())
             }
+            .await;
+            let mut s = state.lock().unwrap();
+            s.pull_task = None;
+            let status = match result {
+                Ok(()) => {
+                    refresh_models(&state, s.backend.clone(), &ui_weak);
+                    format!("Downloaded {}", name)
+                }
+                Err(e) => format!("Download of {} failed: {}", name, e),
+            };
+            let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+                ui.set_pulling(false);
+                ui.set_pull_progress(-1.0);
+                ui.set_pull_status(status.into());
+            });
+        });
+        s.pull_task = Some(task.abort_handle());
+    });
+
+    let s_pull_cancel = state.clone();
+    let u_pull_cancel = ui_handle.clone();
+    ui.on_cancel_pull(move || {
+        let Some(task) = s_pull_cancel.lock().unwrap().pull_task.take() else {
+            return;
+        };
+        // Ollama keeps the layers it finished, so pulling again resumes
+        task.abort();
+        if let Some(ui) = u_pull_cancel.upgrade() {
+            ui.set_pulling(false);
+            ui.set_pull_progress(-1.0);
+            ui.set_pull_status("Download cancelled".into());
+        }
+    });
+
+    let s_models = state.clone();
+    let u_models = ui_handle.clone();
+    ui.on_open_models(move || {
+        let backend = s_models.lock().unwrap().backend.clone();
+        let u_models = u_models.clone();
+        if let Some(ui) = u_models.upgrade() {
+            ui.set_models_status("Loading…".into());
+            ui.set_models_open(true);
         }
+        tokio::spawn(async move {
+            let models = match backend.list_models().await {
+                Ok(models) => models,
+                Err(e) => {
+                    let _ = u_models.upgrade_in_event_loop(move |ui| {
+                        ui.set_models_status(format!("Can't list models: {}", e).into());
+                    });
+                    return;
+                }
+            };
+            let rows: Vec<ModelRow> = models
+                .iter()
+                .map(|m| ModelRow {
+                    name: m.name.clone().into(),
+                    details: model_details(m, &backend::ModelDetails::default()).into(),
+                })
+                .collect();
+            let status = if rows.is_empty() {
+                "No models installed"
+            } else {
+                ""
+            };
+            let _ = u_models.upgrade_in_event_loop(move |ui| {
+                ui.set_models_status(status.into());
+                ui.set_model_rows(Rc::new(VecModel::from(rows)).into());
+            });
+            // Details take a request per model, so rows fill in as they arrive
+            for (i, m) in models.into_iter().enumerate() {
+                let Ok(details) = backend.show_model_info(m.name.clone()).await else {
+                    continue;
+                };
+                let _ = u_models.upgrade_in_event_loop(move |ui| {
+                    let rows = ui.get_model_rows();
+                    if let Some(mut row) = rows.row_data(i).filter(|r| r.name == m.name.as_str()) {
+                        row.details = model_details(&m, &details).into();
+                        rows.set_row_data(i, row);
+                    }
+                });
+            }
+        });
+    });
 
-        let history_copy = s.chat_history.clone();
-        let attachment_names: Vec<SharedString> =
-            s.attachments.iter().map(|(n, _)| n.into()).collect();
-        let _ = u_load.upgrade_in_event_loop(move |ui| {
-            ui.set_attachment_list(Rc::new(VecModel::from(attachment_names)).into());
-            update_ui_model(&ui, &history_copy);
+    let s_delete_model = state.clone();
+    let u_delete_model = ui_handle.clone();
+    ui.on_delete_model(move |name| {
+        let backend = s_delete_model.lock().unwrap().backend.clone();
+        let state = s_delete_model.clone();
+        let ui_weak = u_delete_model.clone();
+        tokio::spawn(async move {
+            let result = backend.delete_model(name.to_string()).await;
+            refresh_models(&state, backend, &ui_weak);
+            let _ = ui_weak.upgrade_in_event_loop(move |ui| match result {
+                Ok(()) => ui.invoke_open_models(),
+                Err(e) => ui.set_models_status(format!("Can't delete {}: {}", name, e).into()),
+            });
         });
     });
 
@@ -173,153 +1344,4812 @@ async fn main() -> Result<(), slint::PlatformError> {
     let u_clear = ui_handle.clone();
     ui.on_clear_chat(move || {
         let mut s = s_clear.lock().unwrap();
+        if let Some(ui) = u_clear.upgrade() {
+            db::save_draft(&s.db.get(), &s.current_session_id, &ui.get_draft_text());
+        }
+        // Only a chat that was saved can be gone back to
+        if db::session_exists(&s.db.get(), &s.current_session_id) {
+            let previous = s.current_session_id.clone();
+            s.push_undo(UndoStep::Clear(previous));
+            show_undo_toast(&u_clear, "Chat cleared");
+        }
         s.current_session_id = Uuid::new_v4().to_string();
+        crash::set_session(&s.current_session_id);
         s.chat_history.clear();
         s.attachments.clear();
+        s.pinned_attachments.clear();
+        s.system_prompt.clear();
+        s.reply_format.clear();
+        s.reply_language.clear();
+        s.options = backend::SamplingOptions::default();
+        s.resumable = None;
         let _ = u_clear.upgrade_in_event_loop(|ui| {
+            ui.set_generating(false);
+            ui.set_can_continue(false);
+            ui.set_session_locked(false);
+            ui.set_selecting(false);
+            ui.set_editing_index(-1);
+            ui.set_session_system_prompt("".into());
+            ui.set_generation_options(GenOptionsForm::default());
+            ui.set_context_notice("".into());
+            ui.set_reply_format("".into());
+            ui.set_reply_language("".into());
+            ui.set_search_query("".into());
+            ui.set_search_hits(Rc::new(VecModel::from(vec![])).into());
+            ui.set_draft_text("".into());
+            ui.set_session_info_open(false);
+            ui.set_diff_open(false);
             ui.set_chat_messages(Rc::new(VecModel::from(vec![])).into());
+            ui.set_message_labels(Rc::new(VecModel::<SharedString>::default()).into());
+            ui.set_message_contexts(Rc::new(VecModel::<SharedString>::default()).into());
+            ui.set_message_variants(Rc::new(VecModel::<VariantInfo>::default()).into());
             ui.set_attachment_list(Rc::new(VecModel::from(vec![])).into());
         });
-        refresh_history(&u_clear, &s.db);
+        refresh_history(&u_clear, &s);
+        refresh_starters(&u_clear, &s);
+        settle_view_state(&u_clear, &mut s);
     });
 
-    let s_send = state.clone();
-    let u_send = ui_handle.clone();
-    let o_send = ollama.clone();
-    ui.on_send_message(move |msg| {
-        let mut s = s_send.lock().unwrap();
-        let raw_input = msg.to_string();
-        let session_id = s.current_session_id.clone();
+    let u_new_tool = ui_handle.clone();
+    ui.on_new_tool(move || {
+        if let Some(ui) = u_new_tool.upgrade() {
+            ui.set_tool_form_id(0);
+            ui.set_tool_form_name("".into());
+            ui.set_tool_form_description("".into());
+            ui.set_tool_form_kind("command".into());
+            ui.set_tool_form_template("".into());
+            ui.set_tool_form_enabled(true);
+            ui.set_tool_form_params(Rc::new(VecModel::<ToolParamData>::default()).into());
+        }
+    });
 
-        if s.chat_history.is_empty() {
-            let _ = s.db.execute(
-                "INSERT INTO sessions (id, title, created_at) VALUES (?1, ?2, datetime('now'))",
-                params![session_id, raw_input],
-            );
+    let s_edit_tool = state.clone();
+    let u_edit_tool = ui_handle.clone();
+    ui.on_edit_tool(move |id| {
+        let s = s_edit_tool.lock().unwrap();
+        if let (Some(tool), Some(ui)) = (
+            s.tools.iter().find(|t| t.id == id as i64),
+            u_edit_tool.upgrade(),
+        ) {
+            let params: Vec<ToolParamData> = tool
+                .params
+                .iter()
+                .map(|p| ToolParamData {
+                    name: p.name.clone().into(),
+                    kind: p.kind.clone().into(),
+                    description: p.description.clone().into(),
+                    required: p.required,
+                })
+                .collect();
+            ui.set_tool_form_id(tool.id as i32);
+            ui.set_tool_form_name(tool.name.clone().into());
+            ui.set_tool_form_description(tool.description.clone().into());
+            ui.set_tool_form_kind(tool.kind.clone().into());
+            ui.set_tool_form_template(tool.template.clone().into());
+            ui.set_tool_form_enabled(tool.enabled);
+            ui.set_tool_form_params(Rc::new(VecModel::from(params)).into());
         }
+    });
 
-        s.chat_history.push(ChatMessage::user(raw_input.clone()));
-        let _ = s.db.execute(
-            "INSERT INTO messages (session_id, role, content) VALUES (?1, 'user', ?2)",
-            params![session_id, raw_input],
-        );
+    let u_add_param = ui_handle.clone();
+    ui.on_add_tool_param(move || {
+        if let Some(ui) = u_add_param.upgrade() {
+            let model = ui.get_tool_form_params();
+            if let Some(vec_model) = model.as_any().downcast_ref::<VecModel<ToolParamData>>() {
+                vec_model.push(ToolParamData {
+                    name: "".into(),
+                    kind: "string".into(),
+                    description: "".into(),
+                    required: true,
+                });
+            }
+        }
+    });
+
+    let u_remove_param = ui_handle.clone();
+    ui.on_remove_tool_param(move |index| {
+        if let Some(ui) = u_remove_param.upgrade() {
+            let model = ui.get_tool_form_params();
+            if let Some(vec_model) = model.as_any().downcast_ref::<VecModel<ToolParamData>>() {
+                if index >= 0 && (index as usize) < vec_model.row_count() {
+                    vec_model.remove(index as usize);
+                }
+            }
+        }
+    });
+
+    let u_update_param = ui_handle.clone();
+    ui.on_update_tool_param(move |index, param| {
+        if let Some(ui) = u_update_param.upgrade() {
+            let model = ui.get_tool_form_params();
+            if index >= 0 && (index as usize) < model.row_count() {
+                model.set_row_data(index as usize, param);
+            }
+        }
+    });
+
+    let s_save_tool = state.clone();
+    let u_save_tool = ui_handle.clone();
+    ui.on_save_tool(move || {
+        let Some(ui) = u_save_tool.upgrade() else {
+            return;
+        };
+        let name = ui.get_tool_form_name().trim().to_string();
+        if name.is_empty() {
+            return;
+        }
+        let tool = tools::ToolDef {
+            id: ui.get_tool_form_id() as i64,
+            name,
+            description: ui.get_tool_form_description().to_string(),
+            params: ui
+                .get_tool_form_params()
+                .iter()
+                .filter(|p| !p.name.trim().is_empty())
+                .map(|p| tools::ToolParam {
+                    name: p.name.trim().to_string(),
+                    kind: p.kind.to_string(),
+                    description: p.description.to_string(),
+                    required: p.required,
+                })
+                .collect(),
+            kind: ui.get_tool_form_kind().to_string(),
+            template: ui.get_tool_form_template().to_string(),
+            enabled: ui.get_tool_form_enabled(),
+        };
+
+        let mut s = s_save_tool.lock().unwrap();
+        if let Err(e) = tools::save_tool(&s.db.get(), &tool) {
+            eprintln!("Error saving tool: {}", e);
+            return;
+        }
+        s.tools = tools::load_tools(&s.db.get());
+        if let Some(saved) = s.tools.iter().find(|t| t.name == tool.name) {
+            ui.set_tool_form_id(saved.id as i32);
+        }
+        refresh_tools(&ui, &s.tools);
+    });
+
+    let s_delete_tool = state.clone();
+    let u_delete_tool = ui_handle.clone();
+    ui.on_delete_tool(move |id| {
+        let mut s = s_delete_tool.lock().unwrap();
+        tools::delete_tool(&s.db.get(), id as i64);
+        s.tools = tools::load_tools(&s.db.get());
+        if let Some(ui) = u_delete_tool.upgrade() {
+            refresh_tools(&ui, &s.tools);
+            ui.invoke_new_tool();
+        }
+    });
+
+    let u_new_template = ui_handle.clone();
+    ui.on_new_template(move || {
+        if let Some(ui) = u_new_template.upgrade() {
+            ui.set_template_form_id(0);
+            ui.set_template_form_name("".into());
+            ui.set_template_form_system("".into());
+            ui.set_template_form_turns(Rc::new(VecModel::<TemplateTurnData>::default()).into());
+        }
+    });
+
+    let s_edit_template = state.clone();
+    let u_edit_template = ui_handle.clone();
+    ui.on_edit_template(move |id| {
+        let s = s_edit_template.lock().unwrap();
+        let Some(template) = templates::load_templates(&s.db.get())
+            .into_iter()
+            .find(|t| t.id == id as i64)
+        else {
+            return;
+        };
+        if let Some(ui) = u_edit_template.upgrade() {
+            show_template_form(&ui, &template);
+        }
+    });
+
+    let s_chat_template = state.clone();
+    let u_chat_template = ui_handle.clone();
+    ui.on_template_from_chat(move || {
+        let s = s_chat_template.lock().unwrap();
+        let turns = s
+            .chat_history
+            .iter()
+            .filter_map(|m| {
+                let role = match m.role {
+                    MessageRole::User => "user",
+                    MessageRole::Assistant => "assistant",
+                    _ => return None,
+                };
+                Some(templates::TemplateTurn {
+                    role: role.into(),
+                    content: m.content.clone(),
+                })
+            })
+            .collect();
+        let template = templates::Template {
+            id: 0,
+            name: String::new(),
+            system_prompt: s.system_prompt.clone(),
+            turns,
+        };
+        if let Some(ui) = u_chat_template.upgrade() {
+            show_template_form(&ui, &template);
+        }
+    });
+
+    let u_add_turn = ui_handle.clone();
+    ui.on_add_template_turn(move || {
+        if let Some(ui) = u_add_turn.upgrade() {
+            let model = ui.get_template_form_turns();
+            if let Some(vec_model) = model.as_any().downcast_ref::<VecModel<TemplateTurnData>>() {
+                // Alternate roles, starting with the user
+                let role = match vec_model.row_count() {
+                    0 => "user",
+                    n if vec_model.row_data(n - 1).is_some_and(|t| t.role == "user") => "assistant",
+                    _ => "user",
+                };
+                vec_model.push(TemplateTurnData {
+                    role: role.into(),
+                    content: "".into(),
+                });
+            }
+        }
+    });
+
+    let u_remove_turn = ui_handle.clone();
+    ui.on_remove_template_turn(move |index| {
+        if let Some(ui) = u_remove_turn.upgrade() {
+            let model = ui.get_template_form_turns();
+            if let Some(vec_model) = model.as_any().downcast_ref::<VecModel<TemplateTurnData>>() {
+                if index >= 0 && (index as usize) < vec_model.row_count() {
+                    vec_model.remove(index as usize);
+                }
+            }
+        }
+    });
+
+    let u_update_turn = ui_handle.clone();
+    ui.on_update_template_turn(move |index, turn| {
+        if let Some(ui) = u_update_turn.upgrade() {
+            let model = ui.get_template_form_turns();
+            if index >= 0 && (index as usize) < model.row_count() {
+                model.set_row_data(index as usize, turn);
+            }
+        }
+    });
+
+    let s_save_template = state.clone();
+    let u_save_template = ui_handle.clone();
+    ui.on_save_template(move || {
+        let Some(ui) = u_save_template.upgrade() else {
+            return;
+        };
+        let name = ui.get_template_form_name().trim().to_string();
+        if name.is_empty() {
+            return;
+        }
+        let template = templates::Template {
+            id: ui.get_template_form_id() as i64,
+            name,
+            system_prompt: ui.get_template_form_system().to_string(),
+            turns: ui
+                .get_template_form_turns()
+                .iter()
+                .filter(|t| !t.content.trim().is_empty())
+                .map(|t| templates::TemplateTurn {
+                    role: t.role.to_string(),
+                    content: t.content.to_string(),
+                })
+                .collect(),
+        };
+        let s = s_save_template.lock().unwrap();
+        match templates::save_template(&s.db.get(), &template) {
+            Ok(id) => ui.set_template_form_id(id as i32),
+            Err(e) => {
+                eprintln!("Error saving template: {}", e);
+                return;
+            }
+        }
+        refresh_templates(&u_save_template, &s);
+    });
+
+    let s_delete_template = state.clone();
+    let u_delete_template = ui_handle.clone();
+    ui.on_delete_template(move |id| {
+        let s = s_delete_template.lock().unwrap();
+        templates::delete_template(&s.db.get(), id as i64);
+        refresh_templates(&u_delete_template, &s);
+        if let Some(ui) = u_delete_template.upgrade() {
+            ui.invoke_new_template();
+        }
+    });
+
+    let u_new_prompt = ui_handle.clone();
+    ui.on_new_prompt(move || {
+        if let Some(ui) = u_new_prompt.upgrade() {
+            ui.set_prompt_form_id(0);
+            ui.set_prompt_form_name("".into());
+            ui.set_prompt_form_content("".into());
+        }
+    });
+
+    let s_edit_prompt = state.clone();
+    let u_edit_prompt = ui_handle.clone();
+    ui.on_edit_prompt(move |id| {
+        let s = s_edit_prompt.lock().unwrap();
+        let Some(prompt) = prompts::load_prompts(&s.db.get())
+            .into_iter()
+            .find(|p| p.id == id as i64)
+        else {
+            return;
+        };
+        if let Some(ui) = u_edit_prompt.upgrade() {
+            ui.set_prompt_form_id(prompt.id as i32);
+            ui.set_prompt_form_name(prompt.name.into());
+            ui.set_prompt_form_content(prompt.content.into());
+        }
+    });
+
+    let s_save_prompt = state.clone();
+    let u_save_prompt = ui_handle.clone();
+    ui.on_save_prompt(move || {
+        let Some(ui) = u_save_prompt.upgrade() else {
+            return;
+        };
+        let name = ui.get_prompt_form_name().trim().to_string();
+        let content = ui.get_prompt_form_content().to_string();
+        if name.is_empty() || content.trim().is_empty() {
+            return;
+        }
+        let prompt = prompts::Prompt {
+            id: ui.get_prompt_form_id() as i64,
+            name,
+            content,
+        };
+        let s = s_save_prompt.lock().unwrap();
+        match prompts::save_prompt(&s.db.get(), &prompt) {
+            Ok(id) => ui.set_prompt_form_id(id as i32),
+            Err(e) => {
+                eprintln!("Error saving prompt: {}", e);
+                return;
+            }
+        }
+        refresh_prompts(&u_save_prompt, &s);
+    });
+
+    let s_delete_prompt = state.clone();
+    let u_delete_prompt = ui_handle.clone();
+    ui.on_delete_prompt(move |id| {
+        let s = s_delete_prompt.lock().unwrap();
+        prompts::delete_prompt(&s.db.get(), id as i64);
+        refresh_prompts(&u_delete_prompt, &s);
+        if let Some(ui) = u_delete_prompt.upgrade() {
+            ui.invoke_new_prompt();
+        }
+    });
+
+    let s_insert_prompt = state.clone();
+    let u_insert_prompt = ui_handle.clone();
+    ui.on_insert_prompt(move |id| {
+        let Some(ui) = u_insert_prompt.upgrade() else {
+            return;
+        };
+        let expanded = {
+            let mut s = s_insert_prompt.lock().unwrap();
+            let Some(prompt) = prompts::load_prompts(&s.db.get())
+                .into_iter()
+                .find(|p| p.id == id as i64)
+            else {
+                return;
+            };
+            let clipboard = s.clipboard.as_mut();
+            prompts::expand(&prompt.content, |name| {
+                let clipboard = clipboard.as_deref_mut()?;
+                match name {
+                    "clipboard" => clipboard.get_text().ok(),
+                    "selection" => selected_text(clipboard),
+                    _ => None,
+                }
+            })
+        };
+        // Added after whatever is already typed, like pasted code
+        let mut draft = ui.get_draft_text().to_string();
+        if !draft.is_empty() && !draft.ends_with('\n') {
+            draft.push('\n');
+        }
+        draft.push_str(&expanded);
+        ui.set_draft_text(draft.clone().into());
+        crash::set_draft(&draft);
+        ui.set_prompts_open(false);
+    });
+
+    let s_start_template = state.clone();
+    let u_start_template = ui_handle.clone();
+    ui.on_start_from_template(move |id| {
+        let session_id = {
+            let s = s_start_template.lock().unwrap();
+            let Some(template) = templates::load_templates(&s.db.get())
+                .into_iter()
+                .find(|t| t.id == id as i64)
+            else {
+                return;
+            };
+            let session_id = templates::start_session(&s.db.get(), &template);
+            refresh_history(&u_start_template, &s);
+            session_id
+        };
+        if let Some(ui) = u_start_template.upgrade() {
+            ui.set_templates_open(false);
+            ui.invoke_load_session(session_id.into());
+        }
+    });
+
+    let s_send = state.clone();
+    let u_send = ui_handle.clone();
+    ui.on_send_message(move |msg| {
+        let mut s = s_send.lock().unwrap();
+        if db::is_session_locked(&s.db.get(), &s.current_session_id) {
+            return;
+        }
+        let size = RequestSize::measure(&s, &msg);
+        if size.needs_confirmation(&s.config) {
+            // The draft stays in the box, so cancelling doesn't lose it
+            let breakdown = size.breakdown();
+            let _ = u_send.upgrade_in_event_loop(move |ui| {
+                ui.set_large_send_breakdown(breakdown.into());
+                ui.set_large_send_open(true);
+            });
+            return;
+        }
+        enqueue_prompt(&s_send, &u_send, &mut s, &msg);
+        clear_after_send(&u_send, &mut s, true);
+    });
+
+    let s_edit = state.clone();
+    let u_edit = ui_handle.clone();
+    ui.on_edit_message(move |index, text| {
+        let mut s = s_edit.lock().unwrap();
+        let session_id = s.current_session_id.clone();
+        if text.trim().is_empty()
+            || s.is_generating()
+            || db::is_session_locked(&s.db.get(), &session_id)
+        {
+            return;
+        }
+        // Bubbles map one to one onto the session's rows while nothing streams
+        let stored = db::load_messages(&s.db.get(), &session_id);
+        let Some(target) = stored.get(index as usize) else {
+            return;
+        };
+        if target.message.role != MessageRole::User {
+            return;
+        }
+        // A new opening prompt gets a title to match, and a new suggestion
+        // once it's answered, unless the user named the chat
+        if index == 0 && !db::is_title_locked(&s.db.get(), &session_id) {
+            db::update_session_title(&s.db.get(), &session_id, &db::title_from_prompt(&text));
+            refresh_history(&u_edit, &s);
+        }
+        let row_ids = db::delete_messages_from(&s.db.get(), &session_id, target.row_id);
+        s.push_undo(UndoStep::Turns {
+            session_id: session_id.clone(),
+            row_ids,
+            edited: true,
+        });
+        show_undo_toast(&u_edit, "Message edited");
+        digest::forget_from(&s.db.get(), &session_id, target.row_id);
+        s.chat_history.truncate(index as usize);
+        s.resumable = None;
+        let history_for_ui = s.chat_history.clone();
+        let _ = u_edit.upgrade_in_event_loop(move |ui| {
+            ui.set_can_continue(false);
+            update_ui_model(&ui, &history_for_ui);
+        });
+        enqueue_prompt(&s_edit, &u_edit, &mut s, &text);
+        clear_after_send(&u_edit, &mut s, false);
+    });
+
+    let s_retry = state.clone();
+    let u_retry = ui_handle.clone();
+    ui.on_retry_message(move |index, model| {
+        let mut s = s_retry.lock().unwrap();
+        let session_id = s.current_session_id.clone();
+        if model.is_empty() || s.is_generating() || db::is_session_locked(&s.db.get(), &session_id)
+        {
+            return;
+        }
+        // Bubbles map one to one onto the session's rows while nothing streams
+        let stored = db::load_messages(&s.db.get(), &session_id);
+        let Some(target) = stored.get(index as usize) else {
+            return;
+        };
+        if target.message.role != MessageRole::Assistant {
+            return;
+        }
+        // Later turns answered the old reply, so they're hidden until the
+        // next start, unless the retry is undone
+        if let Some(next) = stored.get(index as usize + 1) {
+            let row_ids = db::delete_messages_from(&s.db.get(), &session_id, next.row_id);
+            s.push_undo(UndoStep::Turns {
+                session_id: session_id.clone(),
+                row_ids,
+                edited: false,
+            });
+            show_undo_toast(&u_retry, "Later messages removed");
+        }
+        variants::start_retry(&s.db.get(), target.row_id, &model);
+        digest::forget_from(&s.db.get(), &session_id, target.row_id);
+        s.chat_history.truncate(index as usize);
+        s.resumable = None;
+        s.generating.insert(session_id.clone());
+
+        // Files went out with the original prompt only and aren't resent
+        let mut history_for_ai = s.chat_history.clone();
+        let left_out = leave_out_excluded(&s, &session_id, &mut history_for_ai);
+        let digested = fold_into_digest(&s, &session_id, &mut history_for_ai);
+        let tool_defs = s.tools.clone();
+        let earlier = history_for_ai.len().saturating_sub(1) - usize::from(digested > 0);
+        let mut context = stats::UsedContext {
+            history_sent: earlier,
+            history_total: earlier + left_out + digested,
+            summary: digested > 0,
+            ..Default::default()
+        };
+        if let Some(tool_prompt) = tools::system_prompt(&tool_defs) {
+            history_for_ai.insert(0, ChatMessage::system(tool_prompt));
+            context.tools = true;
+        }
+        if let Some(system_prompt) = session_system_prompt(&s, &session_id) {
+            history_for_ai.insert(0, ChatMessage::system(system_prompt));
+            context.system_prompt = true;
+        }
+
+        let history_for_ui = s.chat_history.clone();
+        let _ = u_retry.upgrade_in_event_loop(move |ui| {
+            ui.set_generating(true);
+            ui.set_can_continue(false);
+            update_ui_model(&ui, &history_for_ui);
+        });
+        refresh_message_labels(&u_retry, &s);
+
+        let handle = spawn_generation(
+            s_retry.clone(),
+            u_retry.clone(),
+            GenerationJob {
+                session_id: session_id.clone(),
+                model_name: model.to_string(),
+                backend: s.backend.clone(),
+                messages: history_for_ai,
+                options: session_options(&s, &session_id),
+                attachment_tokens: 0,
+                tools: tool_defs,
+                context,
+                resume: Some((target.row_id, String::new())),
+                retry: true,
+                cancel: s.cancel_token(&session_id),
+            },
+        );
+        s.tasks.insert(session_id, handle);
+    });
+
+    let s_diff = state.clone();
+    let u_diff = ui_handle.clone();
+    ui.on_open_diff(move |index, left, right| {
+        let s = s_diff.lock().unwrap();
+        let conn = s.db.get();
+        let stored = db::load_messages(&conn, &s.current_session_id);
+        let Some(target) = stored.get(index as usize) else {
+            return;
+        };
+        let outputs = variants::outputs(&conn, target.row_id);
+        let side = |n: i32| {
+            let (text, model) = outputs.get(usize::try_from(n - 1).ok()?)?;
+            let label = if model.is_empty() {
+                format!("Variant {}", n)
+            } else {
+                format!("Variant {} · {}", n, model)
+            };
+            Some((text.as_str(), label))
+        };
+        let (Some((old, left_label)), Some((new, right_label))) = (side(left), side(right)) else {
+            return;
+        };
+        let changes = diff::diff(old, new);
+        let count = |kind| changes.iter().filter(|(c, _)| *c == kind).count();
+        let summary = match (count(diff::Change::Removed), count(diff::Change::Added)) {
+            (0, 0) => "No differences".to_string(),
+            (removed, added) => format!("{} removed · {} added", removed, added),
+        };
+        let lines: Vec<DiffLine> = changes
+            .into_iter()
+            .map(|(change, text)| DiffLine {
+                kind: match change {
+                    diff::Change::Same => "same",
+                    diff::Change::Removed => "removed",
+                    diff::Change::Added => "added",
+                }
+                .into(),
+                text: text.into(),
+            })
+            .collect();
+        let _ = u_diff.upgrade_in_event_loop(move |ui| {
+            ui.set_diff_index(index);
+            ui.set_diff_left(left);
+            ui.set_diff_right(right);
+            ui.set_diff_left_label(left_label.into());
+            ui.set_diff_right_label(right_label.into());
+            ui.set_diff_summary(summary.into());
+            ui.set_diff_lines(Rc::new(VecModel::from(lines)).into());
+            ui.set_diff_open(true);
+        });
+    });
+
+    let s_variant = state.clone();
+    let u_variant = ui_handle.clone();
+    ui.on_switch_variant(move |index, step| {
+        let mut s = s_variant.lock().unwrap();
+        if s.is_generating() || db::is_session_locked(&s.db.get(), &s.current_session_id) {
+            return;
+        }
+        let stored = db::load_messages(&s.db.get(), &s.current_session_id);
+        let Some(target) = stored.get(index as usize) else {
+            return;
+        };
+        if variants::select(&s.db.get(), target.row_id, step) {
+            reload_current_session(&u_variant, &mut s);
+            refresh_message_labels(&u_variant, &s);
+        }
+    });
+
+    let s_delete_msg = state.clone();
+    let u_delete_msg = ui_handle.clone();
+    ui.on_delete_message(move |index| {
+        let mut s = s_delete_msg.lock().unwrap();
+        if s.is_generating() || db::is_session_locked(&s.db.get(), &s.current_session_id) {
+            return;
+        }
+        let stored = db::load_messages(&s.db.get(), &s.current_session_id);
+        let Some(target) = stored.get(index as usize) else {
+            return;
+        };
+        db::delete_message(&s.db.get(), target.row_id);
+        let step = UndoStep::Message {
+            session_id: s.current_session_id.clone(),
+            row_id: target.row_id,
+        };
+        s.push_undo(step);
+        show_undo_toast(&u_delete_msg, "Message deleted");
+        if s.resumable == Some(target.row_id) {
+            s.resumable = None;
+            let _ = u_delete_msg.upgrade_in_event_loop(|ui| ui.set_can_continue(false));
+        }
+        reload_current_session(&u_delete_msg, &mut s);
+        refresh_message_labels(&u_delete_msg, &s);
+        refresh_history(&u_delete_msg, &s);
+    });
+
+    let s_undo = state.clone();
+    let u_undo = ui_handle.clone();
+    ui.on_undo(move || undo_last(&s_undo, &u_undo));
+
+    let s_fork = state.clone();
+    let u_fork = ui_handle.clone();
+    ui.on_fork_from_message(move |index| {
+        let id = {
+            let s = s_fork.lock().unwrap();
+            let stored = db::load_messages(&s.db.get(), &s.current_session_id);
+            let Some(target) = stored.get(index as usize) else {
+                return;
+            };
+            let id = db::fork_session(&s.db.get(), &s.current_session_id, target.row_id);
+            refresh_history(&u_fork, &s);
+            id
+        };
+        if let Some(ui) = u_fork.upgrade() {
+            ui.invoke_load_session(id.into());
+        }
+    });
+
+    let s_exclude = state.clone();
+    let u_exclude = ui_handle.clone();
+    ui.on_set_message_excluded(move |index, excluded| {
+        let s = s_exclude.lock().unwrap();
+        let stored = db::load_messages(&s.db.get(), &s.current_session_id);
+        if let Some(target) = stored.get(index as usize) {
+            db::set_message_excluded(&s.db.get(), target.row_id, excluded);
+            refresh_message_labels(&u_exclude, &s);
+        }
+    });
+
+    let s_read = state.clone();
+    let u_read = ui_handle.clone();
+    ui.on_read_aloud(move |index| {
+        let mut s = s_read.lock().unwrap();
+        // Asking again for the bubble being read stops it
+        if let Some((speech, reading)) = s.speech.take() {
+            speech.stop();
+            if reading == index {
+                if let Some(ui) = u_read.upgrade() {
+                    ui.set_speaking_index(-1);
+                }
+                return;
+            }
+        }
+        let Some(content) = s
+            .visible_history()
+            .get(index as usize)
+            .map(|m| m.content.clone())
+        else {
+            return;
+        };
+        match speech::speak(&markdown::to_plain_text(&content)) {
+            Ok(speech) => s.speech = Some((speech, index)),
+            Err(e) => {
+                eprintln!("Failed to read aloud: {}", e);
+                show_toast(&u_read, "No speech synthesizer found");
+                return;
+            }
+        }
+        if let Some(ui) = u_read.upgrade() {
+            ui.set_speaking_index(index);
+        }
+        let s_read = s_read.clone();
+        let u_read = u_read.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(SPEECH_POLL_INTERVAL).await;
+                let mut s = s_read.lock().unwrap();
+                match s.speech.as_mut() {
+                    Some((speech, reading)) if *reading == index => {
+                        if speech.is_running() {
+                            continue;
+                        }
+                    }
+                    // Stopped, or another bubble is being read now
+                    _ => return,
+                }
+                s.speech = None;
+                let _ = u_read.upgrade_in_event_loop(|ui| ui.set_speaking_index(-1));
+                return;
+            }
+        });
+    });
+
+    let s_confirm = state.clone();
+    let u_confirm = ui_handle.clone();
+    ui.on_confirm_large_send(move |msg| {
+        let mut s = s_confirm.lock().unwrap();
+        if db::is_session_locked(&s.db.get(), &s.current_session_id) {
+            return;
+        }
+        enqueue_prompt(&s_confirm, &u_confirm, &mut s, &msg);
+        clear_after_send(&u_confirm, &mut s, true);
+    });
+
+    let s_post = state.clone();
+    ui.on_set_post_processing(move |strip_think, collapse, trim, rules| {
+        let mut s = s_post.lock().unwrap();
+        let mut chain = Vec::new();
+        if strip_think {
+            chain.push(serde_json::json!({ "kind": "strip_think" }));
+        }
+        if collapse {
+            chain.push(serde_json::json!({ "kind": "collapse_repeats" }));
+        }
+        for line in rules.lines() {
+            if let Some((pattern, replace)) = line.split_once("=>") {
+                chain.push(serde_json::json!({
+                    "kind": "regex",
+                    "pattern": pattern.trim(),
+                    "replace": replace.trim(),
+                }));
+            }
+        }
+        if trim {
+            chain.push(serde_json::json!({ "kind": "trim" }));
+        }
+        s.config["post_processors"] = chain.into();
+        save_config(&s.config);
+    });
+
+    let s_stops = state.clone();
+    ui.on_set_stop_patterns(move |text| {
+        let mut s = s_stops.lock().unwrap();
+        let patterns: Vec<String> = text
+            .lines()
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect();
+        s.config["stop_patterns"] = patterns.into();
+        save_config(&s.config);
+    });
+
+    let s_sync_settings = state.clone();
+    ui.on_apply_sync(move |kind, url, user, secret, region, passphrase| {
+        let mut s = s_sync_settings.lock().unwrap();
+        s.config["sync"] = serde_json::json!({
+            "kind": kind.as_str(),
+            "url": url.trim(),
+            "user": user.as_str(),
+            "secret": secret.as_str(),
+            "region": region.trim(),
+            "passphrase": passphrase.as_str(),
+        });
+        save_config(&s.config);
+    });
+
+    let s_sync = state.clone();
+    let u_sync = ui_handle.clone();
+    ui.on_sync_now(move || {
+        let Some(target) = sync::SyncTarget::from_config(&s_sync.lock().unwrap().config) else {
+            let _ = u_sync.upgrade_in_event_loop(|ui| {
+                ui.set_sync_status("Set a sync URL first".into());
+            });
+            return;
+        };
+        let _ = u_sync.upgrade_in_event_loop(|ui| {
+            ui.set_sync_running(true);
+            ui.set_sync_status("Syncing…".into());
+        });
+        let state = s_sync.clone();
+        let ui_weak = u_sync.clone();
+        tokio::spawn(async move {
+            // Pull and merge first so the pushed bundle contains both sides
+            let result = async {
+                let remote = target.download().await?;
+                let remote: Option<serde_json::Value> = remote
+                    .map(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+                    .transpose()?;
+                let (db, cfg) = {
+                    let s = state.lock().unwrap();
+                    (s.db, s.config.clone())
+                };
+                let (bundle, report, merged) = db
+                    .call(move |conn| {
+                        let mut cfg = cfg;
+                        let report = match &remote {
+                            Some(remote) => sync::merge(conn, &mut cfg, remote),
+                            None => sync::MergeReport {
+                                changed: 0,
+                                conflicts: 0,
+                            },
+                        };
+                        (
+                            sync::export(conn, &cfg),
+                            report,
+                            remote.is_some().then(|| (cfg, load_history(conn))),
+                        )
+                    })
+                    .await;
+                if let Some((cfg, history_rows)) = merged {
+                    let mut s = state.lock().unwrap();
+                    s.config = cfg;
+                    save_config(&s.config);
+                    reload_current_session(&ui_weak, &mut s);
+                    show_history(&ui_weak, &s, history_rows);
+                }
+                let body = serde_json::to_vec(&bundle).map_err(|e| e.to_string())?;
+                target.upload(body).await?;
+                db.call(move |conn| sync::mark_synced(conn, &bundle)).await;
+                refresh_conflicts(&ui_weak, &state.lock().unwrap());
+                Ok::<sync::MergeReport, String>(report)
+            }
+            .await;
+            let status = match result {
+                Ok(report) if report.conflicts > 0 => format!(
+                    "Synced, {} conversations updated, {} need a decision",
+                    report.changed, report.conflicts
+                ),
+                Ok(report) => format!("Synced, {} conversations updated", report.changed),
+                Err(e) => format!("Sync failed: {}", e),
+            };
+            let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+                ui.set_sync_running(false);
+                ui.set_sync_status(status.into());
+            });
+        });
+    });
+
+    let s_resolve = state.clone();
+    let u_resolve = ui_handle.clone();
+    ui.on_resolve_conflict(move |session_id, choice| {
+        let mut s = s_resolve.lock().unwrap();
+        let resolution = |choice: &str| match choice {
+            "mine" => sync::Resolution::KeepMine,
+            "theirs" => sync::Resolution::KeepTheirs,
+            "both" => sync::Resolution::KeepBoth,
+            _ => sync::Resolution::Newest,
+        };
+        let settled = if session_id.is_empty() {
+            let conn = s.db.get();
+            sync::conflicts(&conn)
+                .iter()
+                .map(|c| sync::resolve(&conn, &c.session_id, resolution(&choice)))
+                .fold(true, |all, settled| all && settled)
+        } else {
+            sync::resolve(&s.db.get(), &session_id, resolution(&choice))
+        };
+        if !settled {
+            show_toast(
+                &u_resolve,
+                "Unlock the chat to take the other device's copy",
+            );
+        }
+        reload_current_session(&u_resolve, &mut s);
+        refresh_history(&u_resolve, &s);
+        refresh_conflicts(&u_resolve, &s);
+    });
+
+    let s_export_settings = state.clone();
+    ui.on_export_settings(move || {
+        let bundle = {
+            let s = s_export_settings.lock().unwrap();
+            settings::export(&s.db.get(), &s.config)
+        };
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("JSON", &["json"])
+            .set_file_name("ollama-native-settings.json")
+            .save_file()
+        {
+            let json = serde_json::to_string_pretty(&bundle).unwrap_or_default();
+            if let Err(e) = fs::write(&path, json) {
+                eprintln!("Error exporting settings: {}", e);
+            }
+        }
+    });
+
+    let s_import_settings = state.clone();
+    let u_import_settings = ui_handle.clone();
+    ui.on_import_settings(move || {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("JSON", &["json"])
+            .pick_file()
+        else {
+            return;
+        };
+        let result = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))
+            .and_then(|bundle: serde_json::Value| {
+                let mut s = s_import_settings.lock().unwrap();
+                let mut cfg = s.config.clone();
+                let imported_tools = settings::import(&s.db.get(), &mut cfg, &bundle)?;
+                let was_compressed = s.config["compress_history"].as_bool().unwrap_or(false);
+                s.config = cfg;
+                save_config(&s.config);
+                let compress = s.config["compress_history"].as_bool().unwrap_or(false);
+                if compress != was_compressed {
+                    repack_history(s.db, compress);
+                }
+                let previous = s.backend.endpoint();
+                s.backend = backend::from_config(&s.config);
+                if s.backend.endpoint() != previous {
+                    set_view_state(&u_import_settings, &mut s, ViewState::Connecting);
+                    refresh_models(&s_import_settings, s.backend.clone(), &u_import_settings);
+                }
+                let (cfg, rate_limit) = (s.config.clone(), s.rate_limit());
+                let _ = u_import_settings.upgrade_in_event_loop(move |ui| {
+                    apply_settings(&ui, &cfg);
+                    ui.set_rate_limit(rate_limit as i32);
+                });
+                s.tools = tools::load_tools(&s.db.get());
+                s.presets = presets::load_presets(&s.db.get());
+                refresh_presets(&u_import_settings, &s);
+                refresh_templates(&u_import_settings, &s);
+                refresh_prompts(&u_import_settings, &s);
+                let tool_defs = s.tools.clone();
+                let _ = u_import_settings.upgrade_in_event_loop(move |ui| {
+                    refresh_tools(&ui, &tool_defs);
+                });
+                Ok(imported_tools)
+            });
+        let status = match result {
+            Ok(0) => "Settings imported".to_string(),
+            Ok(tools) => format!(
+                "Settings imported. The {} tools from the file are off until you turn them on.",
+                tools
+            ),
+            Err(e) => format!("Import failed: {}", e),
+        };
+        let _ = u_import_settings.upgrade_in_event_loop(move |ui| {
+            ui.set_settings_status(status.into());
+        });
+    });
+
+    let s_import = state.clone();
+    let u_import = ui_handle.clone();
+    ui.on_import_sessions(move || {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Chat exports", &["json", share::EXTENSION])
+            .pick_file()
+        else {
+            return;
+        };
+        let result = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))
+            .and_then(|export: serde_json::Value| {
+                let mut s = s_import.lock().unwrap();
+                let sessions = import::parse(&s.db.get(), &export)?;
+                let report = import::import(&s.db.get(), &sessions);
+                let conflicts = report.conflicts.len();
+                s.import_conflicts = report.conflicts;
+                refresh_history(&u_import, &s);
+                refresh_import_conflicts(&u_import, &s);
+                Ok((report.imported, conflicts))
+            });
+        let status = match result {
+            Ok((imported, 0)) => format!("Imported {} chats", imported),
+            Ok((imported, conflicts)) => format!(
+                "Imported {} chats, {} already exist here",
+                imported, conflicts
+            ),
+            Err(e) => format!("Import failed: {}", e),
+        };
+        let _ = u_import.upgrade_in_event_loop(move |ui| {
+            ui.set_settings_status(status.into());
+        });
+    });
+
+    let s_archive = state.clone();
+    let u_archive = ui_handle.clone();
+    ui.on_open_archive(move || {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("History database", &["db", "sqlite"])
+            .pick_file()
+        else {
+            return;
+        };
+        let mut s = s_archive.lock().unwrap();
+        match archive::open(&path, Path::new("history.db")) {
+            Ok(opened) => {
+                s.archive = Some(opened);
+                refresh_archive(&u_archive, &s);
+            }
+            Err(e) => {
+                let status = format!("Couldn't open {}: {}", path.display(), e);
+                let _ = u_archive.upgrade_in_event_loop(move |ui| {
+                    ui.set_settings_status(status.into());
+                });
+            }
+        }
+    });
+
+    let s_preview_archive = state.clone();
+    let u_preview_archive = ui_handle.clone();
+    ui.on_preview_archive_session(move |id| {
+        let s = s_preview_archive.lock().unwrap();
+        let Some(archive) = &s.archive else {
+            return;
+        };
+        let messages: Vec<ArchiveMessage> = archive
+            .messages(&id)
+            .iter()
+            .map(|m| ArchiveMessage {
+                role: if m["role"] == "user" { "User" } else { "AI" }.into(),
+                content: m["content"].as_str().unwrap_or("").into(),
+            })
+            .collect();
+        let _ = u_preview_archive.upgrade_in_event_loop(move |ui| {
+            ui.set_archive_preview_id(id);
+            ui.set_archive_preview(Rc::new(VecModel::from(messages)).into());
+        });
+    });
+
+    let s_import_archive = state.clone();
+    let u_import_archive = ui_handle.clone();
+    ui.on_import_archive(move || {
+        let Some(ui) = u_import_archive.upgrade() else {
+            return;
+        };
+        let ids: Vec<String> = ui
+            .get_archive_sessions()
+            .iter()
+            .filter(|e| e.selected)
+            .map(|e| e.id.to_string())
+            .collect();
+        if ids.is_empty() {
+            ui.set_archive_status("Tick the chats to import first".into());
+            return;
+        }
+        let mut s = s_import_archive.lock().unwrap();
+        let sessions: Vec<serde_json::Value> = match &s.archive {
+            Some(archive) => ids.iter().filter_map(|id| archive.session(id)).collect(),
+            None => return,
+        };
+        let report = import::import(&s.db.get(), &sessions);
+        let conflicts = report.conflicts.len();
+        let status = match conflicts {
+            0 => format!("Imported {} chats", report.imported),
+            _ => format!(
+                "Imported {} chats, {} already exist here",
+                report.imported, conflicts
+            ),
+        };
+        s.import_conflicts = report.conflicts;
+        refresh_history(&u_import_archive, &s);
+        refresh_import_conflicts(&u_import_archive, &s);
+        ui.set_archive_status(status.into());
+    });
+
+    let s_close_archive = state.clone();
+    let u_close_archive = ui_handle.clone();
+    ui.on_close_archive(move || {
+        s_close_archive.lock().unwrap().archive = None;
+        let _ = u_close_archive.upgrade_in_event_loop(|ui| {
+            ui.set_archive_open(false);
+            ui.set_archive_sessions(Rc::new(VecModel::from(Vec::<ArchiveEntry>::new())).into());
+            ui.set_archive_preview(Rc::new(VecModel::from(Vec::<ArchiveMessage>::new())).into());
+        });
+    });
+
+    let s_share = state.clone();
+    ui.on_share_session(move || {
+        let (bundle, file_name) = {
+            let s = s_share.lock().unwrap();
+            let bundle = share::export(&s.db.get(), &s.current_session_id);
+            (bundle, session_file_name(&s, &s.current_session_id))
+        };
+        let bundle = match bundle {
+            Ok(bundle) => bundle,
+            Err(e) => {
+                eprintln!("Error sharing chat: {}", e);
+                return;
+            }
+        };
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("Shared chat", &[share::EXTENSION])
+            .set_file_name(format!("{}.{}", file_name, share::EXTENSION))
+            .save_file()
+        {
+            let json = serde_json::to_string(&bundle).unwrap_or_default();
+            if let Err(e) = fs::write(&path, json) {
+                eprintln!("Error sharing chat: {}", e);
+            }
+        }
+    });
+
+    let s_export_session = state.clone();
+    ui.on_export_session(move |format| {
+        let (contents, title) = {
+            let s = s_export_session.lock().unwrap();
+            let contents = match format.as_str() {
+                "json" => serde_json::to_string_pretty(&export::to_json(
+                    &s.db.get(),
+                    &s.current_session_id,
+                ))
+                .unwrap_or_default(),
+                _ => export::to_markdown(&s.db.get(), &s.current_session_id),
+            };
+            (contents, session_file_name(&s, &s.current_session_id))
+        };
+        let (filter, extension) = match format.as_str() {
+            "json" => ("JSON", "json"),
+            _ => ("Markdown", "md"),
+        };
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter(filter, &[extension])
+            .set_file_name(format!("{}.{}", title, extension))
+            .save_file()
+        {
+            if let Err(e) = fs::write(&path, contents) {
+                eprintln!("Error exporting chat: {}", e);
+            }
+        }
+    });
+
+    let s_open_shared = state.clone();
+    let u_open_shared = ui_handle.clone();
+    ui.on_open_shared_chat(move || {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("Shared chat", &[share::EXTENSION])
+            .pick_file()
+        {
+            open_shared_file(&s_open_shared, &u_open_shared, &path);
+        }
+    });
+
+    // Later launches and links clicked elsewhere arrive here instead of
+    // opening another window
+    let s_forwarded = state.clone();
+    let u_forwarded = ui_handle.clone();
+    let runtime = tokio::runtime::Handle::current();
+    instance::listen(move |arg| {
+        let _guard = runtime.enter();
+        open_launch_arg(&s_forwarded, &u_forwarded, arg);
+    });
+    if !launch_arg.is_empty() {
+        open_launch_arg(&state, &ui_handle, launch_arg);
+    }
+
+    let s_resolve_import = state.clone();
+    let u_resolve_import = ui_handle.clone();
+    ui.on_resolve_import(move |index, choice| {
+        let mut s = s_resolve_import.lock().unwrap();
+        let resolution = |choice: &str| match choice {
+            "merge" => import::Resolution::Merge,
+            "duplicate" => import::Resolution::Duplicate,
+            _ => import::Resolution::Skip,
+        };
+        // -1 settles every remaining conflict the same way
+        let settled: Vec<import::Conflict> = if index < 0 {
+            s.import_conflicts.drain(..).collect()
+        } else if (index as usize) < s.import_conflicts.len() {
+            vec![s.import_conflicts.remove(index as usize)]
+        } else {
+            Vec::new()
+        };
+        for conflict in &settled {
+            import::resolve(&s.db.get(), conflict, resolution(&choice));
+        }
+        if settled
+            .iter()
+            .any(|c| c.existing_id == s.current_session_id)
+        {
+            reload_current_session(&u_resolve_import, &mut s);
+        }
+        refresh_history(&u_resolve_import, &s);
+        refresh_import_conflicts(&u_resolve_import, &s);
+    });
+
+    let s_palette = state.clone();
+    let u_palette = ui_handle.clone();
+    ui.on_filter_palette(move |query| {
+        let Some(ui) = u_palette.upgrade() else {
+            return;
+        };
+        let presets: Vec<(i64, String)> = s_palette
+            .lock()
+            .unwrap()
+            .presets
+            .iter()
+            .map(|p| (p.id, p.name.clone()))
+            .collect();
+        let entries = palette_entries(&ui, &presets, &query);
+        ui.set_palette_entries(Rc::new(VecModel::from(entries)).into());
+        ui.set_palette_index(0);
+    });
+
+    let u_command = ui_handle.clone();
+    ui.on_run_command(move |id| {
+        if let Some(ui) = u_command.upgrade() {
+            run_command(&ui, &id);
+        }
+    });
+
+    let u_shortcut = ui_handle.clone();
+    ui.on_shortcut(move |chord| {
+        let Some(command) = commands::for_shortcut(&chord.to_lowercase()) else {
+            return false;
+        };
+        if let Some(ui) = u_shortcut.upgrade() {
+            run_command(&ui, command.id);
+        }
+        true
+    });
+
+    let s_starters = state.clone();
+    let u_starters = ui_handle.clone();
+    ui.on_set_starter_prompts(move |text| {
+        let mut s = s_starters.lock().unwrap();
+        let prompts: Vec<String> = text
+            .lines()
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect();
+        s.config["starter_prompts"] = prompts.into();
+        save_config(&s.config);
+        refresh_starters(&u_starters, &s);
+    });
+
+    let s_send_limits = state.clone();
+    ui.on_set_send_limits(move |tokens, mb| {
+        let mut s = s_send_limits.lock().unwrap();
+        s.config["confirm_tokens"] = tokens.max(0).into();
+        s.config["confirm_attachment_mb"] = mb.max(0).into();
+        save_config(&s.config);
+    });
+
+    let s_preview = state.clone();
+    let u_preview = ui_handle.clone();
+    ui.on_preview_message(move |msg| {
+        let s = s_preview.lock().unwrap();
+        let mut history = s.chat_history.clone();
+        history.push(ChatMessage::user(msg.to_string()));
+        let (messages, _) = compose_request(
+            &s,
+            &s.current_session_id,
+            history,
+            &msg,
+            &outgoing_attachments(&s),
+            &s.tools,
+        );
+        let text = messages
+            .iter()
+            .map(|m| format!("── {} ──\n{}", role_label(&m.role), m.content))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let _ = u_preview.upgrade_in_event_loop(move |ui| {
+            ui.set_preview_text(text.into());
+            ui.set_preview_open(true);
+        });
+    });
+
+    let s_copy = state.clone();
+    let u_copy = ui_handle.clone();
+    ui.on_copy_message(move |index, plain| {
+        let mut s = s_copy.lock().unwrap();
+        // Includes the reply still streaming in, which is the last bubble
+        let Some(content) = s
+            .visible_history()
+            .get(index as usize)
+            .map(|m| m.content.clone())
+        else {
+            return;
+        };
+        let text = if plain {
+            markdown::to_plain_text(&content)
+        } else {
+            content
+        };
+        if copy_to_clipboard(&mut s, &text) {
+            show_toast(&u_copy, "Copied");
+        }
+    });
+
+    let s_copy_code = state.clone();
+    let u_copy_code = ui_handle.clone();
+    ui.on_copy_code(move |code| {
+        if copy_to_clipboard(&mut s_copy_code.lock().unwrap(), &code) {
+            show_toast(&u_copy_code, "Copied");
+        }
+    });
+
+    let s_expand = state.clone();
+    let u_expand = ui_handle.clone();
+    ui.on_expand_message(move |index| {
+        let Some(ui) = u_expand.upgrade() else {
+            return;
+        };
+        let s = s_expand.lock().unwrap();
+        let Some(message) = s.chat_history.get(index as usize) else {
+            return;
+        };
+        let model = ui.get_chat_messages();
+        if let Some(mut row) = model.row_data(index as usize) {
+            row.content = message.content.clone().into();
+            if row.role == "AI" {
+                row.segments = segment_model(markdown::to_segments(&message.content));
+            }
+            row.elided = false;
+            model.set_row_data(index as usize, row);
+        }
+    });
+
+    let s_full = state.clone();
+    ui.on_full_message(move |index| {
+        s_full
+            .lock()
+            .unwrap()
+            .chat_history
+            .get(index as usize)
+            .map(|m| m.content.clone().into())
+            .unwrap_or_default()
+    });
+
+    let s_copy_sel = state.clone();
+    let u_copy_sel = ui_handle.clone();
+    ui.on_copy_selected(move || {
+        let Some(ui) = u_copy_sel.upgrade() else {
+            return;
+        };
+        let mut s = s_copy_sel.lock().unwrap();
+        if let Some(text) = selected_transcript(&ui, &s) {
+            if copy_to_clipboard(&mut s, &text) {
+                show_toast(&u_copy_sel, "Copied");
+            }
+        }
+        end_selection(&ui);
+    });
+
+    let s_export_sel = state.clone();
+    let u_export_sel = ui_handle.clone();
+    ui.on_export_selected(move || {
+        let Some(ui) = u_export_sel.upgrade() else {
+            return;
+        };
+        let Some(text) = selected_transcript(&ui, &s_export_sel.lock().unwrap()) else {
+            return;
+        };
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("Markdown", &["md"])
+            .set_file_name("conversation-excerpt.md")
+            .save_file()
+        {
+            if let Err(e) = fs::write(&path, text) {
+                eprintln!("Error exporting messages: {}", e);
+            }
+        }
+        end_selection(&ui);
+    });
+
+    let u_cancel_sel = ui_handle.clone();
+    ui.on_cancel_selection(move || {
+        if let Some(ui) = u_cancel_sel.upgrade() {
+            end_selection(&ui);
+        }
+    });
+
+    let s_copy_link = state.clone();
+    let u_copy_link = ui_handle.clone();
+    ui.on_copy_link(move |index| {
+        let mut s = s_copy_link.lock().unwrap();
+        let message_id = usize::try_from(index).ok().and_then(|i| {
+            db::load_messages(&s.db.get(), &s.current_session_id)
+                .get(i)
+                .map(|m| m.row_id)
+        });
+        let link = instance::session_link(&s.current_session_id, message_id);
+        if copy_to_clipboard(&mut s, &link) {
+            show_toast(&u_copy_link, "Link copied");
+        }
+    });
+
+    let u_register_links = ui_handle.clone();
+    ui.on_register_link_handler(move || {
+        let status = match instance::register_scheme() {
+            Ok(()) => "Chat links now open in this app".to_string(),
+            Err(e) => format!("Couldn't register chat links: {}", e),
+        };
+        if let Some(ui) = u_register_links.upgrade() {
+            ui.set_settings_status(status.into());
+        }
+    });
+
+    let s_journal_msg = state.clone();
+    ui.on_journal_message(move |role, content| {
+        let s = s_journal_msg.lock().unwrap();
+        let message = if role == "User" {
+            ChatMessage::user(content.to_string())
+        } else {
+            ChatMessage::assistant(content.to_string())
+        };
+        append_to_journal(&s, &s.current_session_id, &[message]);
+    });
+
+    let s_journal_session = state.clone();
+    ui.on_journal_session(move || {
+        let s = s_journal_session.lock().unwrap();
+        append_to_journal(&s, &s.current_session_id, &s.chat_history);
+    });
+
+    let s_journal_dir = state.clone();
+    let u_journal_dir = ui_handle.clone();
+    ui.on_pick_journal_dir(move || {
+        let Some(dir) = rfd::FileDialog::new().pick_folder() else {
+            return;
+        };
+        let mut s = s_journal_dir.lock().unwrap();
+        let dir = dir.to_string_lossy().to_string();
+        s.config["journal_dir"] = dir.clone().into();
+        save_config(&s.config);
+        let _ = u_journal_dir.upgrade_in_event_loop(move |ui| {
+            ui.set_journal_dir(dir.into());
+        });
+    });
+
+    let s_journal_auto = state.clone();
+    ui.on_set_journal_auto(move |enabled| {
+        let mut s = s_journal_auto.lock().unwrap();
+        s.config["journal_auto"] = enabled.into();
+        save_config(&s.config);
+    });
+
+    let u_schedule_file = ui_handle.clone();
+    ui.on_pick_schedule_file(move || {
+        if let Some(path) = rfd::FileDialog::new().pick_file() {
+            let path = path.to_string_lossy().to_string();
+            let _ = u_schedule_file.upgrade_in_event_loop(move |ui| {
+                ui.set_schedule_form_file(path.into());
+            });
+        }
+    });
+
+    let s_add_schedule = state.clone();
+    let u_add_schedule = ui_handle.clone();
+    ui.on_add_schedule(move |time, prompt, file| {
+        let Some(time) = schedule::parse_time(&time) else {
+            let _ = u_add_schedule.upgrade_in_event_loop(|ui| {
+                ui.set_schedule_error("Use a 24-hour time like 08:00".into());
+            });
+            return;
+        };
+        if prompt.trim().is_empty() {
+            return;
+        }
+        let s = s_add_schedule.lock().unwrap();
+        let model = u_add_schedule
+            .upgrade()
+            .map(|ui| ui.get_selected_model().to_string())
+            .unwrap_or_else(|| "llama3".into());
+        let entry = schedule::Schedule {
+            id: 0,
+            session_id: s.current_session_id.clone(),
+            prompt: prompt.to_string(),
+            model,
+            time,
+            attachment: (!file.is_empty()).then(|| PathBuf::from(file.as_str())),
+        };
+        if let Err(e) = schedule::create_schedule(&s.db.get(), &entry) {
+            eprintln!("Error saving schedule: {}", e);
+            return;
+        }
+        refresh_schedules(&u_add_schedule, &s);
+        let _ = u_add_schedule.upgrade_in_event_loop(|ui| {
+            ui.set_schedule_error("".into());
+            ui.set_schedule_form_prompt("".into());
+            ui.set_schedule_form_file("".into());
+        });
+    });
+
+    let s_delete_schedule = state.clone();
+    let u_delete_schedule = ui_handle.clone();
+    ui.on_delete_schedule(move |id| {
+        let s = s_delete_schedule.lock().unwrap();
+        schedule::delete_schedule(&s.db.get(), id as i64);
+        refresh_schedules(&u_delete_schedule, &s);
+    });
+
+    let s_move = state.clone();
+    let u_move = ui_handle.clone();
+    ui.on_move_queued(move |id, delta| {
+        let mut s = s_move.lock().unwrap();
+        if let Some(pos) = s.queue.iter().position(|q| q.id == id as u64) {
+            let target = (pos as i64 + delta as i64).clamp(0, s.queue.len() as i64 - 1) as usize;
+            if let Some(item) = s.queue.remove(pos) {
+                s.queue.insert(target, item);
+            }
+        }
+        refresh_queue(&u_move, &s);
+    });
+
+    let s_cancel = state.clone();
+    let u_cancel = ui_handle.clone();
+    ui.on_cancel_queued(move |id| {
+        let mut s = s_cancel.lock().unwrap();
+        s.queue.retain(|q| q.id != id as u64);
+        refresh_queue(&u_cancel, &s);
+    });
+
+    let s_stats = state.clone();
+    let u_stats = ui_handle.clone();
+    ui.on_open_stats(move || {
+        let s = s_stats.lock().unwrap();
+        let by_day: Vec<UsageStat> = stats::by_day(&s.db.get()).iter().map(usage_stat).collect();
+        let by_model: Vec<UsageStat> = stats::by_model(&s.db.get())
+            .iter()
+            .map(usage_stat)
+            .collect();
+        let max_messages = by_day.iter().map(|r| r.messages).max().unwrap_or(0);
+        let models: Vec<SharedString> = by_model.iter().map(|r| r.label.clone()).collect();
+        let _ = u_stats.upgrade_in_event_loop(move |ui| {
+            if let Some(first) = models.first() {
+                ui.invoke_load_latency(first.clone());
+            }
+            ui.set_stats_models(Rc::new(VecModel::from(models)).into());
+            ui.set_stats_max_messages(max_messages);
+            ui.set_stats_by_day(Rc::new(VecModel::from(by_day)).into());
+            ui.set_stats_by_model(Rc::new(VecModel::from(by_model)).into());
+            ui.set_stats_open(true);
+        });
+    });
+
+    let s_latency = state.clone();
+    let u_latency = ui_handle.clone();
+    ui.on_load_latency(move |model| {
+        let s = s_latency.lock().unwrap();
+        let points: Vec<LatencyPoint> = stats::latency_history(&s.db.get(), &model)
+            .into_iter()
+            .map(|p| LatencyPoint {
+                label: p.at.into(),
+                ttft_ms: p.ttft_ms as i32,
+                tokens_per_sec: p.tokens_per_sec as f32,
+            })
+            .collect();
+        let max_ttft = points.iter().map(|p| p.ttft_ms).max().unwrap_or(0);
+        let max_tps = points.iter().map(|p| p.tokens_per_sec).fold(0.0, f32::max);
+        let _ = u_latency.upgrade_in_event_loop(move |ui| {
+            ui.set_latency_model(model);
+            ui.set_latency_max_ttft(max_ttft);
+            ui.set_latency_max_tps(max_tps);
+            ui.set_latency_points(Rc::new(VecModel::from(points)).into());
+        });
+    });
+
+    let s_storage = state.clone();
+    let u_storage = ui_handle.clone();
+    ui.on_open_storage(move || {
+        let (sessions, backend) = {
+            let s = s_storage.lock().unwrap();
+            let sessions: Vec<SessionSize> = stats::largest_sessions(&s.db.get(), 15)
+                .into_iter()
+                .map(|(id, title, bytes)| SessionSize {
+                    id: id.into(),
+                    title: title.into(),
+                    size: format_bytes(bytes).into(),
+                })
+                .collect();
+            (sessions, s.backend.clone())
+        };
+        let db_size = format_bytes(stats::file_size("history.db"));
+        let attachments_size = format_bytes(stats::dir_size("./attachments"));
+        let _ = u_storage.upgrade_in_event_loop(move |ui| {
+            ui.set_storage_db_size(db_size.into());
+            ui.set_storage_attachments_size(attachments_size.into());
+            ui.set_storage_sessions(Rc::new(VecModel::from(sessions)).into());
+            ui.set_storage_models_size("…".into());
+            ui.set_storage_open(true);
+        });
+
+        let u_models = u_storage.clone();
+        tokio::spawn(async move {
+            let models_size = match backend.list_models().await {
+                Ok(models) => format_bytes(models.iter().map(|m| m.size).sum()),
+                Err(_) => "unavailable".to_string(),
+            };
+            let _ = u_models.upgrade_in_event_loop(move |ui| {
+                ui.set_storage_models_size(models_size.into());
+            });
+        });
+    });
+
+    let u_maintenance = ui_handle.clone();
+    ui.on_run_maintenance(move || {
+        let u_progress = u_maintenance.clone();
+        let u_done = u_maintenance.clone();
+        let _ = u_maintenance.upgrade_in_event_loop(|ui| {
+            ui.set_maintenance_running(true);
+        });
+        tokio::task::spawn_blocking(move || {
+            let result = db::run_maintenance("history.db", |step| {
+                let step = SharedString::from(step);
+                let _ = u_progress.upgrade_in_event_loop(move |ui| {
+                    ui.set_maintenance_status(step);
+                });
+            });
+            let status = match result {
+                Ok(_) => "Maintenance complete, database is healthy.".to_string(),
+                Err(e) => {
+                    crash::set_error(&e);
+                    e
+                }
+            };
+            let _ = u_done.upgrade_in_event_loop(move |ui| {
+                ui.set_maintenance_status(status.into());
+                ui.set_maintenance_running(false);
+                ui.invoke_open_storage();
+            });
+        });
+    });
+
+    let s_export_stats = state.clone();
+    ui.on_export_stats(move || {
+        let csv = {
+            let s = s_export_stats.lock().unwrap();
+            let conn = s.db.get();
+            stats::to_csv(&stats::by_day(&conn), &stats::by_model(&conn))
+        };
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("CSV", &["csv"])
+            .set_file_name("ollama-native-usage.csv")
+            .save_file()
+        {
+            if let Err(e) = fs::write(&path, csv) {
+                eprintln!("Error exporting statistics: {}", e);
+            }
+        }
+    });
+
+    let s_info = state.clone();
+    let u_info = ui_handle.clone();
+    ui.on_show_session_info(move || {
+        let s = s_info.lock().unwrap();
+        let conn = s.db.get();
+        let summary = stats::session_summary(&conn, &s.current_session_id);
+        let info = SessionInfo {
+            messages: summary.messages as i32,
+            tokens: summary.tokens as i32,
+            models: summary.models.join(", ").into(),
+            created_at: summary.created_at.into(),
+            generation_secs: (summary.generation_ms as f32) / 1000.0,
+            summary: summary.summary.into(),
+            digest: digest::load(&conn, &s.current_session_id)
+                .map(|d| d.content)
+                .unwrap_or_default()
+                .into(),
+            title_locked: db::is_title_locked(&conn, &s.current_session_id),
+            reminder: reminders::pending(&conn, &s.current_session_id)
+                .unwrap_or_default()
+                .into(),
+            last_budget: stats::last_prompt_budget(&conn, &s.current_session_id)
+                .map(|b| {
+                    format!(
+                        "system {} · history {} · files {} · prompt {}",
+                        b.system, b.history, b.attachments, b.prompt
+                    )
+                })
+                .unwrap_or_default()
+                .into(),
+        };
+        let _ = u_info.upgrade_in_event_loop(move |ui| {
+            ui.set_session_info(info);
+            ui.set_session_info_open(true);
+        });
+    });
+
+    let s_system = state.clone();
+    ui.on_set_session_system_prompt(move |prompt| {
+        let mut s = s_system.lock().unwrap();
+        s.system_prompt = prompt.to_string();
+        let session_id = s.current_session_id.clone();
+        db::set_session_system_prompt(&s.db.get(), &session_id, &prompt);
+    });
+
+    let s_options = state.clone();
+    ui.on_set_generation_options(move |form| {
+        let mut s = s_options.lock().unwrap();
+        s.options = parse_options_form(&form);
+        let session_id = s.current_session_id.clone();
+        db::set_session_options(&s.db.get(), &session_id, &s.options);
+    });
+
+    let s_style = state.clone();
+    ui.on_set_reply_style(move |format, language| {
+        let mut s = s_style.lock().unwrap();
+        s.reply_format = format.to_string();
+        s.reply_language = language.to_string();
+        let session_id = s.current_session_id.clone();
+        db::set_session_reply_style(&s.db.get(), &session_id, &format, &language);
+    });
+
+    let s_lock = state.clone();
+    let u_lock = ui_handle.clone();
+    ui.on_set_session_locked(move |locked| {
+        let s = s_lock.lock().unwrap();
+        db::set_session_locked(&s.db.get(), &s.current_session_id, locked);
+        let _ = u_lock.upgrade_in_event_loop(move |ui| ui.set_session_locked(locked));
+    });
+
+    let s_title_lock = state.clone();
+    ui.on_set_title_locked(move |locked| {
+        let s = s_title_lock.lock().unwrap();
+        db::set_title_locked(&s.db.get(), &s.current_session_id, locked);
+    });
+
+    let s_remind = state.clone();
+    let u_remind = ui_handle.clone();
+    ui.on_set_reminder(move |preset, note| {
+        let s = s_remind.lock().unwrap();
+        if preset < 0 {
+            reminders::clear(&s.db.get(), &s.current_session_id);
+        } else {
+            reminders::set_reminder(&s.db.get(), &s.current_session_id, preset as usize, &note);
+        }
+        let due = reminders::pending(&s.db.get(), &s.current_session_id).unwrap_or_default();
+        let _ = u_remind.upgrade_in_event_loop(move |ui| {
+            let mut info = ui.get_session_info();
+            info.reminder = due.into();
+            ui.set_session_info(info);
+        });
+    });
+
+    let s_summarize = state.clone();
+    let u_summarize = ui_handle.clone();
+    ui.on_summarize_session(move || {
+        let s = s_summarize.lock().unwrap();
+        if s.chat_history.is_empty() {
+            return;
+        }
+        let model_name = u_summarize
+            .upgrade()
+            .map(|ui| ui.get_selected_model().to_string())
+            .unwrap_or_else(|| "llama3".into());
+        spawn_summary(
+            s_summarize.clone(),
+            u_summarize.clone(),
+            s.backend.clone(),
+            model_name,
+            s.current_session_id.clone(),
+            s.chat_history.clone(),
+        );
+    });
+
+    let s_continue = state.clone();
+    let u_continue = ui_handle.clone();
+    ui.on_continue_generation(move || {
+        let mut s = s_continue.lock().unwrap();
+        if s.is_generating() || db::is_session_locked(&s.db.get(), &s.current_session_id) {
+            return;
+        }
+        let Some(row_id) = s.resumable.take() else {
+            return;
+        };
+        let Some(partial) = s.chat_history.pop() else {
+            return;
+        };
+        let session_id = s.current_session_id.clone();
+        s.generating.insert(session_id.clone());
+
+        let model_name = u_continue
+            .upgrade()
+            .map(|ui| ui.get_selected_model().to_string())
+            .unwrap_or_else(|| "llama3".into());
+
+        let mut history_for_ai = s.chat_history.clone();
+        history_for_ai.push(partial.clone());
+        history_for_ai.push(ChatMessage::user(CONTINUE_PROMPT.to_string()));
+        let tool_defs = s.tools.clone();
+        let mut context = stats::UsedContext {
+            history_sent: history_for_ai.len() - 1,
+            history_total: history_for_ai.len() - 1,
+            ..Default::default()
+        };
+        if let Some(tool_prompt) = tools::system_prompt(&tool_defs) {
+            history_for_ai.insert(0, ChatMessage::system(tool_prompt));
+            context.tools = true;
+        }
+        if let Some(system_prompt) = session_system_prompt(&s, &session_id) {
+            history_for_ai.insert(0, ChatMessage::system(system_prompt));
+            context.system_prompt = true;
+        }
+
+        let history_for_ui = s.chat_history.clone();
+        let _ = u_continue.upgrade_in_event_loop(move |ui| {
+            ui.set_generating(true);
+            ui.set_can_continue(false);
+            update_ui_model(&ui, &history_for_ui);
+        });
+        refresh_history(&u_continue, &s);
+
+        let handle = spawn_generation(
+            s_continue.clone(),
+            u_continue.clone(),
+            GenerationJob {
+                session_id: session_id.clone(),
+                model_name,
+                backend: s.backend.clone(),
+                messages: history_for_ai,
+                options: session_options(&s, &session_id),
+                attachment_tokens: 0,
+                tools: tool_defs,
+                context,
+                resume: Some((row_id, partial.content)),
+                retry: false,
+                cancel: s.cancel_token(&session_id),
+            },
+        );
+        s.tasks.insert(session_id, handle);
+    });
+
+    let s_stop = state.clone();
+    ui.on_stop_generation(move || {
+        let s = s_stop.lock().unwrap();
+        if let Some(token) = s.cancels.get(&s.current_session_id) {
+            token.cancel();
+        }
+    });
+
+    // Made here on the main thread, where the hotkey manager has to live
+    let hotkey = Rc::new(RefCell::new(
+        dictation::Hotkey::new()
+            .map_err(|e| eprintln!("Global hotkey unavailable: {}", e))
+            .ok(),
+    ));
+    if ui.get_dictation() {
+        if let Some(hotkey) = hotkey.borrow_mut().as_mut() {
+            if let Err(e) = hotkey.set(Some(&ui.get_dictation_hotkey())) {
+                eprintln!("Couldn't register the dictation hotkey: {}", e);
+            }
+        }
+    }
+    let u_hotkey = ui_handle.clone();
+    dictation::on_pressed(move || {
+        let _ = u_hotkey.upgrade_in_event_loop(|ui| ui.invoke_dictate());
+    });
+
+    let s_set_dictation = state.clone();
+    let u_set_dictation = ui_handle.clone();
+    ui.on_set_dictation(move |enabled| {
+        let Some(ui) = u_set_dictation.upgrade() else {
+            return;
+        };
+        {
+            let mut s = s_set_dictation.lock().unwrap();
+            s.config["dictation"] = enabled.into();
+            save_config(&s.config);
+        }
+        let chord = ui.get_dictation_hotkey().to_string();
+        let result = match hotkey.borrow_mut().as_mut() {
+            Some(hotkey) => hotkey.set(enabled.then_some(chord.as_str())),
+            None => Err("global shortcuts aren't supported here".into()),
+        };
+        match result {
+            Err(e) if enabled => {
+                ui.set_settings_status(format!("Couldn't set up the shortcut: {}", e).into());
+            }
+            _ => {}
+        }
+    });
+
+    let s_dictate = state.clone();
+    let u_dictate = ui_handle.clone();
+    ui.on_dictate(move || {
+        let Some(ui) = u_dictate.upgrade() else {
+            return;
+        };
+        let mut s = s_dictate.lock().unwrap();
+        // Pressing the hotkey again stops the reply being typed
+        if let Some(cancel) = s.dictation.take() {
+            cancel.cancel();
+            return;
+        }
+        let Some(prompt) = s
+            .clipboard
+            .as_mut()
+            .and_then(selected_text)
+            .filter(|text| !text.trim().is_empty())
+        else {
+            notify_dictation("Select the text to answer first.");
+            return;
+        };
+        let cancel = CancellationToken::new();
+        s.dictation = Some(cancel.clone());
+        let backend = s.backend.clone();
+        let model = ui.get_selected_model().to_string();
+        let s_done = s_dictate.clone();
+        tokio::spawn(async move {
+            let result = dictate(backend, model, prompt, &cancel).await;
+            if !cancel.is_cancelled() {
+                s_done.lock().unwrap().dictation = None;
+            }
+            if let Err(e) = result {
+                notify_dictation(&format!("Couldn't type the reply: {}", e));
+            }
+        });
+    });
+
+    let s_close = state.clone();
+    let u_close = ui_handle.clone();
+    ui.window().on_close_requested(move || {
+        let mut s = s_close.lock().unwrap();
+        for (_, handle) in s.tasks.drain() {
+            handle.abort();
+        }
+        // Streams flush every few seconds; write out whatever arrived since
+        let partials: Vec<ActiveStream> = s.streams.drain().map(|(_, stream)| stream).collect();
+        for stream in partials {
+            let conn = s.db.get();
+            db::set_message_text(&conn, stream.row_id, &stream.text);
+            let _ = conn.execute(
+                "UPDATE messages SET partial = 1 WHERE rowid = ?1",
+                params![stream.row_id],
+            );
+        }
+        if let Some(ui) = u_close.upgrade() {
+            db::save_draft(&s.db.get(), &s.current_session_id, &ui.get_draft_text());
+        }
+        slint::CloseRequestResponse::HideWindow
+    });
+
+    ui.run()
+}
+
+struct QueuedPrompt {
+    id: u64,
+    session_id: String,
+    prompt: String,
+    model_name: String,
+    attachments: Vec<(String, PathBuf)>,
+}
+
+/// Starts queued prompts while there is capacity. Only one prompt per
+/// session runs at a time so each transcript stays in order. When the
+/// endpoint's rate limit is exhausted a timer re-runs dispatch once a slot
+/// frees up.
+fn dispatch_queue(
+    state: &Arc<Mutex<AppState>>,
+    ui_weak: &slint::Weak<AppWindow>,
+    s: &mut AppState,
+) {
+    while s.generating.len() < s.max_concurrent() {
+        let Some(pos) = s
+            .queue
+            .iter()
+            .position(|q| !s.generating.contains(&q.session_id))
+        else {
+            break;
+        };
+        if let Some(wait) = s.rate_wait() {
+            if !s.rate_retry_pending {
+                s.rate_retry_pending = true;
+                let s_retry = state.clone();
+                let u_retry = ui_weak.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(wait).await;
+                    let mut s = s_retry.lock().unwrap();
+                    s.rate_retry_pending = false;
+                    dispatch_queue(&s_retry, &u_retry, &mut s);
+                });
+            }
+            break;
+        }
+        if let Some(item) = s.queue.remove(pos) {
+            let endpoint = s.backend.endpoint();
+            s.rate_log
+                .entry(endpoint)
+                .or_default()
+                .push_back(Instant::now());
+            start_prompt(state, ui_weak, s, item);
+        }
+    }
+    refresh_queue(ui_weak, s);
+}
+
+fn start_prompt(
+    state: &Arc<Mutex<AppState>>,
+    ui_weak: &slint::Weak<AppWindow>,
+    s: &mut AppState,
+    item: QueuedPrompt,
+) {
+    let QueuedPrompt {
+        session_id,
+        prompt: raw_input,
+        model_name,
+        attachments,
+        ..
+    } = item;
+    s.generating.insert(session_id.clone());
+
+    if !db::session_exists(&s.db.get(), &session_id) {
+        let (system_prompt, reply_format, reply_language, options) =
+            if s.current_session_id == session_id {
+                (
+                    s.system_prompt.clone(),
+                    s.reply_format.clone(),
+                    s.reply_language.clone(),
+                    s.options.clone(),
+                )
+            } else {
+                Default::default()
+            };
+        let _ = s.db.get().execute(
+            "INSERT INTO sessions (id, title, created_at, icon, system_prompt, reply_format, reply_language) VALUES (?1, ?2, datetime('now'), ?3, ?4, ?5, ?6)",
+            params![
+                session_id,
+                db::title_from_prompt(&raw_input),
+                db::default_session_icon(&session_id),
+                system_prompt,
+                reply_format,
+                reply_language
+            ],
+        );
+        db::set_session_options(&s.db.get(), &session_id, &options);
+    }
+
+    let prompt_row = db::insert_message(&s.db.get(), &session_id, "user", &raw_input, None);
+    if let Some(language) = language::detect(&raw_input) {
+        let _ = s.db.get().execute(
+            "UPDATE messages SET language = ?1 WHERE rowid = ?2",
+            params![language, prompt_row],
+        );
+    }
+    if s.current_session_id == session_id {
+        refresh_message_labels(ui_weak, s);
+    }
+
+    let is_current = s.current_session_id == session_id;
+    let history_for_ai = if is_current {
+        s.chat_history.push(ChatMessage::user(raw_input.clone()));
+        s.chat_history.clone()
+    } else {
+        db::load_messages(&s.db.get(), &session_id)
+            .into_iter()
+            .map(|m| m.message)
+            .collect()
+    };
+
+    let tool_defs = s.tools.clone();
+    let (history_for_ai, context) = compose_request(
+        s,
+        &session_id,
+        history_for_ai,
+        &raw_input,
+        &attachments,
+        &tool_defs,
+    );
+    let attachment_tokens = attachments
+        .iter()
+        .filter_map(|(_, path)| extract::load(&s.db.get(), &s.token_counter, path))
+        .map(|file| file.tokens)
+        .sum();
+
+    if is_current {
+        let history_for_ui = s.chat_history.clone();
+        let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+            ui.set_generating(true);
+            update_ui_model(&ui, &history_for_ui);
+        });
+    }
+    refresh_history(ui_weak, s);
+
+    let handle = spawn_generation(
+        state.clone(),
+        ui_weak.clone(),
+        GenerationJob {
+            session_id: session_id.clone(),
+            model_name,
+            backend: s.backend.clone(),
+            messages: history_for_ai,
+            options: session_options(s, &session_id),
+            attachment_tokens,
+            tools: tool_defs,
+            context,
+            resume: None,
+            retry: false,
+            cancel: s.cancel_token(&session_id),
+        },
+    );
+    s.tasks.insert(session_id, handle);
+}
+
+fn enqueue_prompt(
+    state: &Arc<Mutex<AppState>>,
+    ui_weak: &slint::Weak<AppWindow>,
+    s: &mut AppState,
+    msg: &str,
+) {
+    let model_name = ui_weak
+        .upgrade()
+        .map(|ui| ui.get_selected_model().to_string())
+        .unwrap_or_else(|| "llama3".into());
+
+    // Prompts go through the queue so a busy session (or a busy GPU)
+    // picks them up in order instead of interleaving two streams.
+    let id = s.next_queue_id;
+    s.next_queue_id += 1;
+    let item = QueuedPrompt {
+        id,
+        session_id: s.current_session_id.clone(),
+        prompt: msg.to_string(),
+        model_name,
+        attachments: outgoing_attachments(s),
+    };
+    s.queue.push_back(item);
+    s.resumable = None;
+    let _ = ui_weak.upgrade_in_event_loop(|ui| ui.set_can_continue(false));
+    dispatch_queue(state, ui_weak, s);
+    settle_view_state(ui_weak, s);
+}
+
+/// Clears what shouldn't outlast a send, unless `keep_prompt_on_send` or
+/// `keep_attachments_on_send` says otherwise. `sent_draft` is false when the
+/// text came from somewhere other than the message box, which then stays.
+fn clear_after_send(ui_weak: &slint::Weak<AppWindow>, s: &mut AppState, sent_draft: bool) {
+    let clear_draft = sent_draft && !s.config["keep_prompt_on_send"].as_bool().unwrap_or(false);
+    if clear_draft {
+        crash::set_draft("");
+        let _ = ui_weak.upgrade_in_event_loop(|ui| ui.set_draft_text("".into()));
+    }
+    // Files go out once unless pinned; follow-ups only see them in the
+    // history of the model's answer
+    if !s.config["keep_attachments_on_send"]
+        .as_bool()
+        .unwrap_or(false)
+    {
+        let session_id = s.current_session_id.clone();
+        db::detach_unpinned(&s.db.get(), &session_id);
+        let pinned = s.pinned_attachments.clone();
+        s.attachments.retain(|(name, _)| pinned.contains(name));
+        let chips = attachment_chips(s);
+        let _ = ui_weak.upgrade_in_event_loop(move |ui| show_attachment_chips(&ui, chips));
+    }
+}
+
+/// Size of the next request, split by where it comes from.
+struct RequestSize {
+    system_tokens: usize,
+    history_tokens: usize,
+    attachment_tokens: usize,
+    attachment_bytes: u64,
+    prompt_tokens: usize,
+}
+
+impl RequestSize {
+    fn measure(s: &AppState, prompt: &str) -> Self {
+        let counter = &s.token_counter;
+        let mut attachment_bytes = 0;
+        let mut attachment_tokens = 0;
+        for (_, path) in outgoing_attachments(s) {
+            if let Some(file) = extract::load(&s.db.get(), counter, &path) {
+                attachment_bytes += file.text.len() as u64;
+                attachment_tokens += file.tokens;
+            }
+        }
+        Self {
+            system_tokens: tools::system_prompt(&s.tools)
+                .map(|p| counter.count(&p))
+                .unwrap_or(0),
+            history_tokens: s
+                .chat_history
+                .iter()
+                .map(|m| counter.count(&m.content))
+                .sum(),
+            attachment_tokens,
+            attachment_bytes,
+            prompt_tokens: counter.count(prompt),
+        }
+    }
+
+    fn total_tokens(&self) -> usize {
+        self.system_tokens + self.history_tokens + self.attachment_tokens + self.prompt_tokens
+    }
+
+    /// Limits come from `confirm_tokens` and `confirm_attachment_mb`; 0
+    /// disables a check.
+    fn needs_confirmation(&self, cfg: &serde_json::Value) -> bool {
+        let max_tokens = cfg["confirm_tokens"].as_u64().unwrap_or(8000) as usize;
+        let max_mb = cfg["confirm_attachment_mb"].as_u64().unwrap_or(5);
+        (max_tokens > 0 && self.total_tokens() > max_tokens)
+            || (max_mb > 0 && self.attachment_bytes > max_mb * 1024 * 1024)
+    }
+
+    fn breakdown(&self) -> String {
+        format!(
+            "System prompt: {} tokens\nHistory: {} tokens\nAttachments: {} tokens ({})\nPrompt: {} tokens\n\nTotal: {} tokens",
+            self.system_tokens,
+            self.history_tokens,
+            self.attachment_tokens,
+            format_bytes(self.attachment_bytes),
+            self.prompt_tokens,
+            self.total_tokens()
+        )
+    }
+}
+
+/// The files sent with the next prompt: the manual attachments plus every
+/// preset marked as always-attach.
+fn outgoing_attachments(s: &AppState) -> Vec<(String, PathBuf)> {
+    let mut attachments = s.attachments.clone();
+    for preset in s.presets.iter().filter(|p| p.auto_attach) {
+        for path in &preset.files {
+            let name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            if !attachments.iter().any(|(n, _)| *n == name) {
+                attachments.push((name, path.clone()));
+            }
+        }
+    }
+    attachments
+}
+
+/// Turns a session history ending in the new user prompt into the exact
+/// message list sent to the backend, along with what it's made of. Used for
+/// sending and for the preview, so anything that changes the request belongs
+/// here.
+fn compose_request(
+    s: &AppState,
+    session_id: &str,
+    mut history: Vec<ChatMessage>,
+    prompt: &str,
+    attachments: &[(String, PathBuf)],
+    tool_defs: &[tools::ToolDef],
+) -> (Vec<ChatMessage>, stats::UsedContext) {
+    let left_out = leave_out_excluded(s, session_id, &mut history);
+    let digested = fold_into_digest(s, session_id, &mut history);
+    let earlier = history.len().saturating_sub(1) - usize::from(digested > 0);
+    let mut context = stats::UsedContext {
+        history_sent: earlier,
+        history_total: earlier + left_out + digested,
+        summary: digested > 0,
+        ..Default::default()
+    };
+    let loaded: Vec<(&str, extract::Extracted)> = attachments
+        .iter()
+        .filter_map(|(name, path)| {
+            extract::load(&s.db.get(), &s.token_counter, path).map(|file| (name.as_str(), file))
+        })
+        .collect();
+    let files: Vec<(&str, &str)> = loaded
+        .iter()
+        .map(|(name, file)| (*name, file.text.as_str()))
+        .collect();
+    let mut prompt_with_context = extract::InjectFormat::from_config(&s.config).render(&files);
+    prompt_with_context.push_str(prompt);
+    // Sent with this request only; the stored and displayed prompt stay as typed
+    if let Some(instruction) = reply_instruction(s, session_id, prompt) {
+        prompt_with_context.push_str("\n\n");
+        prompt_with_context.push_str(&instruction);
+    }
+
+    let images: Vec<Image> = attachments
+        .iter()
+        .filter(|(_, path)| extract::is_image(path))
+        .filter_map(|(_, path)| extract::load_image(path))
+        .map(|data| Image::from_base64(&data))
+        .collect();
+    context.attachments = attachments
+        .iter()
+        .filter(|(_, path)| extract::is_image(path))
+        .map(|(name, _)| name.clone())
+        .chain(loaded.iter().map(|(name, _)| name.to_string()))
+        .collect();
+    if let Some(mut last_msg) = history.pop() {
+        last_msg.content = prompt_with_context;
+        history.push(if images.is_empty() {
+            last_msg
+        } else {
+            last_msg.with_images(images)
+        });
+    }
+
+    if digested == 0 && s.config["resume_with_summary"].as_bool().unwrap_or(false) {
+        let days = s.config["resume_after_days"].as_f64().unwrap_or(7.0);
+        if let Some(summary) = db::stale_session_summary(&s.db.get(), session_id, days) {
+            history.insert(
+                0,
+                ChatMessage::system(format!("{}{}", SUMMARY_INTRO, summary)),
+            );
+            context.summary = true;
+        }
+    }
+
+    if let Some(tool_prompt) = tools::system_prompt(tool_defs) {
+        history.insert(0, ChatMessage::system(tool_prompt));
+        context.tools = true;
+    }
+    if let Some(system_prompt) = session_system_prompt(s, session_id) {
+        history.insert(0, ChatMessage::system(system_prompt));
+        context.system_prompt = true;
+    }
+    (history, context)
+}
+
+/// Drops the messages the user left out of the context from `history`,
+/// which holds the session's messages in order. The last one, the prompt
+/// being answered, always stays. Returns how many were dropped.
+fn leave_out_excluded(s: &AppState, session_id: &str, history: &mut Vec<ChatMessage>) -> usize {
+    let excluded = db::excluded_messages(&s.db.get(), session_id);
+    let last = history.len().saturating_sub(1);
+    let before = history.len();
+    let mut position = 0;
+    history.retain(|_| {
+        let keep = position == last || !excluded.get(position).copied().unwrap_or(false);
+        position += 1;
+        keep
+    });
+    before - history.len()
+}
+
+/// Replaces the turns at the start of `history` that the session's digest
+/// covers with the digest itself. `history` holds the messages that are
+/// sent, in order, and its last one always stays. Returns how many turns
+/// the digest stands in for.
+fn fold_into_digest(s: &AppState, session_id: &str, history: &mut Vec<ChatMessage>) -> usize {
+    let conn = s.db.get();
+    let Some(found) = digest::load(&conn, session_id) else {
+        return 0;
+    };
+    let covered =
+        digest::covered(&conn, session_id, found.through_id).min(history.len().saturating_sub(1));
+    if covered > 0 {
+        history.drain(..covered);
+        history.insert(
+            0,
+            ChatMessage::system(format!("{}{}", SUMMARY_INTRO, found.content)),
+        );
+    }
+    covered
+}
+
+/// The user's system prompt for a session, read from memory for the open
+/// session since its row may not exist yet.
+fn session_system_prompt(s: &AppState, session_id: &str) -> Option<String> {
+    let prompt = if s.current_session_id == session_id {
+        s.system_prompt.clone()
+    } else {
+        db::session_system_prompt(&s.db.get(), session_id)
+    };
+    Some(prompt).filter(|p| !p.trim().is_empty())
+}
+
+fn session_options(s: &AppState, session_id: &str) -> backend::SamplingOptions {
+    if s.current_session_id == session_id {
+        s.options.clone()
+    } else {
+        db::session_options(&s.db.get(), session_id)
+    }
+}
+
+/// Output instructions from the session's format and language toggles, or
+/// with `match_language` set, the language `prompt` is written in.
+fn reply_instruction(s: &AppState, session_id: &str, prompt: &str) -> Option<String> {
+    let (format, language) = if s.current_session_id == session_id {
+        (s.reply_format.clone(), s.reply_language.clone())
+    } else {
+        db::session_reply_style(&s.db.get(), session_id)
+    };
+    let mut parts = Vec::new();
+    match format.as_str() {
+        "json" => {
+            parts.push("Respond only with valid JSON, without any text around it.".to_string())
+        }
+        "bullets" => parts.push("Format the answer as a concise bullet-point list.".to_string()),
+        _ => {}
+    }
+    if !language.trim().is_empty() {
+        parts.push(format!("Answer in {}.", language.trim()));
+    } else if s.config["match_language"].as_bool().unwrap_or(false) {
+        // Small models tend to drift into English unless told otherwise
+        if let Some(detected) = language::detect(prompt) {
+            parts.push(format!(
+                "Answer in {}, the language of this message.",
+                detected
+            ));
+        }
+    }
+    Some(parts.join(" ")).filter(|p| !p.is_empty())
+}
+
+fn role_label(role: &MessageRole) -> &'static str {
+    match role {
+        MessageRole::User => "user",
+        MessageRole::Assistant => "assistant",
+        MessageRole::System => "system",
+        #[allow(unreachable_patterns)]
+        _ => "tool",
+    }
+}
+
+fn refresh_queue(ui_weak: &slint::Weak<AppWindow>, s: &AppState) {
+    let entries: Vec<QueueEntry> = s
+        .queue
+        .iter()
+        .map(|q| {
+            let session_title: String =
+                s.db.get()
+                    .query_row(
+                        "SELECT title FROM sessions WHERE id = ?1",
+                        params![q.session_id],
+                        |row| row.get(0),
+                    )
+                    .unwrap_or_else(|_| "New chat".into());
+            QueueEntry {
+                id: q.id as i32,
+                prompt: q.prompt.clone().into(),
+                session_title: session_title.into(),
+                model: q.model_name.clone().into(),
+            }
+        })
+        .collect();
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_queue_list(Rc::new(VecModel::from(entries)).into());
+    });
+}
+
+struct GenerationJob {
+    session_id: String,
+    model_name: String,
+    backend: Arc<dyn ChatBackend>,
+    messages: Vec<ChatMessage>,
+    options: backend::SamplingOptions,
+    // Tokens of file content injected into the messages
+    attachment_tokens: usize,
+    tools: Vec<tools::ToolDef>,
+    // What `messages` is made of, kept up to date as turns are dropped
+    context: stats::UsedContext,
+    // Existing partial row and its text when continuing an interrupted reply
+    resume: Option<(i64, String)>,
+    // Whether `resume` is a retried reply, which gets its old output back
+    // if no new one comes in
+    retry: bool,
+    cancel: CancellationToken,
+}
+
+/// Shows `text`, the reply streamed so far, in its bubble, the last one.
+fn show_partial_reply(ui_weak: &slint::Weak<AppWindow>, text: &str, length: stats::ReplyLength) {
+    let current_text: SharedString = text.into();
+    // Re-rendered from the start on each update, so blocks still being
+    // streamed settle into their final style as they close
+    let current_segments = markdown::to_segments(text);
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_stream_words(length.words as i32);
+        ui.set_stream_chars(length.chars as i32);
+        let model = ui.get_chat_messages();
+        if let Some(vec_model) = model.as_any().downcast_ref::<VecModel<ChatMessageData>>() {
+            let row_idx = vec_model.row_count() - 1;
+            vec_model.set_row_data(
+                row_idx,
+                ChatMessageData {
+                    role: "AI".into(),
+                    content: current_text,
+                    selected: false,
+                    segments: segment_model(current_segments),
+                    elided: false,
+                    size: "".into(),
+                },
+            );
+        }
+    });
+}
+
+/// Streams a reply for `job.session_id` in the background, running tool
+/// calls as they come back. The caller must already have added the session
+/// to `AppState::generating` and should keep the returned handle in
+/// `AppState::tasks`.
+fn spawn_generation(
+    state: Arc<Mutex<AppState>>,
+    ui_weak: slint::Weak<AppWindow>,
+    job: GenerationJob,
+) -> AbortHandle {
+    let GenerationJob {
+        session_id,
+        model_name,
+        backend: b_client,
+        messages: mut history_for_ai,
+        options,
+        attachment_tokens,
+        tools: tool_defs,
+        mut context,
+        mut resume,
+        retry,
+        cancel,
+    } = job;
+    let inner_u = ui_weak;
+    let inner_s = state;
+    let overflow =
+        context::Overflow::parse(options.context_overflow.as_deref().unwrap_or_default());
+
+    let handle = tokio::spawn(async move {
+        // Each tool call costs a full round trip, cap it so a model that
+        // keeps calling tools can't loop forever.
+        for round in 0..MAX_TOOL_ROUNDS {
+            // A resumed reply goes on to the failure handling below, which
+            // puts its row back in the transcript
+            if cancel.is_cancelled() && resume.is_none() {
+                break;
+            }
+            let started = Instant::now();
+            // With a known context size the request is fitted before it's sent.
+            // Otherwise one the server refuses is retried with the oldest turns
+            // left out until it fits or nothing is left to drop
+            let fitted = match options.num_ctx {
+                Some(window) => {
+                    let (db, counter) = {
+                        let s = inner_s.lock().unwrap();
+                        (s.db, s.token_counter.clone())
+                    };
+                    let summary = if overflow == context::Overflow::Summarize {
+                        let session_id = session_id.clone();
+                        db.call(move |conn| db::stale_session_summary(conn, &session_id, 0.0))
+                            .await
+                    } else {
+                        None
+                    };
+                    fit_to_context(
+                        &counter,
+                        summary,
+                        &mut history_for_ai,
+                        window as usize,
+                        overflow,
+                    )
+                }
+                None => Ok((0, false)),
+            };
+            let (mut dropped, summarized) = fitted.as_ref().map_or((0, false), |f| *f);
+            context.summary |= summarized;
+            let request = match fitted {
+                Err(e) => {
+                    if inner_s.lock().unwrap().current_session_id == session_id {
+                        let notice = e.clone();
+                        let _ = inner_u.upgrade_in_event_loop(move |ui| {
+                            ui.set_context_notice(notice.into());
+                        });
+                    }
+                    Some(Err(e))
+                }
+                Ok(_) => loop {
+                    let result = tokio::select! {
+                        _ = cancel.cancelled() => None,
+                        result = b_client.chat_stream(model_name.clone(), history_for_ai.clone(), options.clone()) => Some(result),
+                    };
+                    match result {
+                        Some(Err(e))
+                            if backend::is_context_overflow(&e)
+                                && overflow != context::Overflow::Fail =>
+                        {
+                            let removed = drop_oldest_turns(&mut history_for_ai);
+                            if removed == 0 {
+                                break Some(Err(e));
+                            }
+                            dropped += removed;
+                        }
+                        other => break other,
+                    }
+                },
+            };
+            context.history_sent = context.history_sent.saturating_sub(dropped);
+            if dropped > 0 && inner_s.lock().unwrap().current_session_id == session_id {
+                let notice = format!(
+                    "The conversation didn't fit the model's context, so the {} oldest message{} were left out of this reply.{}",
+                    dropped,
+                    if dropped == 1 { "" } else { "s" },
+                    match (overflow, summarized) {
+                        (_, true) => " The chat summary was sent in their place.",
+                        (context::Overflow::Summarize, false) => " Summarize the chat to have a summary sent in their place.",
+                        _ => "",
+                    }
+                );
+                let _ = inner_u.upgrade_in_event_loop(move |ui| {
+                    ui.set_context_notice(notice.into());
+                });
+            }
+            let mut stream = match request {
+                Some(Ok(stream)) => stream,
+                failed => {
+                    if let Some(Err(e)) = failed {
+                        eprintln!("Chat request failed: {}", e);
+                        crash::set_error(&e);
+                    }
+                    if let Some((row_id, text)) = resume.take() {
+                        // A retry that never got going shows the output it
+                        // was replacing again
+                        let restored = if retry {
+                            let db = inner_s.lock().unwrap().db;
+                            let session_id = session_id.clone();
+                            db.call(move |conn| {
+                                variants::abandon_retry(conn, row_id)
+                                    .map(|text| (text, load_message_labels(conn, &session_id)))
+                            })
+                            .await
+                        } else {
+                            None
+                        };
+                        let mut s_fail = inner_s.lock().unwrap();
+                        if s_fail.current_session_id == session_id {
+                            let can_continue = restored.is_none();
+                            let text = match restored {
+                                Some((text, labels)) => {
+                                    show_message_labels(&inner_u, labels);
+                                    text
+                                }
+                                None => {
+                                    s_fail.resumable = Some(row_id);
+                                    text
+                                }
+                            };
+                            s_fail.chat_history.push(ChatMessage::assistant(text));
+                            let history_for_ui = s_fail.chat_history.clone();
+                            let _ = inner_u.upgrade_in_event_loop(move |ui| {
+                                ui.set_can_continue(can_continue);
+                                update_ui_model(&ui, &history_for_ui);
+                            });
+                        }
+                    }
+                    break;
+                }
+            };
+
+            // The reply row is written up front and flagged partial so a crash
+            // mid-stream still leaves something to resume from.
+            let (row_id, mut full_response) = match resume.take() {
+                Some(existing) => existing,
+                None => {
+                    let db = inner_s.lock().unwrap().db;
+                    let (session_id, model_name) = (session_id.clone(), model_name.clone());
+                    let row_id = db
+                        .call(move |conn| {
+                            let _ = conn.execute(
+                                "INSERT INTO messages (session_id, role, content, partial, created_at, model) VALUES (?1, 'assistant', '', 1, datetime('now'), ?2)",
+                                params![session_id, model_name],
+                            );
+                            conn.last_insert_rowid()
+                        })
+                        .await;
+                    (row_id, String::new())
+                }
+            };
+            let is_current = {
+                let mut s_start = inner_s.lock().unwrap();
+                s_start.streams.insert(
+                    session_id.clone(),
+                    ActiveStream {
+                        row_id,
+                        text: full_response.clone(),
+                    },
+                );
+                s_start.current_session_id == session_id
+            };
+            // Counted as chunks arrive; a resumed reply starts from its old text
+            let mut length = stats::ReplyLength::default();
+            length.feed(&full_response);
+            if is_current {
+                let initial_text: SharedString = full_response.clone().into();
+                let initial_segments = markdown::to_segments(&full_response);
+                let _ = inner_u.upgrade_in_event_loop(move |ui| {
+                    ui.set_stream_words(length.words as i32);
+                    ui.set_stream_chars(length.chars as i32);
+                    let model = ui.get_chat_messages();
+                    if let Some(vec_model) =
+                        model.as_any().downcast_ref::<VecModel<ChatMessageData>>()
+                    {
+                        vec_model.push(ChatMessageData {
+                            role: "AI".into(),
+                            content: initial_text,
+                            selected: false,
+                            segments: segment_model(initial_segments),
+                            elided: false,
+                            size: "".into(),
+                        });
+                    }
+                });
+            }
+
+            let stop_patterns = postprocess::stop_patterns(&inner_s.lock().unwrap().config);
+            let mut last_flush = Instant::now();
+            let mut prompt_tokens = None;
+            let mut response_tokens = None;
+            let mut prompt_eval_ms = None;
+            let mut eval_ms = None;
+            let mut ttft = None;
+            let mut last_token = None;
+            let mut stopped_by_user = false;
+            let mut repaint = tokio::time::interval(STREAM_REPAINT_INTERVAL);
+            repaint.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            // Text arrived that the bubble doesn't show yet
+            let mut unpainted = false;
+            loop {
+                let res = tokio::select! {
+                    _ = cancel.cancelled() => {
+                        stopped_by_user = true;
+                        break;
+                    }
+                    _ = repaint.tick(), if unpainted => {
+                        unpainted = false;
+                        if inner_s.lock().unwrap().current_session_id == session_id {
+                            show_partial_reply(&inner_u, &full_response, length);
+                        }
+                        continue;
+                    }
+                    next = stream.next() => match next {
+                        Some(Ok(res)) => res,
+                        _ => break,
+                    },
+                };
+                prompt_tokens = res.prompt_tokens.or(prompt_tokens);
+                response_tokens = res.response_tokens.or(response_tokens);
+                prompt_eval_ms = res.prompt_eval_ms.or(prompt_eval_ms);
+                eval_ms = res.eval_ms.or(eval_ms);
+                let chunk = res.content;
+                if !chunk.is_empty() {
+                    if ttft.is_none() {
+                        ttft = Some(started.elapsed());
+                    }
+                    last_token = Some(started.elapsed());
+                }
+                full_response.push_str(&chunk);
+                length.feed(&chunk);
+                // Dropping the stream closes the connection, which stops
+                // the server generating as well.
+                let stopped = match postprocess::find_stop(&stop_patterns, &full_response) {
+                    Some(at) => {
+                        full_response.truncate(at);
+                        true
+                    }
+                    None => false,
+                };
+
+                // Tokens belong to the session that sent the prompt; only
+                // mirror them into the UI while that session is on screen.
+                let (is_current, db) = {
+                    let mut s_chunk = inner_s.lock().unwrap();
+                    if let Some(partial) = s_chunk.streams.get_mut(&session_id) {
+                        if stopped {
+                            partial.text = full_response.clone();
+                        } else {
+                            partial.text.push_str(&chunk);
+                        }
+                    }
+                    (s_chunk.current_session_id == session_id, s_chunk.db)
+                };
+                if last_flush.elapsed() >= PARTIAL_FLUSH_INTERVAL {
+                    let text = full_response.clone();
+                    db.call(move |conn| db::set_message_text(conn, row_id, &text))
+                        .await;
+                    last_flush = Instant::now();
+                }
+                unpainted = is_current;
+                if stopped {
+                    break;
+                }
+            }
+            if unpainted && inner_s.lock().unwrap().current_session_id == session_id {
+                show_partial_reply(&inner_u, &full_response, length);
+            }
+
+            let (db, steps, token_counter, journal_dir) = {
+                let s = inner_s.lock().unwrap();
+                let journal_dir = s.config["journal_auto"]
+                    .as_bool()
+                    .unwrap_or(false)
+                    .then(|| s.config["journal_dir"].as_str().unwrap_or("").to_string())
+                    .filter(|dir| !dir.is_empty());
+                (
+                    s.db,
+                    postprocess::from_config(&s.config),
+                    s.token_counter.clone(),
+                    journal_dir,
+                )
+            };
+            if !steps.is_empty() {
+                full_response = postprocess::apply(&steps, &full_response);
+            }
+            if stopped_by_user {
+                full_response.push_str(STOPPED_MARKER);
+            }
+            let budget = prompt_tokens.map(|tokens| {
+                stats::PromptBudget::split(
+                    &token_counter,
+                    &history_for_ai,
+                    attachment_tokens,
+                    round == 0,
+                    tokens,
+                )
+            });
+            let reply_stats = stats::ResponseStats {
+                prompt_tokens,
+                response_tokens,
+                prompt_eval_ms,
+                eval_ms,
+                stream_ms: ttft
+                    .zip(last_token)
+                    .map(|(first, last)| (last - first).as_millis() as u64),
+            };
+            let duration_ms = started.elapsed().as_millis() as i64;
+            let is_tool_call = tools::parse_tool_call(&full_response).is_some();
+            let (message_count, history_rows, labels) = {
+                let (text, model_name, context, session_id) = (
+                    full_response.clone(),
+                    model_name.clone(),
+                    context.clone(),
+                    session_id.clone(),
+                );
+                db.call(move |conn| {
+                    if let Some(budget) = budget {
+                        let _ = conn.execute(
+                            "UPDATE messages SET budget_system = ?1, budget_history = ?2, budget_attachments = ?3, budget_prompt = ?4 WHERE rowid = ?5",
+                            params![budget.system, budget.history, budget.attachments, budget.prompt, row_id],
+                        );
+                    }
+                    db::set_message_text(conn, row_id, &text);
+                    let _ = conn.execute(
+                        "UPDATE messages SET prompt_tokens = ?1, response_tokens = ?2, duration_ms = ?3, ttft_ms = ?4 WHERE rowid = ?5",
+                        params![
+                            prompt_tokens.map(|t| t as i64),
+                            response_tokens.map(|t| t as i64),
+                            duration_ms,
+                            ttft.map(|t| t.as_millis() as i64),
+                            row_id
+                        ],
+                    );
+                    variants::add_output(conn, row_id, &text, &model_name);
+                    stats::record_context(conn, row_id, &context);
+                    stats::record_response(conn, row_id, &reply_stats);
+                    let _ = conn.execute(
+                        "UPDATE messages SET partial = 0 WHERE rowid = ?1",
+                        params![row_id],
+                    );
+
+                    if let Some(dir) = journal_dir.filter(|_| !is_tool_call) {
+                        let prompt: String = conn
+                            .query_row(
+                                &format!(
+                                    "SELECT {} FROM messages WHERE session_id = ?1 AND role = 'user' AND deleted = 0 ORDER BY rowid DESC LIMIT 1",
+                                    db::MESSAGE_TEXT
+                                ),
+                                params![session_id],
+                                |row| row.get(0),
+                            )
+                            .unwrap_or_default();
+                        write_journal(
+                            conn,
+                            &dir,
+                            &session_id,
+                            &[ChatMessage::user(prompt), ChatMessage::assistant(text)],
+                        );
+                    }
+
+                    let message_count: i64 = conn
+                        .query_row(
+                            &format!(
+                                "SELECT COUNT(*) FROM messages WHERE session_id = ?1 AND deleted = 0 AND {}",
+                                templates::AFTER_SEED
+                            ),
+                            params![session_id],
+                            |row| row.get(0),
+                        )
+                        .unwrap_or(0);
+                    (
+                        message_count,
+                        load_history(conn),
+                        load_message_labels(conn, &session_id),
+                    )
+                })
+                .await
+            };
+
+            {
+                let mut s_final = inner_s.lock().unwrap();
+                s_final.streams.remove(&session_id);
+                if (!steps.is_empty() || stopped_by_user)
+                    && s_final.current_session_id == session_id
+                {
+                    let final_text: SharedString = full_response.clone().into();
+                    let final_segments = markdown::to_segments(&full_response);
+                    let _ = inner_u.upgrade_in_event_loop(move |ui| {
+                        let model = ui.get_chat_messages();
+                        if let Some(vec_model) =
+                            model.as_any().downcast_ref::<VecModel<ChatMessageData>>()
+                        {
+                            let row_idx = vec_model.row_count() - 1;
+                            vec_model.set_row_data(
+                                row_idx,
+                                ChatMessageData {
+                                    role: "AI".into(),
+                                    content: final_text,
+                                    selected: false,
+                                    segments: segment_model(final_segments),
+                                    elided: false,
+                                    size: "".into(),
+                                },
+                            );
+                        }
+                    });
+                }
+                if s_final.current_session_id == session_id {
+                    s_final
+                        .chat_history
+                        .push(ChatMessage::assistant(full_response.clone()));
+                }
+                if s_final.current_session_id == session_id {
+                    show_message_labels(&inner_u, labels);
+                }
+                show_history(&inner_u, &s_final, history_rows);
+
+                if s_final.config["auto_copy"].as_bool().unwrap_or(false) && !is_tool_call {
+                    copy_to_clipboard(&mut s_final, &full_response);
+                }
+
+                if s_final.config["auto_summarize"].as_bool().unwrap_or(false)
+                    && !s_final.digesting.contains(&session_id)
+                {
+                    s_final.digesting.insert(session_id.clone());
+                    spawn_digest(
+                        inner_s.clone(),
+                        b_client.clone(),
+                        model_name.clone(),
+                        session_id.clone(),
+                    );
+                }
+
+                if message_count == 2 && s_final.config["model_icons"].as_bool().unwrap_or(false) {
+                    spawn_icon_suggestion(
+                        inner_s.clone(),
+                        inner_u.clone(),
+                        b_client.clone(),
+                        model_name.clone(),
+                        session_id.clone(),
+                    );
+                }
+                if message_count == 2 && s_final.config["model_titles"].as_bool().unwrap_or(true) {
+                    spawn_title_suggestion(
+                        inner_s.clone(),
+                        inner_u.clone(),
+                        b_client.clone(),
+                        model_name.clone(),
+                        session_id.clone(),
+                        full_response.clone(),
+                    );
+                }
+            }
+
+            if stopped_by_user {
+                break;
+            }
+            let Some((tool_name, args)) = tools::parse_tool_call(&full_response) else {
+                break;
+            };
+            let Some(tool) = tool_defs.iter().find(|t| t.enabled && t.name == tool_name) else {
+                break;
+            };
+            let result = match tools::run_tool(tool, &args).await {
+                Ok(output) => output,
+                Err(e) => format!("Error: {}", e),
+            };
+            let tool_message = format!("Tool result ({}):\n{}", tool_name, result);
+
+            history_for_ai.push(ChatMessage::assistant(full_response));
+            history_for_ai.push(ChatMessage::user(tool_message.clone()));
+            // The tool result is the new prompt, the call joins the history
+            context.history_sent += 2;
+            context.history_total += 2;
+
+            let db = inner_s.lock().unwrap().db;
+            {
+                let (session_id, tool_message) = (session_id.clone(), tool_message.clone());
+                db.call(move |conn| {
+                    db::insert_message(conn, &session_id, "user", &tool_message, None);
+                })
+                .await;
+            }
+            let mut s_tool = inner_s.lock().unwrap();
+            if s_tool.current_session_id == session_id {
+                s_tool
+                    .chat_history
+                    .push(ChatMessage::user(tool_message.clone()));
+                let history_for_ui = s_tool.chat_history.clone();
+                let _ = inner_u.upgrade_in_event_loop(move |ui| {
+                    update_ui_model(&ui, &history_for_ui);
+                });
+            }
+        }
+
+        let history_rows = {
+            let db = inner_s.lock().unwrap().db;
+            db.call(load_history).await
+        };
+        let mut s_done = inner_s.lock().unwrap();
+        s_done.generating.remove(&session_id);
+        s_done.tasks.remove(&session_id);
+        s_done.cancels.remove(&session_id);
+        if s_done.current_session_id == session_id {
+            let _ = inner_u.upgrade_in_event_loop(|ui| {
+                ui.set_generating(false);
+            });
+        } else {
+            s_done.unread.insert(session_id.clone());
+        }
+        show_history(&inner_u, &s_done, history_rows);
+        dispatch_queue(&inner_s, &inner_u, &mut s_done);
+    });
+    handle.abort_handle()
+}
+
+/// Fits `messages` into a context window of `window` tokens as the
+/// session's `overflow` setting says, with `summary` the session summary
+/// when it has one. Returns how many turns were left out and whether the
+/// summary was added in their place.
+fn fit_to_context(
+    counter: &tokens::TokenCounter,
+    summary: Option<String>,
+    messages: &mut Vec<ChatMessage>,
+    window: usize,
+    overflow: context::Overflow,
+) -> Result<(usize, bool), String> {
+    if overflow != context::Overflow::Summarize {
+        return context::fit(messages, counter, window, overflow).map(|dropped| (dropped, false));
+    }
+    let mut trimmed = messages.clone();
+    if context::fit(&mut trimmed, counter, window, overflow)? == 0 {
+        return Ok((0, false));
+    }
+    // Sent already when the chat was resumed with its summary
+    let summary = summary
+        .map(|summary| ChatMessage::system(format!("{}{}", SUMMARY_INTRO, summary)))
+        .filter(|summary| !messages.iter().any(|m| m.content == summary.content));
+    match summary {
+        Some(summary) => {
+            context::insert_summary(messages, summary);
+            context::fit(messages, counter, window, overflow).map(|dropped| (dropped, true))
+        }
+        None => {
+            let dropped = messages.len() - trimmed.len();
+            *messages = trimmed;
+            Ok((dropped, false))
+        }
+    }
+}
+
+/// Removes the older half of the conversation from a request, keeping system
+/// messages and the latest message. Returns how many messages were removed.
+fn drop_oldest_turns(messages: &mut Vec<ChatMessage>) -> usize {
+    let droppable: Vec<usize> = messages
+        .iter()
+        .enumerate()
+        .take(messages.len().saturating_sub(1))
+        .filter(|(_, m)| m.role != MessageRole::System)
+        .map(|(i, _)| i)
+        .collect();
+    let count = droppable.len().div_ceil(2);
+    for &i in droppable[..count].iter().rev() {
+        messages.remove(i);
+    }
+    count
+}
+
+/// Asks the model for an emoji summing up a new session and stores it as
+/// the session icon, replacing the hash-based default.
+fn spawn_icon_suggestion(
+    state: Arc<Mutex<AppState>>,
+    ui_weak: slint::Weak<AppWindow>,
+    backend: Arc<dyn ChatBackend>,
+    model_name: String,
+    session_id: String,
+) {
+    tokio::spawn(async move {
+        let db = state.lock().unwrap().db;
+        let id = session_id.clone();
+        let first_prompt: String = db
+            .call(move |conn| {
+                conn.query_row(
+                    &format!(
+                        "SELECT {} FROM messages WHERE session_id = ?1 AND role = 'user' AND deleted = 0 AND {} ORDER BY id LIMIT 1",
+                        db::MESSAGE_TEXT,
+                        templates::AFTER_SEED
+                    ),
+                    params![id],
+                    |row| row.get(0),
+                )
+                .unwrap_or_default()
+            })
+            .await;
+        let messages = vec![
+            ChatMessage::system(
+                "Reply with exactly one emoji that represents the topic of the user's message. No other text."
+                    .to_string(),
+            ),
+            ChatMessage::user(first_prompt),
+        ];
+        let Ok(reply) = backend::collect_reply(backend.as_ref(), model_name, messages).await else {
+            return;
+        };
+        let icon: String = reply
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .chars()
+            .take(4)
+            .collect();
+        if icon.is_empty() || icon.chars().any(|c| c.is_alphanumeric()) {
+            return;
+        }
+        db.call(move |conn| {
+            let _ = conn.execute(
+                "UPDATE sessions SET icon = ?1 WHERE id = ?2",
+                params![icon, session_id],
+            );
+        })
+        .await;
+        refresh_history_async(&state, &ui_weak).await;
+    });
+}
+
+/// Asks the model for a short title once the first exchange is done and
+/// replaces the one cut from the opening prompt. Titles the user renamed,
+/// then or at any point before, are left alone.
+fn spawn_title_suggestion(
+    state: Arc<Mutex<AppState>>,
+    ui_weak: slint::Weak<AppWindow>,
+    backend: Arc<dyn ChatBackend>,
+    model_name: String,
+    session_id: String,
+    reply: String,
+) {
+    tokio::spawn(async move {
+        let db = state.lock().unwrap().db;
+        let id = session_id.clone();
+        let first_prompt: String = db
+            .call(move |conn| {
+                conn.query_row(
+                    &format!(
+                        "SELECT {} FROM messages WHERE session_id = ?1 AND role = 'user' AND deleted = 0 AND {} ORDER BY id LIMIT 1",
+                        db::MESSAGE_TEXT,
+                        templates::AFTER_SEED
+                    ),
+                    params![id],
+                    |row| row.get(0),
+                )
+                .unwrap_or_default()
+            })
+            .await;
+        let messages = vec![
+            ChatMessage::system(
+                "Summarize the conversation below as a title of at most 5 words. Reply with the title only, no quotes or punctuation at the end."
+                    .to_string(),
+            ),
+            ChatMessage::user(format!("User: {}\n\nAssistant: {}", first_prompt, reply)),
+        ];
+        let Ok(reply) = backend::collect_reply(backend.as_ref(), model_name, messages).await else {
+            return;
+        };
+        let title: String = reply
+            .lines()
+            .map(|l| l.trim().trim_matches(['"', '\'', '*', '#']).trim())
+            .find(|l| !l.is_empty())
+            .unwrap_or("")
+            .split_whitespace()
+            .take(8)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let title = title.trim_end_matches(['.', '!', ':']);
+        if title.is_empty() {
+            return;
+        }
+        let title = title.to_string();
+        let updated = db
+            .call(move |conn| {
+                let current: String = conn
+                    .query_row(
+                        "SELECT title FROM sessions WHERE id = ?1",
+                        params![session_id],
+                        |row| row.get(0),
+                    )
+                    .unwrap_or_default();
+                // A template's chat starts out named after the template
+                (current == db::title_from_prompt(&first_prompt)
+                    || templates::is_seeded(conn, &session_id))
+                    && !db::is_title_locked(conn, &session_id)
+                    && !db::is_session_locked(conn, &session_id)
+                    && db::update_session_title(conn, &session_id, &title)
+            })
+            .await;
+        if updated {
+            refresh_history_async(&state, &ui_weak).await;
+        }
+    });
+}
+
+/// Asks the model for a short summary of the session and stores it on the
+/// session row, where the sidebar and long-pause resumes pick it up.
+fn spawn_summary(
+    state: Arc<Mutex<AppState>>,
+    ui_weak: slint::Weak<AppWindow>,
+    backend: Arc<dyn ChatBackend>,
+    model_name: String,
+    session_id: String,
+    history: Vec<ChatMessage>,
+) {
+    let _ = ui_weak.upgrade_in_event_loop(|ui| ui.set_summarizing(true));
+    tokio::spawn(async move {
+        let mut messages = history;
+        messages.push(ChatMessage::user(
+            "Summarize this conversation in a few sentences: the topic, what was decided and what is still open. Reply with the summary only."
+                .to_string(),
+        ));
+        let reply = backend::collect_reply(backend.as_ref(), model_name, messages).await;
+        match reply {
+            Ok(summary) => {
+                let db = state.lock().unwrap().db;
+                let (id, text) = (session_id.clone(), summary.clone());
+                db.call(move |conn| db::set_session_summary(conn, &id, &text))
+                    .await;
+                refresh_history_async(&state, &ui_weak).await;
+                if state.lock().unwrap().current_session_id == session_id {
+                    let summary = summary.trim().to_string();
+                    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+                        let mut info = ui.get_session_info();
+                        info.summary = summary.into();
+                        ui.set_session_info(info);
+                    });
+                }
+            }
+            Err(e) => eprintln!("Summary failed: {}", e),
+        }
+        let _ = ui_weak.upgrade_in_event_loop(|ui| ui.set_summarizing(false));
+    });
+}
+
+/// Summarizes the session's older turns into its digest once those not yet
+/// covered grow past the `auto_summarize_tokens` estimate, keeping the
+/// latest few out of it. The caller adds the session to
+/// `AppState::digesting`, which this clears when done.
+fn spawn_digest(
+    state: Arc<Mutex<AppState>>,
+    backend: Arc<dyn ChatBackend>,
+    model_name: String,
+    session_id: String,
+) {
+    tokio::spawn(async move {
+        let (db, counter, threshold) = {
+            let s = state.lock().unwrap();
+            let threshold = s.config["auto_summarize_tokens"]
+                .as_u64()
+                .unwrap_or(AUTO_SUMMARIZE_TOKENS);
+            (s.db, s.token_counter.clone(), threshold as usize)
+        };
+        let id = session_id.clone();
+        let (previous, stored, excluded) = db
+            .call(move |conn| {
+                (
+                    digest::load(conn, &id),
+                    db::load_messages(conn, &id),
+                    db::excluded_messages(conn, &id),
+                )
+            })
+            .await;
+        let after = previous.as_ref().map_or(0, |d| d.through_id);
+        let pending: Vec<&db::StoredMessage> = stored
+            .iter()
+            .zip(excluded.iter().copied().chain(std::iter::repeat(false)))
+            .filter(|(m, excluded)| m.row_id > after && !m.partial && !excluded)
+            .map(|(m, _)| m)
+            .collect();
+        let tokens: usize = pending
+            .iter()
+            .map(|m| counter.count(&m.message.content))
+            .sum();
+        let fold = pending.len().saturating_sub(DIGEST_KEEP_RECENT);
+        if tokens >= threshold && fold > 0 {
+            let mut transcript = String::new();
+            if let Some(previous) = &previous {
+                transcript.push_str(&format!(
+                    "Summary of the turns before these:\n{}\n\n",
+                    previous.content
+                ));
+            }
+            for m in &pending[..fold] {
+                let speaker = match m.message.role {
+                    MessageRole::Assistant => "Assistant",
+                    _ => "User",
+                };
+                transcript.push_str(&format!("{}: {}\n\n", speaker, m.message.content));
+            }
+            let messages = vec![
+                ChatMessage::system(
+                    "Condense the conversation below into a summary the assistant can continue from in place of it: the topic, facts and decisions, code or names that were settled on, and what is still open. Reply with the summary only."
+                        .to_string(),
+                ),
+                ChatMessage::user(transcript),
+            ];
+            let through_id = pending[fold - 1].row_id;
+            match backend::collect_reply(backend.as_ref(), model_name, messages).await {
+                Ok(summary) if !summary.trim().is_empty() => {
+                    let id = session_id.clone();
+                    db.call(move |conn| digest::save(conn, &id, summary.trim(), through_id))
+                        .await;
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Summarizing older turns failed: {}", e),
+            }
+        }
+        state.lock().unwrap().digesting.remove(&session_id);
+    });
+}
+
+fn segment_model(segments: Vec<markdown::Segment>) -> slint::ModelRc<MdSegment> {
+    let rows: Vec<MdSegment> = segments
+        .into_iter()
+        .map(|seg| MdSegment {
+            kind: seg.kind.into(),
+            text: seg.text.into(),
+            marker: seg.marker.into(),
+            level: seg.level as i32,
+            bold: seg.bold,
+            italic: seg.italic,
+            mono: seg.mono,
+            lang: seg.lang.into(),
+            lines: code_line_model(seg.code_lines),
+        })
+        .collect();
+    Rc::new(VecModel::from(rows)).into()
+}
+
+fn code_line_model(lines: Vec<Vec<highlight::Span>>) -> slint::ModelRc<CodeLine> {
+    let rows: Vec<CodeLine> = lines
+        .into_iter()
+        .map(|spans| {
+            let spans: Vec<CodeSpan> = spans
+                .into_iter()
+                .map(|span| CodeSpan {
+                    text: span.text.into(),
+                    color: slint::Color::from_rgb_u8(span.color.0, span.color.1, span.color.2),
+                })
+                .collect();
+            CodeLine {
+                spans: Rc::new(VecModel::from(spans)).into(),
+            }
+        })
+        .collect();
+    Rc::new(VecModel::from(rows)).into()
+}
+
+/// Start of a message too long to lay out in full, cut on a line break
+/// where there's one close by.
+fn elided_text(text: &str) -> Option<&str> {
+    if text.len() <= ELIDE_BYTES {
+        return None;
+    }
+    let mut cut = ELIDE_BYTES;
+    while !text.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    let head = &text[..cut];
+    Some(match head.rfind('\n') {
+        Some(pos) if pos > ELIDE_BYTES / 2 => &head[..pos],
+        _ => head,
+    })
+}
+
+fn message_row(m: &ChatMessage) -> ChatMessageData {
+    let is_user = m.role == ollama_rs::generation::chat::MessageRole::User;
+    let shown = elided_text(&m.content);
+    let text = shown.unwrap_or(&m.content);
+    ChatMessageData {
+        role: if is_user { "User".into() } else { "AI".into() },
+        content: text.into(),
+        selected: false,
+        segments: if is_user {
+            Default::default()
+        } else {
+            segment_model(markdown::to_segments(text))
+        },
+        elided: shown.is_some(),
+        size: if shown.is_some() {
+            format_bytes(m.content.len() as u64).into()
+        } else {
+            "".into()
+        },
+    }
+}
+
+fn update_ui_model(ui: &AppWindow, history: &[ChatMessage]) {
+    let ui_messages: Vec<ChatMessageData> = history.iter().map(message_row).collect();
+    ui.set_chat_messages(Rc::new(VecModel::from(ui_messages)).into());
+}
+
+/// A session as the sidebar lists it, before `show_history` adds what only
+/// the app state knows.
+struct HistoryRow {
+    id: String,
+    title: String,
+    icon: String,
+    summary: String,
+    reminder: bool,
+}
+
+fn load_history(conn: &Connection) -> Vec<HistoryRow> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, title, icon, summary FROM sessions WHERE deleted = 0
+             ORDER BY COALESCE(updated_at, created_at) DESC",
+        )
+        .unwrap();
+    let reminded = reminders::fired_sessions(conn);
+    stmt.query_map([], |row| {
+        let id = row.get::<usize, String>(0).unwrap();
+        let icon = row
+            .get::<usize, Option<String>>(2)?
+            .unwrap_or_else(|| db::default_session_icon(&id).to_string());
+        Ok(HistoryRow {
+            icon,
+            reminder: reminded.contains(&id),
+            title: row.get::<usize, String>(1).unwrap(),
+            summary: row.get::<usize, Option<String>>(3)?.unwrap_or_default(),
+            id,
+        })
+    })
+    .unwrap()
+    .map(|r| r.unwrap())
+    .collect()
+}
+
+fn show_history(ui_weak: &slint::Weak<AppWindow>, s: &AppState, rows: Vec<HistoryRow>) {
+    let history_items: Vec<HistoryEntry> = rows
+        .into_iter()
+        .map(|row| HistoryEntry {
+            icon: row.icon.into(),
+            generating: s.generating.contains(&row.id),
+            unread: s.unread.contains(&row.id),
+            reminder: row.reminder,
+            id: row.id.into(),
+            title: row.title.into(),
+            summary: row.summary.into(),
+        })
+        .collect();
+
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_history_source(Rc::new(VecModel::from(history_items)).into());
+        apply_history_filter(&ui);
+    });
+}
+
+fn refresh_history(ui_weak: &slint::Weak<AppWindow>, s: &AppState) {
+    let rows = load_history(&s.db.get());
+    show_history(ui_weak, s, rows);
+}
+
+/// `refresh_history` for tasks: the sessions are read off the runtime and
+/// the state is locked only once they're in.
+async fn refresh_history_async(state: &Arc<Mutex<AppState>>, ui_weak: &slint::Weak<AppWindow>) {
+    let db = state.lock().unwrap().db;
+    let rows = db.call(load_history).await;
+    show_history(ui_weak, &state.lock().unwrap(), rows);
+}
+
+fn usage_stat(row: &stats::UsageRow) -> UsageStat {
+    UsageStat {
+        label: row.label.clone().into(),
+        messages: row.messages as i32,
+        prompt_tokens: row.prompt_tokens as i32,
+        response_tokens: row.response_tokens as i32,
+        avg_response_ms: row.avg_response_ms as i32,
+    }
+}
+
+/// One line about an installed model for the models dialog, leaving out
+/// whatever the server didn't report.
+fn model_details(model: &backend::ModelEntry, details: &backend::ModelDetails) -> String {
+    let mut parts = Vec::new();
+    if model.size > 0 {
+        parts.push(format_bytes(model.size));
+    }
+    if !details.parameter_size.is_empty() {
+        parts.push(format!("{} parameters", details.parameter_size));
+    }
+    parts.push(details.quantization.clone());
+    parts.push(details.family.clone());
+    // RFC 3339 timestamp, of which the date is enough here
+    if let Some(date) = model.modified_at.get(..10) {
+        parts.push(format!("modified {}", date));
+    }
+    parts.retain(|p| !p.is_empty());
+    parts.join(" · ")
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Narrows the loaded session list to titles containing the sidebar filter
+/// and sessions whose messages mention it.
+fn apply_history_filter(ui: &AppWindow) {
+    let needle = ui.get_history_filter().to_lowercase();
+    let source = ui.get_history_source();
+    let content_hits: HashSet<SharedString> = ui.get_history_content_hits().iter().collect();
+    let entries: Vec<HistoryEntry> = source
+        .iter()
+        .filter(|e| {
+            needle.is_empty()
+                || e.title.to_lowercase().contains(&needle)
+                || content_hits.contains(&e.id)
+        })
+        .collect();
+    ui.set_history_list(Rc::new(VecModel::from(entries)).into());
+}
+
+/// Copies a file into the session's attachment folder and remembers it in
+/// the recent files list. Files over the size limit are stored shortened, or
+/// refused when that would break the total limit or truncation is off.
+fn attach_file(s: &mut AppState, ui_weak: &slint::Weak<AppWindow>, path: &Path) {
+    let session_id = s.current_session_id.clone();
+    let filename = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    // Images go to the model as they are and don't count toward the limits
+    // on prompt text
+    let image = extract::is_image(path);
+    let limits = extract::Limits::from_config(&s.config);
+    // PDFs are attached as the text of their pages, and the limits apply to
+    // that text rather than to the file
+    let pdf_pages = if extract::is_pdf(path) {
+        match fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| extract::pdf_pages(&bytes))
+        {
+            Ok(pages) => Some(pages),
+            Err(e) => {
+                show_attachment_notice(ui_weak, format!("{} not attached: {}", filename, e));
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    let size = match &pdf_pages {
+        Some(pages) => extract::join_pages(pages, usize::MAX).len() as u64,
+        None => fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+    };
+    let oversized = !image && limits.max_file > 0 && size > limits.max_file;
+    let kept = if oversized { limits.max_file } else { size };
+    let used: u64 = s
+        .attachments
+        .iter()
+        .filter(|(_, p)| !extract::is_image(p))
+        .filter_map(|(_, p)| fs::metadata(p).ok())
+        .map(|m| m.len())
+        .sum();
+    if !image && limits.max_total > 0 && used + kept > limits.max_total {
+        show_attachment_notice(
+            ui_weak,
+            format!(
+                "{} not attached: attachments would exceed {} KB in total",
+                filename,
+                limits.max_total / 1024
+            ),
+        );
+        return;
+    }
+
+    let mut dest_dir = PathBuf::from("./attachments");
+    dest_dir.push(&session_id);
+    let _ = fs::create_dir_all(&dest_dir);
+
+    // The chip keeps the PDF's name, the copy on disk is the extracted text
+    let dest_path = if pdf_pages.is_some() {
+        dest_dir.join(format!("{}.txt", filename))
+    } else {
+        dest_dir.join(&filename)
+    };
+    let copied = if oversized {
+        let Some(keep_tail) = limits.truncate else {
+            show_attachment_notice(
+                ui_weak,
+                format!("{} is larger than {} KB", filename, limits.max_file / 1024),
+            );
+            return;
+        };
+        match &pdf_pages {
+            Some(pages) => fs::write(
+                &dest_path,
+                extract::join_pages(pages, limits.max_file as usize),
+            ),
+            None => match fs::read(path).map(String::from_utf8) {
+                Ok(Ok(text)) => fs::write(
+                    &dest_path,
+                    extract::truncate(&text, limits.max_file as usize, keep_tail),
+                ),
+                Ok(Err(_)) => {
+                    show_attachment_notice(
+                        ui_weak,
+                        format!(
+                            "{} is too large and isn't text, so it can't be shortened",
+                            filename
+                        ),
+                    );
+                    return;
+                }
+                Err(e) => Err(e),
+            },
+        }
+    } else if let Some(pages) = &pdf_pages {
+        fs::write(&dest_path, extract::join_pages(pages, usize::MAX))
+    } else {
+        fs::copy(path, &dest_path).map(|_| ())
+    };
+    match copied {
+        Ok(()) => {
+            db::add_attachment(&s.db.get(), &session_id, &filename, &dest_path);
+            s.attachments.retain(|(name, _)| *name != filename);
+            s.attachments.push((filename.clone(), dest_path));
+            db::touch_recent_file(&s.db.get(), path, &filename);
+            let chips = attachment_chips(s);
+            let notice = if oversized {
+                format!(
+                    "{} was shortened to {} KB",
+                    filename,
+                    limits.max_file / 1024
+                )
+            } else {
+                String::new()
+            };
+            let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+                show_attachment_chips(&ui, chips);
+                ui.set_attachment_notice(notice.into());
+            });
+            refresh_recent_files(ui_weak, s);
+        }
+        Err(err) => eprintln!("Error copying attachment: {}", err),
+    }
+}
+
+fn refresh_library(ui_weak: &slint::Weak<AppWindow>, s: &AppState) {
+    let files: Vec<LibraryFile> = db::attachment_library(&s.db.get(), &s.current_session_id)
+        .into_iter()
+        .map(|(name, path, active, pinned)| LibraryFile {
+            size: fs::metadata(&path)
+                .map(|m| format_bytes(m.len()))
+                .unwrap_or_else(|_| "missing".into())
+                .into(),
+            name: name.into(),
+            active,
+            pinned,
+        })
+        .collect();
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_library_files(Rc::new(VecModel::from(files)).into());
+    });
+}
 
-        let model_name = u_send
-            .upgrade()
-            .map(|ui| ui.get_selected_model().to_string())
-            .unwrap_or_else(|| "llama3".into());
-        let o_client = o_send.clone();
+/// Rows rewritten per database lock, so a long first pass doesn't stall
+/// chatting
+const REPACK_BATCH: usize = 200;
 
-        let mut history_for_ai = s.chat_history.clone();
-        let mut prompt_with_context = String::new();
-        if !s.attachments.is_empty() {
-            prompt_with_context.push_str("Context from files:\n");
-            for (name, path) in &s.attachments {
-                if let Ok(content) = fs::read_to_string(path) {
-                    prompt_with_context.push_str(&format!("[{}]\n{}\n", name, content));
+/// Compresses the stored history in the background, or restores it to plain
+/// text when compression was switched off.
+fn repack_history(history_db: db::Db, compress: bool) {
+    tokio::task::spawn_blocking(move || {
+        let mut total = 0;
+        loop {
+            let changed = db::repack_history(&history_db.get(), compress, REPACK_BATCH);
+            total += changed;
+            if changed == 0 {
+                if total > 0 {
+                    let _ = history_db.get().execute_batch("PRAGMA incremental_vacuum;");
                 }
+                break;
             }
         }
-        prompt_with_context.push_str(&raw_input);
+    });
+}
+
+fn show_attachment_notice(ui_weak: &slint::Weak<AppWindow>, notice: String) {
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_attachment_notice(notice.into());
+    });
+}
+
+/// What the attachment strip shows for one file. Thumbnails can't cross
+/// threads, so images are only loaded by `show_attachment_chips`.
+struct ChipInfo {
+    name: String,
+    truncated: bool,
+    pinned: bool,
+    image: Option<PathBuf>,
+}
+
+fn attachment_chips(s: &AppState) -> Vec<ChipInfo> {
+    s.attachments
+        .iter()
+        .map(|(name, path)| ChipInfo {
+            name: name.clone(),
+            truncated: extract::is_truncated(path),
+            pinned: s.pinned_attachments.contains(name),
+            image: extract::is_image(path).then(|| path.clone()),
+        })
+        .collect()
+}
+
+fn show_attachment_chips(ui: &AppWindow, chips: Vec<ChipInfo>) {
+    let chips: Vec<AttachmentChip> = chips
+        .into_iter()
+        .map(|chip| AttachmentChip {
+            name: chip.name.into(),
+            truncated: chip.truncated,
+            pinned: chip.pinned,
+            is_image: chip.image.is_some(),
+            thumbnail: chip
+                .image
+                .and_then(|path| slint::Image::load_from_path(&path).ok())
+                .unwrap_or_default(),
+        })
+        .collect();
+    ui.set_attachment_list(Rc::new(VecModel::from(chips)).into());
+}
+
+fn refresh_recent_files(ui_weak: &slint::Weak<AppWindow>, s: &AppState) {
+    let entries: Vec<RecentFile> = db::recent_files(&s.db.get(), RECENT_FILES_LIMIT)
+        .into_iter()
+        .map(|(path, name)| RecentFile {
+            path: path.into(),
+            name: name.into(),
+        })
+        .collect();
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_recent_files(Rc::new(VecModel::from(entries)).into());
+    });
+}
+
+/// Appends messages to today's file in the configured journal folder. Does
+/// nothing until a folder has been chosen.
+fn append_to_journal(s: &AppState, session_id: &str, messages: &[ChatMessage]) {
+    if let Some(dir) = s.config["journal_dir"].as_str().filter(|d| !d.is_empty()) {
+        write_journal(&s.db.get(), dir, session_id, messages);
+    }
+}
+
+fn write_journal(conn: &Connection, dir: &str, session_id: &str, messages: &[ChatMessage]) {
+    let title: String = conn
+        .query_row(
+            "SELECT title FROM sessions WHERE id = ?1",
+            params![session_id],
+            |row| row.get(0),
+        )
+        .unwrap_or_else(|_| "New chat".into());
+    let link = instance::session_link(session_id, None);
+    let entry = journal::format_entry(conn, &title, &link, messages);
+    if let Err(e) = journal::append(conn, Path::new(dir), &entry) {
+        eprintln!("Failed to write journal: {}", e);
+    }
+}
+
+/// The ticked bubbles of the open session as Markdown, in transcript order.
+fn selected_transcript(ui: &AppWindow, s: &AppState) -> Option<String> {
+    let messages: Vec<ChatMessage> = ui
+        .get_chat_messages()
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| m.selected)
+        .map(|(i, m)| {
+            let content = match s.chat_history.get(i) {
+                Some(full) if m.elided => full.content.clone(),
+                _ => m.content.to_string(),
+            };
+            if m.role == "User" {
+                ChatMessage::user(content)
+            } else {
+                ChatMessage::assistant(content)
+            }
+        })
+        .collect();
+    if messages.is_empty() {
+        return None;
+    }
+    let title: String =
+        s.db.get()
+            .query_row(
+                "SELECT title FROM sessions WHERE id = ?1",
+                params![s.current_session_id],
+                |row| row.get(0),
+            )
+            .unwrap_or_else(|_| "New chat".into());
+    let link = instance::session_link(&s.current_session_id, None);
+    Some(journal::format_entry(&s.db.get(), &title, &link, &messages))
+}
+
+/// Case-insensitive occurrences of `needle` per message, plus the message
+/// index of every occurrence in order for next/previous navigation.
+fn search_transcript(history: &[ChatMessage], needle: &str) -> (Vec<i32>, Vec<i32>) {
+    let needle = needle.trim().to_lowercase();
+    if needle.is_empty() {
+        return (Vec::new(), Vec::new());
+    }
+    let mut counts = Vec::with_capacity(history.len());
+    let mut hits = Vec::new();
+    for (i, m) in history.iter().enumerate() {
+        let count = m.content.to_lowercase().matches(&needle).count();
+        counts.push(count as i32);
+        hits.extend(std::iter::repeat(i as i32).take(count));
+    }
+    (counts, hits)
+}
 
-        if let Some(last_msg) = history_for_ai.last_mut() {
-            last_msg.content = prompt_with_context;
+/// Leaves selection mode and unticks every bubble.
+fn end_selection(ui: &AppWindow) {
+    let model = ui.get_chat_messages();
+    for i in 0..model.row_count() {
+        if let Some(mut row) = model.row_data(i).filter(|m| m.selected) {
+            row.selected = false;
+            model.set_row_data(i, row);
         }
+    }
+    ui.set_selecting(false);
+}
 
-        let history_for_ui = s.chat_history.clone();
-        let inner_u = u_send.clone();
-        let inner_s = s_send.clone();
+/// Answers `prompt` with `model` and types the reply into the focused
+/// window as it streams in, for the hotkey that works from any app. Nothing
+/// of it is kept in the history.
+async fn dictate(
+    backend: Arc<dyn ChatBackend>,
+    model: String,
+    prompt: String,
+    cancel: &CancellationToken,
+) -> Result<(), String> {
+    let typist = dictation::Typist::start()?;
+    let messages = vec![
+        ChatMessage::system(DICTATION_SYSTEM_PROMPT.to_string()),
+        ChatMessage::user(prompt),
+    ];
+    let mut stream = backend
+        .chat_stream(model, messages, backend::SamplingOptions::default())
+        .await?;
+    loop {
+        let chunk = tokio::select! {
+            _ = cancel.cancelled() => {
+                typist.stop();
+                return Ok(());
+            }
+            chunk = stream.next() => chunk,
+        };
+        let Some(chunk) = chunk else {
+            return Ok(());
+        };
+        if !typist.type_text(&chunk?.content) {
+            return Err("the focused window stopped taking input".into());
+        }
+    }
+}
 
-        let _ = inner_u.upgrade_in_event_loop(move |ui| {
-            update_ui_model(&ui, &history_for_ui);
-        });
+/// The app's window is usually in the background while dictating, so
+/// problems are reported as a system notification rather than a toast.
+fn notify_dictation(body: &str) {
+    if let Err(e) = notify_rust::Notification::new()
+        .summary("Ollama Native")
+        .body(body)
+        .show()
+    {
+        eprintln!("Failed to show notification: {}", e);
+    }
+}
 
-        tokio::spawn(async move {
-            let req = ChatMessageRequest::new(model_name, history_for_ai);
-            if let Ok(mut stream) = o_client.send_chat_messages_stream(req).await {
-                let mut full_response = String::new();
+/// Text selected in another app: the primary selection on Linux, which
+/// needs no copying first, and the clipboard everywhere else.
+fn selected_text(clipboard: &mut arboard::Clipboard) -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        use arboard::{GetExtLinux, LinuxClipboardKind};
+        if let Ok(text) = clipboard
+            .get()
+            .clipboard(LinuxClipboardKind::Primary)
+            .text()
+        {
+            return Some(text);
+        }
+    }
+    clipboard.get_text().ok()
+}
 
-                let _ = inner_u.upgrade_in_event_loop(|ui| {
-                    let model = ui.get_chat_messages();
-                    if let Some(vec_model) =
-                        model.as_any().downcast_ref::<VecModel<ChatMessageData>>()
-                    {
-                        vec_model.push(ChatMessageData {
-                            role: "AI".into(),
-                            content: "".into(),
-                        });
-                    }
-                });
+/// Returns whether the text made it onto the clipboard.
+fn copy_to_clipboard(s: &mut AppState, text: &str) -> bool {
+    let Some(clipboard) = s.clipboard.as_mut() else {
+        return false;
+    };
+    match clipboard.set_text(text) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Failed to copy: {}", e);
+            false
+        }
+    }
+}
 
-                while let Some(Ok(res)) = stream.next().await {
-                    let chunk = res.message.content;
-                    full_response.push_str(&chunk);
-                    let current_text: SharedString = full_response.clone().into();
+/// Shows `text` over the window for `TOAST_DURATION`, unless another toast
+/// replaces it first.
+fn show_toast(ui_weak: &slint::Weak<AppWindow>, text: &'static str) {
+    show_toast_for(ui_weak, text, false, TOAST_DURATION);
+}
 
-                    let _ = inner_u.upgrade_in_event_loop(move |ui| {
-                        let model = ui.get_chat_messages();
-                        if let Some(vec_model) =
-                            model.as_any().downcast_ref::<VecModel<ChatMessageData>>()
-                        {
-                            let row_idx = vec_model.row_count() - 1;
-                            vec_model.set_row_data(
-                                row_idx,
-                                ChatMessageData {
-                                    role: "AI".into(),
-                                    content: current_text,
-                                },
-                            );
-                        }
-                    });
-                }
+/// A toast with an Undo button that takes back the latest deletion.
+fn show_undo_toast(ui_weak: &slint::Weak<AppWindow>, text: &'static str) {
+    show_toast_for(ui_weak, text, true, UNDO_TOAST_DURATION);
+}
 
-                let mut s_final = inner_s.lock().unwrap();
-                s_final
-                    .chat_history
-                    .push(ChatMessage::assistant(full_response.clone()));
-                let _ = s_final.db.execute(
-                    "INSERT INTO messages (session_id, role, content) VALUES (?1, 'assistant', ?2)",
-                    params![session_id, full_response],
-                );
-                refresh_history(&inner_u, &s_final.db);
+fn show_toast_for(
+    ui_weak: &slint::Weak<AppWindow>,
+    text: &'static str,
+    undo: bool,
+    duration: Duration,
+) {
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_toast(text.into());
+        ui.set_toast_undo(undo);
+    });
+    let ui_weak = ui_weak.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(duration).await;
+        let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+            if ui.get_toast() == text {
+                ui.set_toast("".into());
+                ui.set_toast_undo(false);
             }
         });
     });
+}
 
-    ui.run()
+/// Takes back the latest message delete, retry, edit, session delete or
+/// clear, showing what it brought back.
+fn undo_last(state: &Arc<Mutex<AppState>>, ui_weak: &slint::Weak<AppWindow>) {
+    let mut s = state.lock().unwrap();
+    let Some(step) = s.undo.pop() else {
+        show_toast(ui_weak, "Nothing to undo");
+        return;
+    };
+    let _ = ui_weak.upgrade_in_event_loop(|ui| {
+        ui.set_toast("".into());
+        ui.set_toast_undo(false);
+    });
+    let reopen = match step {
+        UndoStep::Message { session_id, row_id } => {
+            db::restore_message(&s.db.get(), row_id);
+            refresh_history(ui_weak, &s);
+            if s.current_session_id == session_id {
+                reload_current_session(ui_weak, &mut s);
+                refresh_message_labels(ui_weak, &s);
+                None
+            } else {
+                Some(session_id)
+            }
+        }
+        UndoStep::Turns {
+            session_id,
+            row_ids,
+            edited,
+        } => {
+            // The turns come back after the new reply, so it has to be done
+            if s.generating.contains(&session_id) {
+                s.undo.push(UndoStep::Turns {
+                    session_id,
+                    row_ids,
+                    edited,
+                });
+                show_toast(ui_weak, "Wait for the reply to finish");
+                return;
+            }
+            if let Some(last) = row_ids.iter().max().filter(|_| edited) {
+                db::delete_messages_from(&s.db.get(), &session_id, last + 1);
+            }
+            for row_id in row_ids {
+                db::restore_message(&s.db.get(), row_id);
+            }
+            refresh_history(ui_weak, &s);
+            if s.current_session_id == session_id {
+                reload_current_session(ui_weak, &mut s);
+                refresh_message_labels(ui_weak, &s);
+                None
+            } else {
+                Some(session_id)
+            }
+        }
+        UndoStep::Session { id, was_open } => {
+            db::restore_session(&s.db.get(), &id);
+            refresh_history(ui_weak, &s);
+            was_open.then_some(id)
+        }
+        UndoStep::Clear(id) => Some(id),
+    };
+    drop(s);
+    if let Some(id) = reopen {
+        let _ = ui_weak.upgrade_in_event_loop(move |ui| ui.invoke_load_session(id.into()));
+    }
 }
 
-fn update_ui_model(ui: &AppWindow, history: &[ChatMessage]) {
-    let ui_messages: Vec<ChatMessageData> = history
+fn refresh_conflicts(ui_weak: &slint::Weak<AppWindow>, s: &AppState) {
+    let entries: Vec<ConflictEntry> = sync::conflicts(&s.db.get())
+        .into_iter()
+        .map(|c| ConflictEntry {
+            session_id: c.session_id.into(),
+            title: c.title.into(),
+            local_updated: c.local_updated.into(),
+            remote_updated: c.remote_updated.into(),
+        })
+        .collect();
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_sync_conflicts(Rc::new(VecModel::from(entries)).into());
+    });
+}
+
+/// The palette's rows for `query`: the fixed commands plus one entry per
+/// model and attachment preset, best fuzzy matches first.
+fn palette_entries(ui: &AppWindow, presets: &[(i64, String)], query: &str) -> Vec<PaletteEntry> {
+    let mut entries: Vec<(String, String, String)> = commands::COMMANDS
         .iter()
-        .map(|m| ChatMessageData {
-            role: if m.role == ollama_rs::generation::chat::MessageRole::User {
-                "User".into()
+        .map(|c| {
+            (
+                c.id.to_string(),
+                c.label.to_string(),
+                c.shortcut.unwrap_or("").to_string(),
+            )
+        })
+        .collect();
+    for model in ui.get_model_list().iter() {
+        entries.push((
+            format!("model:{}", model),
+            format!("Switch model: {}", model),
+            String::new(),
+        ));
+    }
+    for template in ui.get_template_list().iter() {
+        entries.push((
+            format!("template:{}", template.id),
+            format!("New chat from: {}", template.name),
+            String::new(),
+        ));
+    }
+    for (id, name) in presets {
+        entries.push((
+            format!("preset:{}", id),
+            format!("Attach preset: {}", name),
+            String::new(),
+        ));
+    }
+
+    let mut scored: Vec<(i32, (String, String, String))> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            if query.trim().is_empty() {
+                Some((0, entry))
             } else {
-                "AI".into()
-            },
-            content: m.content.clone().into(),
+                commands::fuzzy_score(query, &entry.1).map(|score| (score, entry))
+            }
         })
         .collect();
-    ui.set_chat_messages(Rc::new(VecModel::from(ui_messages)).into());
+    scored.sort_by(|a, b| b.0.cmp(&a.0));
+    scored
+        .into_iter()
+        .map(|(_, (id, label, shortcut))| PaletteEntry {
+            id: id.into(),
+            label: label.into(),
+            shortcut: shortcut.into(),
+        })
+        .collect()
 }
 
-fn refresh_history(ui_weak: &slint::Weak<AppWindow>, db: &Connection) {
-    let mut stmt = db
-        .prepare("SELECT id, title FROM sessions ORDER BY created_at DESC")
-        .unwrap();
-    let history_items: Vec<HistoryEntry> = stmt
-        .query_map([], |row| {
-            Ok(HistoryEntry {
-                id: row.get::<usize, String>(0).unwrap().into(),
-                title: row.get::<usize, String>(1).unwrap().into(),
-            })
+/// Runs a palette entry or shortcut. Runs on the UI thread, so it only
+/// flips UI state or invokes the callback the equivalent button would.
+fn run_command(ui: &AppWindow, id: &str) {
+    match id {
+        "palette" => {
+            ui.set_palette_query("".into());
+            ui.invoke_filter_palette("".into());
+            ui.set_palette_open(true);
+        }
+        "new_chat" => ui.invoke_clear_chat(),
+        "undo" => ui.invoke_undo(),
+        "attach" => ui.invoke_pick_attachment(),
+        "files" => ui.invoke_open_library(),
+        "settings" => {
+            ui.set_sidebar_expanded(true);
+            ui.set_settings_expanded(true);
+        }
+        "stop" => ui.invoke_stop_generation(),
+        "lock" => ui.invoke_set_session_locked(!ui.get_session_locked()),
+        "summarize" => ui.invoke_summarize_session(),
+        "info" => ui.invoke_show_session_info(),
+        "journal" => ui.invoke_journal_session(),
+        "import_chats" => ui.invoke_import_sessions(),
+        "export_settings" => ui.invoke_export_settings(),
+        "stats" => ui.invoke_open_stats(),
+        "storage" => ui.invoke_open_storage(),
+        "tools" => ui.set_tools_open(true),
+        "share" => ui.invoke_share_session(),
+        "export_markdown" => ui.invoke_export_session("markdown".into()),
+        "export_json" => ui.invoke_export_session("json".into()),
+        "open_database" => ui.invoke_open_archive(),
+        "open_shared" => ui.invoke_open_shared_chat(),
+        "copy_link" => ui.invoke_copy_link(-1),
+        "register_links" => ui.invoke_register_link_handler(),
+        "templates" => {
+            ui.invoke_new_template();
+            ui.set_templates_open(true);
+        }
+        "prompts" => {
+            ui.invoke_new_prompt();
+            ui.set_prompts_open(true);
+        }
+        "pull_model" => ui.set_pull_open(true),
+        "models" => ui.invoke_open_models(),
+        other => {
+            if let Some(model) = other.strip_prefix("model:") {
+                ui.set_selected_model(model.into());
+                ui.invoke_model_selected(model.into());
+            } else if let Some(preset) = other.strip_prefix("preset:").and_then(|p| p.parse().ok())
+            {
+                ui.invoke_apply_preset(preset);
+            } else if let Some(template) =
+                other.strip_prefix("template:").and_then(|t| t.parse().ok())
+            {
+                ui.invoke_start_from_template(template);
+            }
+        }
+    }
+}
+
+/// The session title made safe to use as a file name.
+fn session_file_name(s: &AppState, session_id: &str) -> String {
+    let title: String =
+        s.db.get()
+            .query_row(
+                "SELECT title FROM sessions WHERE id = ?1",
+                params![session_id],
+                |row| row.get(0),
+            )
+            .unwrap_or_default();
+    let name: String = title
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == ' ' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    match name.trim() {
+        "" => "chat".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Brings the window forward with what a launch passed: an
+/// `ollama-native://` link opens its session, a shared chat file is
+/// imported.
+fn open_launch_arg(state: &Arc<Mutex<AppState>>, ui_weak: &slint::Weak<AppWindow>, arg: String) {
+    let _ = ui_weak.upgrade_in_event_loop(|ui| {
+        let _ = ui.show();
+    });
+    if let Some((session_id, message_id)) = instance::parse_link(&arg) {
+        let exists = {
+            let mut s = state.lock().unwrap();
+            s.jump_to_message = message_id;
+            db::session_exists(&s.db.get(), &session_id)
+        };
+        if !exists {
+            show_toast(ui_weak, "That chat no longer exists");
+            return;
+        }
+        let _ = ui_weak.upgrade_in_event_loop(move |ui| ui.invoke_load_session(session_id.into()));
+        return;
+    }
+    let path = PathBuf::from(arg);
+    if path.extension().is_some_and(|e| e == share::EXTENSION) {
+        let state = state.clone();
+        let ui_weak = ui_weak.clone();
+        tokio::task::spawn_blocking(move || open_shared_file(&state, &ui_weak, &path));
+    }
+}
+
+/// Imports a shared chat file and opens the session it becomes.
+fn open_shared_file(state: &Arc<Mutex<AppState>>, ui_weak: &slint::Weak<AppWindow>, path: &Path) {
+    let result = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))
+        .and_then(|bundle: serde_json::Value| {
+            let s = state.lock().unwrap();
+            let id = share::import(&s.db.get(), &bundle)?;
+            refresh_history(ui_weak, &s);
+            Ok(id)
+        });
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| match result {
+        Ok(id) => ui.invoke_load_session(id.into()),
+        Err(e) => ui.set_settings_status(format!("Couldn't open shared chat: {}", e).into()),
+    });
+}
+
+/// Lists the chats of the open archive, with nothing ticked or previewed.
+fn refresh_archive(ui_weak: &slint::Weak<AppWindow>, s: &AppState) {
+    let Some(archive) = &s.archive else {
+        return;
+    };
+    let name = archive.name.clone();
+    let entries: Vec<ArchiveEntry> = archive
+        .sessions()
+        .into_iter()
+        .map(|session| ArchiveEntry {
+            id: session.id.into(),
+            title: session.title.into(),
+            updated: session.updated.into(),
+            messages: session.messages as i32,
+            selected: false,
+        })
+        .collect();
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_archive_name(name.into());
+        ui.set_archive_sessions(Rc::new(VecModel::from(entries)).into());
+        ui.set_archive_preview_id("".into());
+        ui.set_archive_preview(Rc::new(VecModel::from(Vec::<ArchiveMessage>::new())).into());
+        ui.set_archive_status("".into());
+        ui.set_archive_open(true);
+    });
+}
+
+fn refresh_import_conflicts(ui_weak: &slint::Weak<AppWindow>, s: &AppState) {
+    let entries: Vec<ImportConflictEntry> = s
+        .import_conflicts
+        .iter()
+        .map(|c| {
+            let existing_title: String =
+                s.db.get()
+                    .query_row(
+                        "SELECT title FROM sessions WHERE id = ?1",
+                        params![c.existing_id],
+                        |row| row.get(0),
+                    )
+                    .unwrap_or_default();
+            ImportConflictEntry {
+                title: c.session["title"].as_str().unwrap_or("").into(),
+                existing_title: existing_title.into(),
+                messages: c.session["messages"].as_array().map_or(0, |m| m.len()) as i32,
+                identical: c.identical,
+            }
+        })
+        .collect();
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_import_conflicts_open(!entries.is_empty());
+        ui.set_import_conflicts(Rc::new(VecModel::from(entries)).into());
+    });
+}
+
+/// Re-reads the open session after its rows were replaced underneath it,
+/// unless a reply is streaming into it.
+fn reload_current_session(ui_weak: &slint::Weak<AppWindow>, s: &mut AppState) {
+    if s.is_generating() {
+        return;
+    }
+    s.chat_history = db::load_messages(&s.db.get(), &s.current_session_id)
+        .into_iter()
+        .map(|m| m.message)
+        .collect();
+    let history = s.chat_history.clone();
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        update_ui_model(&ui, &history);
+    });
+}
+
+/// Cards shown on an empty chat: the configured `starter_prompts` (or the
+/// defaults), the openers of recent sessions, and an attach-and-ask card.
+fn refresh_starters(ui_weak: &slint::Weak<AppWindow>, s: &AppState) {
+    let configured: Vec<String> = match s.config["starter_prompts"].as_array() {
+        Some(prompts) => prompts
+            .iter()
+            .filter_map(|p| p.as_str())
+            .map(str::to_string)
+            .collect(),
+        None => DEFAULT_STARTER_PROMPTS
+            .iter()
+            .map(|p| p.to_string())
+            .collect(),
+    };
+    let mut cards: Vec<StarterCard> = configured
+        .into_iter()
+        .map(|prompt| StarterCard {
+            label: "Suggested".into(),
+            prompt: prompt.into(),
+            attach: false,
         })
+        .collect();
+
+    let conn = s.db.get();
+    let mut stmt = conn
+        .prepare(
+            "SELECT unpack(COALESCE(b.content, m.content)) FROM sessions s JOIN messages m ON m.rowid =
+                 (SELECT MIN(rowid) FROM messages WHERE session_id = s.id AND role = 'user' AND deleted = 0)
+             LEFT JOIN blobs b ON b.hash = m.blob
+             WHERE s.deleted = 0
+             ORDER BY s.created_at DESC LIMIT ?1",
+        )
+        .unwrap();
+    let recent: Vec<String> = stmt
+        .query_map(params![STARTER_RECENT_LIMIT as i64], |row| row.get(0))
         .unwrap()
-        .map(|r| r.unwrap())
+        .flatten()
+        .collect();
+    cards.extend(recent.into_iter().map(|prompt| StarterCard {
+        label: "Recent".into(),
+        prompt: prompt.into(),
+        attach: false,
+    }));
+    cards.push(StarterCard {
+        label: "Attach a file and ask…".into(),
+        prompt: "Summarize the attached file and list anything that needs attention.".into(),
+        attach: true,
+    });
+
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_starter_cards(Rc::new(VecModel::from(cards)).into());
+    });
+}
+
+fn refresh_schedules(ui_weak: &slint::Weak<AppWindow>, s: &AppState) {
+    let conn = s.db.get();
+    let entries: Vec<ScheduleEntry> = schedule::load_schedules(&conn)
+        .into_iter()
+        .map(|job| {
+            let session_title: String = conn
+                .query_row(
+                    "SELECT title FROM sessions WHERE id = ?1",
+                    params![job.session_id],
+                    |row| row.get(0),
+                )
+                .unwrap_or_else(|_| "New chat".into());
+            ScheduleEntry {
+                id: job.id as i32,
+                time: job.time.into(),
+                prompt: job.prompt.into(),
+                session_title: session_title.into(),
+                file: job
+                    .attachment
+                    .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+                    .unwrap_or_default()
+                    .into(),
+            }
+        })
+        .collect();
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_schedule_list(Rc::new(VecModel::from(entries)).into());
+    });
+}
+
+fn refresh_presets(ui_weak: &slint::Weak<AppWindow>, s: &AppState) {
+    let entries: Vec<PresetEntry> = s
+        .presets
+        .iter()
+        .map(|p| PresetEntry {
+            id: p.id as i32,
+            name: p.name.clone().into(),
+            file_count: p.files.len() as i32,
+            auto_attach: p.auto_attach,
+        })
+        .collect();
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_preset_list(Rc::new(VecModel::from(entries)).into());
+    });
+}
+
+fn options_form(options: &backend::SamplingOptions) -> GenOptionsForm {
+    fn field<T: ToString>(value: Option<T>) -> SharedString {
+        value.map(|v| v.to_string()).unwrap_or_default().into()
+    }
+    GenOptionsForm {
+        temperature: field(options.temperature),
+        top_p: field(options.top_p),
+        top_k: field(options.top_k),
+        repeat_penalty: field(options.repeat_penalty),
+        num_ctx: field(options.num_ctx),
+        seed: field(options.seed),
+        overflow: options.context_overflow.clone().unwrap_or_default().into(),
+    }
+}
+
+/// Blank or unparsable fields fall back to the model's default.
+fn parse_options_form(form: &GenOptionsForm) -> backend::SamplingOptions {
+    fn field<T: std::str::FromStr>(text: &SharedString) -> Option<T> {
+        text.trim().parse().ok()
+    }
+    backend::SamplingOptions {
+        temperature: field(&form.temperature),
+        top_p: field(&form.top_p),
+        top_k: field(&form.top_k),
+        repeat_penalty: field(&form.repeat_penalty),
+        num_ctx: field(&form.num_ctx),
+        seed: field(&form.seed),
+        context_overflow: Some(form.overflow.to_string()).filter(|o| !o.is_empty() && o != "trim"),
+    }
+}
+
+/// Metadata lines and used-context notes under the displayed session's
+/// messages.
+struct MessageLabels {
+    labels: Vec<String>,
+    contexts: Vec<String>,
+    variants: Vec<(usize, usize, String)>,
+    excluded: Vec<bool>,
+}
+
+fn load_message_labels(conn: &Connection, session_id: &str) -> MessageLabels {
+    MessageLabels {
+        labels: stats::message_labels(conn, session_id),
+        contexts: stats::message_contexts(conn, session_id),
+        variants: variants::session_variants(conn, session_id),
+        excluded: db::excluded_messages(conn, session_id),
+    }
+}
+
+fn show_message_labels(ui_weak: &slint::Weak<AppWindow>, labels: MessageLabels) {
+    let MessageLabels {
+        labels,
+        contexts,
+        variants,
+        excluded,
+    } = labels;
+    let labels: Vec<SharedString> = labels.into_iter().map(Into::into).collect();
+    let contexts: Vec<SharedString> = contexts.into_iter().map(Into::into).collect();
+    let variants: Vec<VariantInfo> = variants
+        .into_iter()
+        .map(|(current, count, model)| VariantInfo {
+            current: current as i32,
+            count: count as i32,
+            model: model.into(),
+        })
+        .collect();
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_message_labels(Rc::new(VecModel::from(labels)).into());
+        ui.set_message_excluded(Rc::new(VecModel::from(excluded)).into());
+        ui.set_message_contexts(Rc::new(VecModel::from(contexts)).into());
+        ui.set_message_variants(Rc::new(VecModel::from(variants)).into());
+    });
+}
+
+fn refresh_message_labels(ui_weak: &slint::Weak<AppWindow>, s: &AppState) {
+    let labels = load_message_labels(&s.db.get(), &s.current_session_id);
+    show_message_labels(ui_weak, labels);
+}
+
+fn refresh_templates(ui_weak: &slint::Weak<AppWindow>, s: &AppState) {
+    let entries: Vec<TemplateEntry> = templates::load_templates(&s.db.get())
+        .into_iter()
+        .map(|t| TemplateEntry {
+            id: t.id as i32,
+            name: t.name.into(),
+            turns: t.turns.len() as i32,
+        })
+        .collect();
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_template_list(Rc::new(VecModel::from(entries)).into());
+    });
+}
+
+fn refresh_prompts(ui_weak: &slint::Weak<AppWindow>, s: &AppState) {
+    let entries: Vec<PromptEntry> = prompts::load_prompts(&s.db.get())
+        .into_iter()
+        .map(|p| PromptEntry {
+            id: p.id as i32,
+            name: p.name.into(),
+            content: p.content.into(),
+        })
+        .collect();
+    let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+        ui.set_prompt_list(Rc::new(VecModel::from(entries)).into());
+    });
+}
+
+fn show_template_form(ui: &AppWindow, template: &templates::Template) {
+    let turns: Vec<TemplateTurnData> = template
+        .turns
+        .iter()
+        .map(|t| TemplateTurnData {
+            role: t.role.clone().into(),
+            content: t.content.clone().into(),
+        })
+        .collect();
+    ui.set_template_form_id(template.id as i32);
+    ui.set_template_form_name(template.name.clone().into());
+    ui.set_template_form_system(template.system_prompt.clone().into());
+    ui.set_template_form_turns(Rc::new(VecModel::from(turns)).into());
+}
+
+fn refresh_tools(ui: &AppWindow, tool_defs: &[tools::ToolDef]) {
+    let entries: Vec<ToolEntry> = tool_defs
+        .iter()
+        .map(|t| ToolEntry {
+            id: t.id as i32,
+            name: t.name.clone().into(),
+            description: t.description.clone().into(),
+            kind: t.kind.clone().into(),
+            template: t.template.clone().into(),
+            enabled: t.enabled,
+        })
         .collect();
+    ui.set_tool_list(Rc::new(VecModel::from(entries)).into());
+}
 
+fn init_db(db: &Connection) {
+    db::init(db);
+    tools::init_table(db);
+    templates::init_table(db);
+    prompts::init_table(db);
+    stats::init_table(db);
+    presets::init_table(db);
+    extract::init_table(db);
+    schedule::init_table(db);
+    reminders::init_table(db);
+    sync::init_table(db);
+    search::init_table(db);
+    variants::init_table(db);
+    health::init_table(db);
+    digest::init_table(db);
+    db::purge_deleted(db);
+}
+
+/// The backend settings as entered in the settings panel, in config form.
+fn backend_form(ui: &AppWindow) -> serde_json::Value {
+    serde_json::json!({
+        "backend": ui.get_backend_kind().to_string(),
+        "backend_url": ui.get_backend_url().to_string(),
+        "backend_api_key": ui.get_backend_api_key().to_string(),
+        "ollama_host": ui.get_ollama_host().trim().to_string(),
+        "ollama_port": ui.get_ollama_port().trim().parse::<u16>().unwrap_or(11434),
+        "ollama_token": ui.get_ollama_token().to_string(),
+    })
+}
+
+fn refresh_models(
+    state: &Arc<Mutex<AppState>>,
+    backend: Arc<dyn ChatBackend>,
+    ui_weak: &slint::Weak<AppWindow>,
+) {
+    let s_models = state.clone();
+    let u_models = ui_weak.clone();
+    tokio::spawn(async move {
+        match backend.list_models().await {
+            Ok(models) => {
+                let names: Vec<SharedString> = models.into_iter().map(|m| m.name.into()).collect();
+                let mut s = s_models.lock().unwrap();
+                if names.is_empty() {
+                    set_view_state(&u_models, &mut s, ViewState::NoModels);
+                } else {
+                    // Let the session decide what to show again
+                    s.view_state = ViewState::Chat;
+                    settle_view_state(&u_models, &mut s);
+                }
+                let _ = u_models.upgrade_in_event_loop(move |ui| {
+                    ui.set_model_list(Rc::new(VecModel::from(names)).into());
+                });
+            }
+            Err(e) => {
+                eprintln!("Failed to list models: {}", e);
+                crash::set_error(&e);
+                set_view_state(
+                    &u_models,
+                    &mut s_models.lock().unwrap(),
+                    ViewState::Offline(e),
+                );
+            }
+        }
+    });
+}
+
+fn set_view_state(ui_weak: &slint::Weak<AppWindow>, s: &mut AppState, view: ViewState) {
+    let key = view.key();
+    let detail = match &view {
+        ViewState::Offline(e) => e.clone(),
+        _ => String::new(),
+    };
+    s.view_state = view;
     let _ = ui_weak.upgrade_in_event_loop(move |ui| {
-        ui.set_history_list(Rc::new(VecModel::from(history_items)).into());
+        ui.set_view_state(key.into());
+        ui.set_view_detail(detail.into());
     });
 }
+
+/// Derives the state from the open session, unless the backend is
+/// unreachable or has no models.
+fn settle_view_state(ui_weak: &slint::Weak<AppWindow>, s: &mut AppState) {
+    if s.view_state.is_backend_problem() {
+        return;
+    }
+    let view = if s.chat_history.is_empty() && !s.is_generating() {
+        ViewState::EmptySession
+    } else {
+        ViewState::Chat
+    };
+    if view != s.view_state {
+        set_view_state(ui_weak, s, view);
+    }
+}
+
+/// Shows the config's values in the settings controls, at startup and after
+/// an import.
+fn apply_settings(ui: &AppWindow, cfg: &serde_json::Value) {
+    ui.set_default_model_setting(cfg["default_model"].as_str().unwrap_or("llama3").into());
+    ui.set_scroll_lock(cfg["scroll_lock"].as_bool().unwrap_or(true));
+    ui.set_backend_kind(cfg["backend"].as_str().unwrap_or("ollama").into());
+    ui.set_backend_url(cfg["backend_url"].as_str().unwrap_or("").into());
+    ui.set_backend_api_key(cfg["backend_api_key"].as_str().unwrap_or("").into());
+    ui.set_ollama_host(
+        cfg["ollama_host"]
+            .as_str()
+            .unwrap_or("http://localhost")
+            .into(),
+    );
+    ui.set_ollama_port(
+        cfg["ollama_port"]
+            .as_u64()
+            .unwrap_or(11434)
+            .to_string()
+            .into(),
+    );
+    ui.set_ollama_token(cfg["ollama_token"].as_str().unwrap_or("").into());
+
+    ui.set_model_icons(cfg["model_icons"].as_bool().unwrap_or(false));
+    ui.set_model_titles(cfg["model_titles"].as_bool().unwrap_or(true));
+    ui.set_compress_history(cfg["compress_history"].as_bool().unwrap_or(false));
+    ui.set_match_language(cfg["match_language"].as_bool().unwrap_or(false));
+    ui.set_enter_sends(cfg["enter_sends"].as_bool().unwrap_or(true));
+    ui.set_auto_fence(cfg["auto_fence"].as_bool().unwrap_or(true));
+    ui.set_keep_prompt(cfg["keep_prompt_on_send"].as_bool().unwrap_or(false));
+    ui.set_keep_attachments(cfg["keep_attachments_on_send"].as_bool().unwrap_or(false));
+    ui.set_preview_context(cfg["preview_context"].as_bool().unwrap_or(false));
+    ui.set_auto_copy(cfg["auto_copy"].as_bool().unwrap_or(false));
+    ui.set_attach_max_kb(cfg["attach_max_kb"].as_i64().unwrap_or(256) as i32);
+    ui.set_attach_total_kb(cfg["attach_total_kb"].as_i64().unwrap_or(1024) as i32);
+    ui.set_attach_truncate(cfg["attach_truncate"].as_str().unwrap_or("head").into());
+    ui.set_attachment_format(cfg["attachment_format"].as_str().unwrap_or("plain").into());
+    ui.set_attachment_template(
+        cfg["attachment_template"]
+            .as_str()
+            .unwrap_or("--- {name} ---\n{content}")
+            .into(),
+    );
+    ui.set_long_reply_words(cfg["long_reply_words"].as_i64().unwrap_or(800) as i32);
+    ui.set_warm_up_on_select(cfg["warm_up_on_select"].as_bool().unwrap_or(false));
+    ui.set_journal_dir(cfg["journal_dir"].as_str().unwrap_or("").into());
+    ui.set_starter_prompts(
+        match cfg["starter_prompts"].as_array() {
+            Some(prompts) => prompts
+                .iter()
+                .filter_map(|p| p.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            None => DEFAULT_STARTER_PROMPTS.join("\n"),
+        }
+        .into(),
+    );
+    ui.set_sync_kind(cfg["sync"]["kind"].as_str().unwrap_or("webdav").into());
+    ui.set_sync_url(cfg["sync"]["url"].as_str().unwrap_or("").into());
+    ui.set_sync_user(cfg["sync"]["user"].as_str().unwrap_or("").into());
+    ui.set_sync_secret(cfg["sync"]["secret"].as_str().unwrap_or("").into());
+    ui.set_sync_region(cfg["sync"]["region"].as_str().unwrap_or("").into());
+    ui.set_sync_passphrase(cfg["sync"]["passphrase"].as_str().unwrap_or("").into());
+    ui.set_journal_auto(cfg["journal_auto"].as_bool().unwrap_or(false));
+    ui.set_resume_with_summary(cfg["resume_with_summary"].as_bool().unwrap_or(false));
+    ui.set_auto_summarize(cfg["auto_summarize"].as_bool().unwrap_or(false));
+    ui.set_dictation(cfg["dictation"].as_bool().unwrap_or(false));
+    ui.set_dictation_hotkey(
+        cfg["dictation_hotkey"]
+            .as_str()
+            .unwrap_or(dictation::DEFAULT_HOTKEY)
+            .into(),
+    );
+    ui.set_max_concurrent(cfg["max_concurrent"].as_i64().unwrap_or(1) as i32);
+    apply_post_processing_settings(&ui, &cfg);
+    ui.set_stop_patterns(
+        cfg["stop_patterns"]
+            .as_array()
+            .map(|p| {
+                p.iter()
+                    .filter_map(|v| v.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .unwrap_or_default()
+            .into(),
+    );
+    ui.set_confirm_tokens(cfg["confirm_tokens"].as_i64().unwrap_or(8000) as i32);
+    ui.set_confirm_attachment_mb(cfg["confirm_attachment_mb"].as_i64().unwrap_or(5) as i32);
+}
+
+/// Mirrors the `post_processors` chain into the settings controls. Regex
+/// rules are shown one per line as `pattern => replacement`.
+fn apply_post_processing_settings(ui: &AppWindow, cfg: &serde_json::Value) {
+    let entries = cfg["post_processors"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let has = |kind: &str| entries.iter().any(|e| e["kind"] == kind);
+    let rules: Vec<String> = entries
+        .iter()
+        .filter(|e| e["kind"] == "regex")
+        .map(|e| {
+            format!(
+                "{} => {}",
+                e["pattern"].as_str().unwrap_or(""),
+                e["replace"].as_str().unwrap_or("")
+            )
+        })
+        .collect();
+    ui.set_pp_strip_think(has("strip_think"));
+    ui.set_pp_collapse(has("collapse_repeats"));
+    ui.set_pp_trim(has("trim"));
+    ui.set_pp_rules(rules.join("\n").into());
+}
+
+fn save_config(cfg: &serde_json::Value) {
+    if let Err(e) = confy::store("ollama-native", None, cfg) {
+        eprintln!("Failed to save config: {}", e);
+    }
+}
@@ -0,0 +1,312 @@
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
+
+use crate::highlight;
+
+/// Renders markdown as the text a reader would see: no emphasis markers or
+/// fences, list items as bullets and links as `text (url)`.
+pub fn to_plain_text(markdown: &str) -> String {
+    let mut out = String::new();
+    let mut link_urls: Vec<String> = Vec::new();
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Text(text) | Event::Code(text) | Event::Html(text) | Event::InlineHtml(text) => {
+                out.push_str(&text)
+            }
+            Event::SoftBreak | Event::HardBreak => out.push('\n'),
+            Event::Rule => out.push_str("\n\n"),
+            Event::Start(Tag::Item) => out.push_str("• "),
+            Event::Start(Tag::Link { dest_url, .. }) => link_urls.push(dest_url.to_string()),
+            Event::End(TagEnd::Link) => {
+                if let Some(url) = link_urls.pop() {
+                    out.push_str(&format!(" ({})", url));
+                }
+            }
+            Event::End(TagEnd::Item) => push_newlines(&mut out, 1),
+            Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::CodeBlock
+                | TagEnd::BlockQuote(_)
+                | TagEnd::List(_),
+            ) => push_newlines(&mut out, 2),
+            _ => {}
+        }
+    }
+    out.trim_end().to_string()
+}
+
+/// Endings and openings typical of source lines and rare in prose
+const CODE_LINE_ENDS: &[&str] = &[";", "{", "}", ")", "):", "=>", ","];
+const CODE_LINE_STARTS: &[&str] = &[
+    "fn ", "def ", "class ", "import ", "from ", "use ", "let ", "const ", "var ", "pub ",
+    "return ", "if (", "for (", "#include", "//", "#!",
+];
+
+/// Wraps pasted text in a code fence when it reads like source code: several
+/// lines, most of them indented or shaped like statements. Text that already
+/// has fences is left alone.
+pub fn fence_pasted_code(text: &str) -> Option<String> {
+    if text.contains("```") {
+        return None;
+    }
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    if lines.len() < 3 {
+        return None;
+    }
+    let code_like = lines
+        .iter()
+        .filter(|line| {
+            let trimmed = line.trim();
+            line.starts_with("    ")
+                || line.starts_with('\t')
+                || CODE_LINE_ENDS.iter().any(|end| trimmed.ends_with(end))
+                || CODE_LINE_STARTS
+                    .iter()
+                    .any(|start| trimmed.starts_with(start))
+        })
+        .count();
+    if code_like * 2 < lines.len() {
+        return None;
+    }
+    let lang = highlight::language_from_first_line(text).unwrap_or_default();
+    Some(format!("```{}\n{}\n```", lang, text.trim_end()))
+}
+
+/// Ends the text with at least `count` line breaks.
+fn push_newlines(out: &mut String, count: usize) {
+    let existing = out.chars().rev().take_while(|c| *c == '\n').count();
+    for _ in existing..count {
+        out.push('\n');
+    }
+}
+
+/// One block of rendered markdown: a paragraph, heading, list item, quote,
+/// code block or rule. Slint's `Text` styles all of its text at once, so
+/// emphasis is kept where it covers the whole block and otherwise only its
+/// markers are dropped.
+#[derive(Clone, Debug, Default)]
+pub struct Segment {
+    pub kind: &'static str,
+    pub text: String,
+    /// Bullet or number in front of a list item
+    pub marker: String,
+    /// Heading level, or nesting depth of a list item
+    pub level: usize,
+    pub bold: bool,
+    pub italic: bool,
+    pub mono: bool,
+    /// Language of a fenced code block, as written after the fence
+    pub lang: String,
+    /// Highlighted lines of a code block
+    pub code_lines: Vec<Vec<highlight::Span>>,
+}
+
+/// Splits markdown into styled blocks for the chat bubbles. Cheap enough to
+/// rerun on the whole reply each time more of it has streamed in; an
+/// unfinished block simply shows up as what it parses as so far.
+pub fn to_segments(markdown: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut current: Option<Segment> = None;
+    let (mut strong, mut emphasis, mut quotes, mut heading) = (0, 0, 0, 0);
+    let mut in_code = false;
+    // Next number of each open list, `None` for bullet lists
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut marker = String::new();
+    let mut lang = String::new();
+
+    fn flush(segments: &mut Vec<Segment>, current: &mut Option<Segment>) {
+        if let Some(mut segment) = current.take() {
+            segment.text = segment.text.trim_end().to_string();
+            if segment.kind == "code" {
+                segment.code_lines = highlight::lines(&segment.text, &segment.lang);
+            }
+            if !segment.text.is_empty() {
+                segments.push(segment);
+            }
+        }
+    }
+
+    for event in Parser::new(markdown) {
+        let (text, code) = match event {
+            Event::Text(text) | Event::Html(text) | Event::InlineHtml(text) => (text, false),
+            Event::Code(text) => (text, true),
+            Event::SoftBreak | Event::HardBreak => ("\n".into(), false),
+            Event::Start(Tag::Strong) => {
+                strong += 1;
+                continue;
+            }
+            Event::End(TagEnd::Strong) => {
+                strong -= 1;
+                continue;
+            }
+            Event::Start(Tag::Emphasis) => {
+                emphasis += 1;
+                continue;
+            }
+            Event::End(TagEnd::Emphasis) => {
+                emphasis -= 1;
+                continue;
+            }
+            Event::Start(Tag::Heading { level, .. }) => {
+                flush(&mut segments, &mut current);
+                heading = level as usize;
+                continue;
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                flush(&mut segments, &mut current);
+                heading = 0;
+                continue;
+            }
+            Event::Start(Tag::List(start)) => {
+                flush(&mut segments, &mut current);
+                lists.push(start);
+                continue;
+            }
+            Event::End(TagEnd::List(_)) => {
+                flush(&mut segments, &mut current);
+                lists.pop();
+                continue;
+            }
+            Event::Start(Tag::Item) => {
+                flush(&mut segments, &mut current);
+                marker = match lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}.", *n - 1)
+                    }
+                    _ => "•".to_string(),
+                };
+                continue;
+            }
+            Event::Start(Tag::BlockQuote(_)) => {
+                flush(&mut segments, &mut current);
+                quotes += 1;
+                continue;
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                flush(&mut segments, &mut current);
+                quotes -= 1;
+                continue;
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                flush(&mut segments, &mut current);
+                in_code = true;
+                lang = match kind {
+                    CodeBlockKind::Fenced(info) => info.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                continue;
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                flush(&mut segments, &mut current);
+                in_code = false;
+                continue;
+            }
+            Event::End(TagEnd::Paragraph | TagEnd::Item) => {
+                flush(&mut segments, &mut current);
+                continue;
+            }
+            Event::Rule => {
+                flush(&mut segments, &mut current);
+                segments.push(Segment {
+                    kind: "rule",
+                    ..Default::default()
+                });
+                continue;
+            }
+            _ => continue,
+        };
+
+        let segment = current.get_or_insert_with(|| {
+            let (kind, level) = if in_code {
+                ("code", 0)
+            } else if heading > 0 {
+                ("heading", heading)
+            } else if !lists.is_empty() {
+                ("item", lists.len())
+            } else if quotes > 0 {
+                ("quote", 0)
+            } else {
+                ("paragraph", 0)
+            };
+            Segment {
+                kind,
+                level,
+                // Only the first paragraph of an item carries its marker
+                marker: if kind == "item" {
+                    std::mem::take(&mut marker)
+                } else {
+                    String::new()
+                },
+                bold: true,
+                italic: true,
+                mono: true,
+                lang: if in_code { lang.clone() } else { String::new() },
+                ..Default::default()
+            }
+        });
+        // Whitespace between styled runs doesn't break up the block's style
+        if !text.trim().is_empty() {
+            segment.bold &= strong > 0;
+            segment.italic &= emphasis > 0;
+            segment.mono &= code || in_code;
+        }
+        segment.text.push_str(&text);
+    }
+    flush(&mut segments, &mut current);
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_drops_markup() {
+        assert_eq!(
+            to_plain_text("See **the** [docs](https://example.com).\n\n- one\n- two"),
+            "See the docs (https://example.com).\n\n• one\n• two"
+        );
+    }
+
+    #[test]
+    fn fences_code_but_not_prose() {
+        let code = "fn main() {\n    let x = 1;\n    println!(\"{}\", x);\n}";
+        let fenced = fence_pasted_code(code).unwrap();
+        assert!(fenced.starts_with("```"));
+        assert!(fenced.ends_with(&format!("{}\n```", code)));
+
+        let prose = "Dear team,\nthe build is green again\nthanks for the quick fixes";
+        assert_eq!(fence_pasted_code(prose), None);
+        assert_eq!(fence_pasted_code("```\na;\nb;\nc;\n```"), None);
+    }
+
+    #[test]
+    fn splits_blocks() {
+        let segments = to_segments(
+            "# Title\n\nsome **bold** text\n\n3. a\n4. b\n\n---\n\n```rust\nfn a() {}\n```",
+        );
+        let kinds: Vec<_> = segments.iter().map(|s| s.kind).collect();
+        assert_eq!(
+            kinds,
+            ["heading", "paragraph", "item", "item", "rule", "code"]
+        );
+        assert_eq!(segments[0].level, 1);
+        assert_eq!(segments[0].text, "Title");
+        assert_eq!(segments[1].text, "some bold text");
+        assert!(!segments[1].bold);
+        assert_eq!(segments[2].marker, "3.");
+        assert_eq!(segments[3].marker, "4.");
+        assert_eq!(segments[5].lang, "rust");
+        assert_eq!(segments[5].text, "fn a() {}");
+        assert!(segments[5].mono);
+    }
+
+    #[test]
+    fn keeps_emphasis_covering_the_block() {
+        let segments = to_segments("**all of it**");
+        assert_eq!(segments.len(), 1);
+        assert!(segments[0].bold);
+        assert!(!segments[0].italic);
+    }
+}
@@ -0,0 +1,159 @@
+use regex::Regex;
+
+/// One step of the chain applied to a finished reply before it is stored
+/// and sent back as context.
+pub enum Step {
+    StripThink,
+    Trim,
+    CollapseRepeats,
+    Replace(Regex, String),
+}
+
+/// Reads the `post_processors` config array, in order. Entries look like
+/// `{"kind": "strip_think"}` or
+/// `{"kind": "regex", "pattern": "...", "replace": "..."}`.
+pub fn from_config(cfg: &serde_json::Value) -> Vec<Step> {
+    let Some(entries) = cfg["post_processors"].as_array() else {
+        return Vec::new();
+    };
+    entries
+        .iter()
+        .filter_map(|entry| match entry["kind"].as_str()? {
+            "strip_think" => Some(Step::StripThink),
+            "trim" => Some(Step::Trim),
+            "collapse_repeats" => Some(Step::CollapseRepeats),
+            "regex" => {
+                let pattern = entry["pattern"].as_str()?;
+                match Regex::new(pattern) {
+                    Ok(re) => Some(Step::Replace(
+                        re,
+                        entry["replace"].as_str().unwrap_or("").to_string(),
+                    )),
+                    Err(e) => {
+                        eprintln!("Skipping post-processor /{}/: {}", pattern, e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Patterns from the `stop_patterns` config array. A match anywhere in the
+/// streamed reply ends the stream.
+pub fn stop_patterns(cfg: &serde_json::Value) -> Vec<Regex> {
+    cfg["stop_patterns"]
+        .as_array()
+        .map(|patterns| {
+            patterns
+                .iter()
+                .filter_map(|p| p.as_str())
+                .filter_map(|p| {
+                    Regex::new(p)
+                        .map_err(|e| eprintln!("Skipping stop pattern /{}/: {}", p, e))
+                        .ok()
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Position of the earliest stop pattern match, where the reply gets cut.
+pub fn find_stop(patterns: &[Regex], text: &str) -> Option<usize> {
+    patterns
+        .iter()
+        .filter_map(|re| re.find(text))
+        .map(|m| m.start())
+        .min()
+}
+
+pub fn apply(steps: &[Step], text: &str) -> String {
+    let mut out = text.to_string();
+    for step in steps {
+        out = match step {
+            Step::StripThink => strip_think(&out),
+            Step::Trim => out.trim().to_string(),
+            Step::CollapseRepeats => collapse_repeats(&out),
+            Step::Replace(re, replacement) => {
+                re.replace_all(&out, replacement.as_str()).into_owned()
+            }
+        };
+    }
+    out
+}
+
+/// Drops reasoning blocks. An unterminated block (stream cut off while the
+/// model was still thinking) is dropped up to the end.
+fn strip_think(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("<think>") {
+        out.push_str(&rest[..start]);
+        match rest[start..].find("</think>") {
+            Some(end) => rest = &rest[start + end + "</think>".len()..],
+            None => rest = "",
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Keeps one copy of each run of identical non-empty lines.
+fn collapse_repeats(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines() {
+        if !line.trim().is_empty() && lines.last() == Some(&line) {
+            continue;
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn strips_reasoning_blocks() {
+        assert_eq!(strip_think("<think>hmm</think>Answer"), "Answer");
+        assert_eq!(strip_think("a<think>x</think>b<think>y</think>c"), "abc");
+        assert_eq!(strip_think("Answer<think>cut off"), "Answer");
+    }
+
+    #[test]
+    fn collapses_repeated_lines_only() {
+        assert_eq!(
+            collapse_repeats("Sure!\nSure!\nSure!\n\n\nDone"),
+            "Sure!\n\n\nDone"
+        );
+    }
+
+    #[test]
+    fn runs_configured_steps_in_order() {
+        let steps = from_config(&json!({
+            "post_processors": [
+                { "kind": "strip_think" },
+                { "kind": "regex", "pattern": "(?i)as an ai,? ", "replace": "" },
+                { "kind": "regex", "pattern": "(" },
+                { "kind": "unknown" },
+                { "kind": "trim" },
+            ]
+        }));
+        assert_eq!(steps.len(), 3);
+        assert_eq!(
+            apply(&steps, "<think>plan</think>\n As an AI, I think so. \n"),
+            "I think so."
+        );
+    }
+
+    #[test]
+    fn stops_at_earliest_pattern() {
+        let patterns = stop_patterns(&json!({ "stop_patterns": ["User:", "\\n\\n\\n", "["] }));
+        assert_eq!(patterns.len(), 2);
+        assert_eq!(find_stop(&patterns, "Hi\n\n\nUser: more"), Some(2));
+        assert_eq!(find_stop(&patterns, "Hi"), None);
+    }
+}
@@ -0,0 +1,86 @@
+use rusqlite::{params, Connection};
+use std::path::PathBuf;
+
+/// A named set of files that can be attached in one click, or to every
+/// message when `auto_attach` is set.
+#[derive(Clone, Debug)]
+pub struct Preset {
+    pub id: i64,
+    pub name: String,
+    pub auto_attach: bool,
+    pub files: Vec<PathBuf>,
+}
+
+pub fn init_table(db: &Connection) {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS attachment_presets (id INTEGER PRIMARY KEY, name TEXT UNIQUE, auto_attach INTEGER DEFAULT 0)",
+        [],
+    )
+    .unwrap();
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS attachment_preset_files (preset_id INTEGER, path TEXT)",
+        [],
+    )
+    .unwrap();
+}
+
+pub fn load_presets(db: &Connection) -> Vec<Preset> {
+    let mut stmt = db
+        .prepare("SELECT id, name, auto_attach FROM attachment_presets ORDER BY name")
+        .unwrap();
+    let mut presets: Vec<Preset> = stmt
+        .query_map([], |row| {
+            Ok(Preset {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                auto_attach: row.get::<usize, i64>(2)? != 0,
+                files: Vec::new(),
+            })
+        })
+        .unwrap()
+        .flatten()
+        .collect();
+
+    let mut files_stmt = db
+        .prepare("SELECT path FROM attachment_preset_files WHERE preset_id = ?1")
+        .unwrap();
+    for preset in &mut presets {
+        preset.files = files_stmt
+            .query_map(params![preset.id], |row| row.get::<usize, String>(0))
+            .unwrap()
+            .flatten()
+            .map(PathBuf::from)
+            .collect();
+    }
+    presets
+}
+
+pub fn create_preset(db: &Connection, name: &str, files: &[PathBuf]) -> rusqlite::Result<i64> {
+    db.execute(
+        "INSERT INTO attachment_presets (name) VALUES (?1)",
+        params![name],
+    )?;
+    let id = db.last_insert_rowid();
+    for path in files {
+        db.execute(
+            "INSERT INTO attachment_preset_files (preset_id, path) VALUES (?1, ?2)",
+            params![id, path.to_string_lossy()],
+        )?;
+    }
+    Ok(id)
+}
+
+pub fn set_auto_attach(db: &Connection, id: i64, auto_attach: bool) {
+    let _ = db.execute(
+        "UPDATE attachment_presets SET auto_attach = ?1 WHERE id = ?2",
+        params![auto_attach, id],
+    );
+}
+
+pub fn delete_preset(db: &Connection, id: i64) {
+    let _ = db.execute(
+        "DELETE FROM attachment_preset_files WHERE preset_id = ?1",
+        params![id],
+    );
+    let _ = db.execute("DELETE FROM attachment_presets WHERE id = ?1", params![id]);
+}
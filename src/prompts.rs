@@ -0,0 +1,76 @@
+use rusqlite::{params, Connection};
+
+/// A saved prompt the user inserts into the input box instead of typing it
+/// again. `{{name}}` placeholders in it are filled in on insert, see
+/// `expand`.
+#[derive(Clone, Debug)]
+pub struct Prompt {
+    pub id: i64,
+    pub name: String,
+    pub content: String,
+}
+
+pub fn init_table(db: &Connection) {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS prompts (id INTEGER PRIMARY KEY, name TEXT, content TEXT, created_at DATETIME)",
+        [],
+    )
+    .unwrap();
+}
+
+pub fn load_prompts(db: &Connection) -> Vec<Prompt> {
+    let mut stmt = db
+        .prepare("SELECT id, name, content FROM prompts ORDER BY name COLLATE NOCASE")
+        .unwrap();
+    stmt.query_map([], |row| {
+        Ok(Prompt {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            content: row.get(2)?,
+        })
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
+
+pub fn save_prompt(db: &Connection, prompt: &Prompt) -> rusqlite::Result<i64> {
+    if prompt.id > 0 {
+        db.execute(
+            "UPDATE prompts SET name = ?1, content = ?2 WHERE id = ?3",
+            params![prompt.name, prompt.content, prompt.id],
+        )?;
+        Ok(prompt.id)
+    } else {
+        db.execute(
+            "INSERT INTO prompts (name, content, created_at) VALUES (?1, ?2, datetime('now'))",
+            params![prompt.name, prompt.content],
+        )?;
+        Ok(db.last_insert_rowid())
+    }
+}
+
+pub fn delete_prompt(db: &Connection, id: i64) {
+    let _ = db.execute("DELETE FROM prompts WHERE id = ?1", params![id]);
+}
+
+/// Replaces each `{{name}}` in `content` with `value(name)`. Placeholders
+/// without a value, and unclosed braces, are left as written.
+pub fn expand(content: &str, mut value: impl FnMut(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let end = start + len + 4;
+        out.push_str(&rest[..start]);
+        match value(rest[start + 2..end - 2].trim()) {
+            Some(text) => out.push_str(&text),
+            None => out.push_str(&rest[start..end]),
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
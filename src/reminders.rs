@@ -0,0 +1,95 @@
+use rusqlite::{params, Connection};
+use std::collections::HashSet;
+
+/// Follow-up times offered in the UI, as pairs of SQLite date modifiers
+/// applied to today. Reminders fire at 09:00 on the resulting day.
+const PRESETS: [(&str, &str); 3] = [
+    ("+1 day", "+0 days"),
+    // The next Friday, a week out when today is Friday
+    ("+1 day", "weekday 5"),
+    ("+7 days", "+0 days"),
+];
+
+pub struct Reminder {
+    pub title: String,
+    pub note: String,
+}
+
+pub fn init_table(db: &Connection) {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS reminders (id INTEGER PRIMARY KEY, session_id TEXT, due_at DATETIME, note TEXT, fired INTEGER DEFAULT 0)",
+        [],
+    )
+    .unwrap();
+}
+
+/// Replaces the session's reminder. `preset` indexes the UI's choices:
+/// tomorrow, Friday, next week.
+pub fn set_reminder(db: &Connection, session_id: &str, preset: usize, note: &str) {
+    let Some((first, second)) = PRESETS.get(preset) else {
+        return;
+    };
+    clear(db, session_id);
+    let _ = db.execute(
+        "INSERT INTO reminders (session_id, due_at, note)
+         VALUES (?1, date('now', 'localtime', ?2, ?3) || ' 09:00:00', ?4)",
+        params![session_id, first, second, note.trim()],
+    );
+}
+
+pub fn clear(db: &Connection, session_id: &str) {
+    let _ = db.execute(
+        "DELETE FROM reminders WHERE session_id = ?1",
+        params![session_id],
+    );
+}
+
+/// When the session's pending reminder is due, formatted for display.
+pub fn pending(db: &Connection, session_id: &str) -> Option<String> {
+    db.query_row(
+        "SELECT strftime('%a %d %b, %H:%M', due_at) FROM reminders WHERE session_id = ?1 AND fired = 0",
+        params![session_id],
+        |row| row.get(0),
+    )
+    .ok()
+}
+
+/// Reminders that just came due, marked fired so they notify only once.
+pub fn take_due(db: &Connection) -> Vec<Reminder> {
+    let mut stmt = db
+        .prepare(
+            "SELECT r.id, COALESCE(s.title, ''), r.note FROM reminders r
+             LEFT JOIN sessions s ON s.id = r.session_id
+             WHERE r.fired = 0 AND r.due_at <= datetime('now', 'localtime')
+             AND COALESCE(s.deleted, 0) = 0",
+        )
+        .unwrap();
+    let due: Vec<(i64, Reminder)> = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                Reminder {
+                    title: row.get(1)?,
+                    note: row.get(2)?,
+                },
+            ))
+        })
+        .unwrap()
+        .flatten()
+        .collect();
+    for (id, _) in &due {
+        let _ = db.execute("UPDATE reminders SET fired = 1 WHERE id = ?1", params![id]);
+    }
+    due.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Sessions whose reminder has fired and that haven't been opened since.
+pub fn fired_sessions(db: &Connection) -> HashSet<String> {
+    let mut stmt = db
+        .prepare("SELECT session_id FROM reminders WHERE fired = 1")
+        .unwrap();
+    stmt.query_map([], |row| row.get(0))
+        .unwrap()
+        .flatten()
+        .collect()
+}
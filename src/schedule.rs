@@ -0,0 +1,94 @@
+use rusqlite::{params, Connection};
+use std::path::PathBuf;
+
+/// A prompt fired into a session once a day at `time` (local `HH:MM`).
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    pub id: i64,
+    pub session_id: String,
+    pub prompt: String,
+    pub model: String,
+    pub time: String,
+    pub attachment: Option<PathBuf>,
+}
+
+pub fn init_table(db: &Connection) {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS schedules (id INTEGER PRIMARY KEY, session_id TEXT, prompt TEXT, model TEXT, time TEXT, attachment TEXT, last_run DATE)",
+        [],
+    )
+    .unwrap();
+}
+
+fn query(db: &Connection, sql: &str) -> Vec<Schedule> {
+    let mut stmt = db.prepare(sql).unwrap();
+    stmt.query_map([], |row| {
+        Ok(Schedule {
+            id: row.get(0)?,
+            session_id: row.get(1)?,
+            prompt: row.get(2)?,
+            model: row.get(3)?,
+            time: row.get(4)?,
+            attachment: row.get::<usize, Option<String>>(5)?.map(PathBuf::from),
+        })
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
+
+pub fn load_schedules(db: &Connection) -> Vec<Schedule> {
+    query(
+        db,
+        "SELECT id, session_id, prompt, model, time, attachment FROM schedules
+         WHERE session_id NOT IN (SELECT id FROM sessions WHERE deleted = 1) ORDER BY time",
+    )
+}
+
+/// Accepts `H:MM` or `HH:MM` and normalizes it so string comparison against
+/// SQLite's `%H:%M` works.
+pub fn parse_time(input: &str) -> Option<String> {
+    let (h, m) = input.trim().split_once(':')?;
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    (h < 24 && m < 60).then(|| format!("{:02}:{:02}", h, m))
+}
+
+pub fn create_schedule(db: &Connection, schedule: &Schedule) -> rusqlite::Result<()> {
+    // Don't fire today if the time has already passed
+    db.execute(
+        "INSERT INTO schedules (session_id, prompt, model, time, attachment, last_run)
+         VALUES (?1, ?2, ?3, ?4, ?5,
+                 CASE WHEN ?4 <= strftime('%H:%M', 'now', 'localtime') THEN date('now', 'localtime') END)",
+        params![
+            schedule.session_id,
+            schedule.prompt,
+            schedule.model,
+            schedule.time,
+            schedule.attachment.as_ref().map(|p| p.to_string_lossy().to_string()),
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn delete_schedule(db: &Connection, id: i64) {
+    let _ = db.execute("DELETE FROM schedules WHERE id = ?1", params![id]);
+}
+
+/// Schedules whose time has come today and that haven't run yet, marked as
+/// run so each fires once per day.
+pub fn take_due(db: &Connection) -> Vec<Schedule> {
+    let due = query(
+        db,
+        "SELECT id, session_id, prompt, model, time, attachment FROM schedules
+         WHERE time <= strftime('%H:%M', 'now', 'localtime')
+         AND (last_run IS NULL OR last_run < date('now', 'localtime'))
+         AND session_id NOT IN (SELECT id FROM sessions WHERE deleted = 1)",
+    );
+    for schedule in &due {
+        let _ = db.execute(
+            "UPDATE schedules SET last_run = date('now', 'localtime') WHERE id = ?1",
+            params![schedule.id],
+        );
+    }
+    due
+}
@@ -0,0 +1,222 @@
+use rusqlite::{params, Connection};
+
+use crate::db;
+
+// Around the matched terms in snippets; never part of stored text
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+// Tokens of context around the best match in a snippet
+const SNIPPET_TOKENS: i32 = 12;
+
+/// A message matching a history search.
+pub struct Hit {
+    pub session_id: String,
+    pub title: String,
+    pub message_id: i64,
+    /// Runs of the snippet, flagged where they matched the query
+    pub snippet: Vec<(String, bool)>,
+}
+
+/// Creates the full-text index of message texts and the triggers that keep
+/// it in step with `messages`, indexing existing history the first time.
+/// The index holds no copy of the text: it reads it through the
+/// `message_texts` view, which gives each message's text as
+/// `db::MESSAGE_TEXT` reads it, so large and compressed messages are
+/// searchable too.
+pub fn init_table(db: &Connection) {
+    let existing: Option<String> = db
+        .query_row(
+            "SELECT sql FROM sqlite_master WHERE name = 'message_search'",
+            [],
+            |row| row.get(0),
+        )
+        .ok();
+    // Earlier versions kept a full copy of every message in the index
+    let stale = existing
+        .as_deref()
+        .is_some_and(|sql| !sql.contains("content_rowid"));
+    if stale {
+        let _ = db.execute("DROP TABLE message_search", []);
+    }
+
+    // Views and triggers are made afresh so they follow `db::MESSAGE_TEXT`
+    let _ = db.execute_batch(&format!(
+        "DROP VIEW IF EXISTS message_texts;
+         CREATE VIEW message_texts AS SELECT id, {} AS content FROM messages;",
+        db::MESSAGE_TEXT
+    ));
+    db.execute(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS message_search USING fts5(content, content = 'message_texts', content_rowid = 'id', tokenize = 'unicode61 remove_diacritics 2')",
+        [],
+    )
+    .unwrap();
+
+    // An external-content index is told what it held for a row when the
+    // row goes, so the old text is read the way the new one is
+    let new_text = db::MESSAGE_TEXT.replace("messages.", "NEW.");
+    let old_text = db::MESSAGE_TEXT.replace("messages.", "OLD.");
+    let _ = db.execute_batch(&format!(
+        "DROP TRIGGER IF EXISTS index_message_on_insert;
+         DROP TRIGGER IF EXISTS index_message_on_update;
+         DROP TRIGGER IF EXISTS index_message_on_delete;
+         CREATE TRIGGER index_message_on_insert AFTER INSERT ON messages
+         BEGIN
+             INSERT INTO message_search (rowid, content) VALUES (NEW.id, {new_text});
+         END;
+         CREATE TRIGGER index_message_on_update AFTER UPDATE OF content, blob ON messages
+         BEGIN
+             INSERT INTO message_search (message_search, rowid, content) VALUES ('delete', OLD.id, {old_text});
+             INSERT INTO message_search (rowid, content) VALUES (NEW.id, {new_text});
+         END;
+         CREATE TRIGGER index_message_on_delete AFTER DELETE ON messages
+         BEGIN
+             INSERT INTO message_search (message_search, rowid, content) VALUES ('delete', OLD.id, {old_text});
+         END;"
+    ));
+
+    if existing.is_none() || stale {
+        let _ = db.execute(
+            "INSERT INTO message_search (message_search) VALUES ('rebuild')",
+            [],
+        );
+    }
+}
+
+/// FTS5 query for what the user typed: every word must occur, the last one
+/// possibly unfinished. Quoting each word keeps FTS5 operators and
+/// punctuation in the input from being read as query syntax.
+fn match_query(query: &str) -> Option<String> {
+    let words: Vec<&str> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    let (last, rest) = words.split_last()?;
+    let mut terms: Vec<String> = rest.iter().map(|w| format!("\"{}\"", w)).collect();
+    terms.push(format!("\"{}\"*", last));
+    Some(terms.join(" "))
+}
+
+/// Best matching messages for `query`, most relevant first.
+pub fn search(db: &Connection, query: &str, limit: usize) -> Vec<Hit> {
+    let Some(fts_query) = match_query(query) else {
+        return Vec::new();
+    };
+    let Ok(mut stmt) = db.prepare(
+        "SELECT m.session_id, COALESCE(s.title, 'New chat'), ms.rowid,
+                snippet(message_search, 0, ?2, ?3, '…', ?4)
+         FROM message_search ms JOIN messages m ON m.id = ms.rowid
+         JOIN sessions s ON s.id = m.session_id
+         WHERE message_search MATCH ?1 AND s.deleted = 0 AND m.deleted = 0
+         ORDER BY rank LIMIT ?5",
+    ) else {
+        return Vec::new();
+    };
+    stmt.query_map(
+        params![
+            fts_query,
+            MATCH_START.to_string(),
+            MATCH_END.to_string(),
+            SNIPPET_TOKENS,
+            limit as i64
+        ],
+        |row| {
+            Ok(Hit {
+                session_id: row.get(0)?,
+                title: row.get(1)?,
+                message_id: row.get(2)?,
+                snippet: split_snippet(&row.get::<usize, String>(3)?),
+            })
+        },
+    )
+    .map(|rows| rows.flatten().collect())
+    .unwrap_or_default()
+}
+
+/// Ids of every session with a message matching `query`.
+pub fn sessions_matching(db: &Connection, query: &str) -> Vec<String> {
+    let Some(fts_query) = match_query(query) else {
+        return Vec::new();
+    };
+    db.prepare(
+        "SELECT DISTINCT m.session_id FROM message_search ms JOIN messages m ON m.id = ms.rowid
+         WHERE message_search MATCH ?1 AND m.deleted = 0",
+    )
+    .and_then(|mut stmt| {
+        let ids: Vec<String> = stmt
+            .query_map(params![fts_query], |row| row.get(0))?
+            .flatten()
+            .collect();
+        Ok(ids)
+    })
+    .unwrap_or_default()
+}
+
+/// Cuts a snippet at the match markers into one line of runs.
+fn split_snippet(snippet: &str) -> Vec<(String, bool)> {
+    let flat = snippet.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut runs = Vec::new();
+    for (i, part) in flat.split(MATCH_START).enumerate() {
+        let (matched, rest) = match part.split_once(MATCH_END) {
+            Some((matched, rest)) if i > 0 => (matched, rest),
+            _ => ("", part),
+        };
+        if !matched.is_empty() {
+            runs.push((matched.to_string(), true));
+        }
+        if !rest.is_empty() {
+            runs.push((rest.to_string(), false));
+        }
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_words_and_completes_the_last() {
+        assert_eq!(
+            match_query("rust  OR \"borrow"),
+            Some("\"rust\" \"OR\" \"borrow\"*".to_string())
+        );
+        assert_eq!(match_query(" -*- "), None);
+    }
+
+    #[test]
+    fn splits_snippet_at_markers() {
+        let snippet = format!(
+            "before {s}quick{e} and\n{s}brown{e} after",
+            s = MATCH_START,
+            e = MATCH_END
+        );
+        assert_eq!(
+            split_snippet(&snippet),
+            [
+                ("before ".to_string(), false),
+                ("quick".to_string(), true),
+                (" and ".to_string(), false),
+                ("brown".to_string(), true),
+                (" after".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn index_follows_edits() {
+        let db = Connection::open_in_memory().unwrap();
+        crate::init_db(&db);
+        db.execute("INSERT INTO sessions (id, title) VALUES ('s1', 'Fox')", [])
+            .unwrap();
+        let id = db::insert_message(&db, "s1", "user", "The quick brown fox", None);
+
+        let hits = search(&db, "qui", 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message_id, id);
+        assert!(hits[0].snippet.contains(&("quick".to_string(), true)));
+
+        db::set_message_text(&db, id, "A slow turtle");
+        assert!(search(&db, "quick", 10).is_empty());
+        assert_eq!(sessions_matching(&db, "turtle"), ["s1"]);
+    }
+}
@@ -0,0 +1,183 @@
+use rusqlite::Connection;
+use serde_json::json;
+use std::path::PathBuf;
+
+use crate::{presets, prompts, templates, tools};
+
+const SETTINGS_VERSION: u64 = 1;
+
+/// Credentials stay on the machine they were entered on.
+fn strip_secrets(cfg: &mut serde_json::Value) {
+    if let Some(map) = cfg.as_object_mut() {
+        map.remove("backend_api_key");
+        map.remove("ollama_token");
+    }
+    if let Some(sync) = cfg.get_mut("sync").and_then(|s| s.as_object_mut()) {
+        sync.remove("secret");
+        sync.remove("passphrase");
+    }
+}
+
+/// Config plus the tool definitions, attachment presets, conversation
+/// templates and saved prompts stored in the database, as one JSON document.
+pub fn export(db: &Connection, cfg: &serde_json::Value) -> serde_json::Value {
+    let mut config = cfg.clone();
+    strip_secrets(&mut config);
+    let tools: Vec<serde_json::Value> = tools::load_tools(db)
+        .into_iter()
+        .map(|t| {
+            json!({
+                "name": t.name,
+                "description": t.description,
+                "params": t.params,
+                "kind": t.kind,
+                "template": t.template,
+                "enabled": t.enabled,
+            })
+        })
+        .collect();
+    let presets: Vec<serde_json::Value> = presets::load_presets(db)
+        .into_iter()
+        .map(|p| {
+            json!({
+                "name": p.name,
+                "auto_attach": p.auto_attach,
+                "files": p.files,
+            })
+        })
+        .collect();
+    let templates: Vec<serde_json::Value> = templates::load_templates(db)
+        .into_iter()
+        .map(|t| {
+            json!({
+                "name": t.name,
+                "system_prompt": t.system_prompt,
+                "turns": t.turns,
+            })
+        })
+        .collect();
+    let prompts: Vec<serde_json::Value> = prompts::load_prompts(db)
+        .into_iter()
+        .map(|p| json!({ "name": p.name, "content": p.content }))
+        .collect();
+    json!({
+        "version": SETTINGS_VERSION,
+        "config": config,
+        "tools": tools,
+        "attachment_presets": presets,
+        "templates": templates,
+        "prompts": prompts,
+    })
+}
+
+/// Applies an exported bundle. Config keys from the bundle overwrite local
+/// ones except credentials; tools, presets, templates and prompts replace
+/// local entries with the same name. Imported tools are switched off, since a command tool runs
+/// whatever the file says; returns how many there were.
+pub fn import(
+    db: &Connection,
+    cfg: &mut serde_json::Value,
+    bundle: &serde_json::Value,
+) -> Result<usize, String> {
+    if bundle["version"].as_u64().is_none() || !bundle["config"].is_object() {
+        return Err("not a settings export".into());
+    }
+
+    let mut incoming = bundle["config"].clone();
+    strip_secrets(&mut incoming);
+    if let (Some(local), Some(incoming)) = (cfg.as_object_mut(), incoming.as_object()) {
+        for (key, value) in incoming {
+            if key == "sync" {
+                let sync = local.entry("sync").or_insert_with(|| json!({}));
+                if !sync.is_object() {
+                    *sync = json!({});
+                }
+                // Keep the local credentials inside the sync object
+                for (sync_key, sync_value) in value.as_object().into_iter().flatten() {
+                    sync[sync_key] = sync_value.clone();
+                }
+            } else {
+                local.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    let existing_tools = tools::load_tools(db);
+    let mut imported_tools = 0;
+    for t in bundle["tools"].as_array().into_iter().flatten() {
+        let name = t["name"].as_str().unwrap_or("").to_string();
+        if name.is_empty() {
+            continue;
+        }
+        let tool = tools::ToolDef {
+            id: existing_tools
+                .iter()
+                .find(|e| e.name == name)
+                .map(|e| e.id)
+                .unwrap_or(0),
+            name,
+            description: t["description"].as_str().unwrap_or("").to_string(),
+            params: serde_json::from_value(t["params"].clone()).unwrap_or_default(),
+            kind: t["kind"].as_str().unwrap_or("command").to_string(),
+            template: t["template"].as_str().unwrap_or("").to_string(),
+            enabled: false,
+        };
+        tools::save_tool(db, &tool).map_err(|e| e.to_string())?;
+        imported_tools += 1;
+    }
+
+    let existing_presets = presets::load_presets(db);
+    for p in bundle["attachment_presets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        let Some(name) = p["name"].as_str() else {
+            continue;
+        };
+        if let Some(old) = existing_presets.iter().find(|e| e.name == name) {
+            presets::delete_preset(db, old.id);
+        }
+        let files: Vec<PathBuf> = serde_json::from_value(p["files"].clone()).unwrap_or_default();
+        let id = presets::create_preset(db, name, &files).map_err(|e| e.to_string())?;
+        presets::set_auto_attach(db, id, p["auto_attach"].as_bool().unwrap_or(false));
+    }
+
+    let existing_templates = templates::load_templates(db);
+    for t in bundle["templates"].as_array().into_iter().flatten() {
+        let name = t["name"].as_str().unwrap_or("").to_string();
+        if name.is_empty() {
+            continue;
+        }
+        let template = templates::Template {
+            id: existing_templates
+                .iter()
+                .find(|e| e.name == name)
+                .map(|e| e.id)
+                .unwrap_or(0),
+            name,
+            system_prompt: t["system_prompt"].as_str().unwrap_or("").to_string(),
+            turns: serde_json::from_value(t["turns"].clone()).unwrap_or_default(),
+        };
+        templates::save_template(db, &template).map_err(|e| e.to_string())?;
+    }
+
+    let existing_prompts = prompts::load_prompts(db);
+    for p in bundle["prompts"].as_array().into_iter().flatten() {
+        let name = p["name"].as_str().unwrap_or("").to_string();
+        if name.is_empty() {
+            continue;
+        }
+        let prompt = prompts::Prompt {
+            id: existing_prompts
+                .iter()
+                .find(|e| e.name == name)
+                .map(|e| e.id)
+                .unwrap_or(0),
+            name,
+            content: p["content"].as_str().unwrap_or("").to_string(),
+        };
+        prompts::save_prompt(db, &prompt).map_err(|e| e.to_string())?;
+    }
+    Ok(imported_tools)
+}
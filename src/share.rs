@@ -0,0 +1,122 @@
+use base64::Engine;
+use rusqlite::{params, Connection};
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{db, import, sync};
+
+/// File extension of shared chats.
+pub const EXTENSION: &str = "ollchat";
+const FORMAT: &str = "ollchat";
+const FORMAT_VERSION: u64 = 1;
+
+/// One session as a self-contained JSON document: its messages in the sync
+/// bundle's shape, the per-chat settings, and the session's files with
+/// their content inlined as base64.
+pub fn export(db: &Connection, session_id: &str) -> Result<serde_json::Value, String> {
+    let mut session = db
+        .query_row(
+            "SELECT title, created_at, icon, summary FROM sessions WHERE id = ?1",
+            params![session_id],
+            |row| {
+                Ok(json!({
+                    "title": row.get::<usize, Option<String>>(0)?,
+                    "created_at": row.get::<usize, Option<String>>(1)?,
+                    "icon": row.get::<usize, Option<String>>(2)?,
+                    "summary": row.get::<usize, Option<String>>(3)?,
+                }))
+            },
+        )
+        .map_err(|_| "this chat hasn't been saved yet".to_string())?;
+    let (reply_format, reply_language) = db::session_reply_style(db, session_id);
+    session["system_prompt"] = db::session_system_prompt(db, session_id).into();
+    session["reply_format"] = reply_format.into();
+    session["reply_language"] = reply_language.into();
+    session["options"] = json!(db::session_options(db, session_id));
+    session["messages"] = sync::export_messages(db, session_id).into();
+
+    let attachments: Vec<serde_json::Value> = db::attachment_library(db, session_id)
+        .into_iter()
+        .filter_map(|(name, path, active, pinned)| {
+            let bytes = fs::read(&path).ok()?;
+            Some(json!({
+                "name": name,
+                // The stored copy, which for PDFs is the extracted text
+                "file": path.file_name()?.to_string_lossy(),
+                "active": active,
+                "pinned": pinned,
+                "data": base64::engine::general_purpose::STANDARD.encode(bytes),
+            }))
+        })
+        .collect();
+
+    Ok(json!({
+        "format": FORMAT,
+        "version": FORMAT_VERSION,
+        "app_version": env!("CARGO_PKG_VERSION"),
+        "exported_at": db
+            .query_row("SELECT datetime('now')", [], |row| row.get::<usize, String>(0))
+            .unwrap_or_default(),
+        "session": session,
+        "attachments": attachments,
+    }))
+}
+
+/// Adds a shared chat as a new session and returns its id. Opening the same
+/// file twice returns the session from the first time instead of a copy.
+pub fn import(db: &Connection, bundle: &serde_json::Value) -> Result<String, String> {
+    if bundle["format"] != FORMAT {
+        return Err("not a shared chat file".into());
+    }
+    if bundle["version"].as_u64().unwrap_or(0) > FORMAT_VERSION {
+        return Err("the chat was shared from a newer version of the app".into());
+    }
+    let session = &bundle["session"];
+    let messages = session["messages"].as_array().cloned().unwrap_or_default();
+    if let Some(existing) = import::identical_session(db, &messages) {
+        return Ok(existing);
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+    let mut copy = session.clone();
+    copy["rev"] = serde_json::Value::Null;
+    sync::replace_session(db, &id, &copy);
+    db::set_session_system_prompt(db, &id, session["system_prompt"].as_str().unwrap_or(""));
+    db::set_session_reply_style(
+        db,
+        &id,
+        session["reply_format"].as_str().unwrap_or(""),
+        session["reply_language"].as_str().unwrap_or(""),
+    );
+    db::set_session_options(
+        db,
+        &id,
+        &serde_json::from_value(session["options"].clone()).unwrap_or_default(),
+    );
+
+    let dir = PathBuf::from("./attachments").join(&id);
+    for file in bundle["attachments"].as_array().into_iter().flatten() {
+        let (Some(name), Some(data)) = (file["name"].as_str(), file["data"].as_str()) else {
+            continue;
+        };
+        // Only the last component, so a crafted file can't write elsewhere
+        let Some(file_name) = Path::new(file["file"].as_str().unwrap_or(name)).file_name() else {
+            continue;
+        };
+        let Ok(bytes) = base64::engine::general_purpose::STANDARD.decode(data) else {
+            continue;
+        };
+        let _ = fs::create_dir_all(&dir);
+        let path = dir.join(file_name);
+        if fs::write(&path, bytes).is_err() {
+            continue;
+        }
+        db::add_attachment(db, &id, name, &path);
+        db::set_attachment_pinned(db, &id, name, file["pinned"].as_bool().unwrap_or(false));
+        if !file["active"].as_bool().unwrap_or(true) {
+            db::detach(db, &id, name);
+        }
+    }
+    Ok(id)
+}
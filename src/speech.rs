@@ -0,0 +1,73 @@
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+
+/// Text being read out by the system's speech synthesizer.
+pub struct Speech {
+    child: Child,
+    program: &'static str,
+}
+
+impl Speech {
+    /// Whether the synthesizer is still reading.
+    pub fn is_running(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    pub fn stop(mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        // speech-dispatcher keeps talking after its client is gone
+        if self.program == "spd-say" {
+            let _ = Command::new("spd-say").arg("--cancel").status();
+        }
+    }
+}
+
+/// Synthesizers tried in order, each given the text on stdin or, for those
+/// that can't read it, as the last argument.
+#[cfg(target_os = "macos")]
+const SYNTHESIZERS: &[(&str, &[&str], bool)] = &[("say", &["-f", "-"], true)];
+#[cfg(target_os = "windows")]
+const SYNTHESIZERS: &[(&str, &[&str], bool)] = &[(
+    "powershell",
+    &[
+        "-NoProfile",
+        "-Command",
+        "Add-Type -AssemblyName System.Speech; (New-Object System.Speech.Synthesis.SpeechSynthesizer).Speak([Console]::In.ReadToEnd())",
+    ],
+    true,
+)];
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+const SYNTHESIZERS: &[(&str, &[&str], bool)] = &[
+    ("espeak-ng", &["--stdin"], true),
+    ("espeak", &["--stdin"], true),
+    ("spd-say", &["--wait"], false),
+];
+
+/// Starts reading `text` aloud with the first synthesizer found.
+pub fn speak(text: &str) -> Result<Speech, String> {
+    for &(program, args, stdin) in SYNTHESIZERS {
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        if stdin {
+            command.stdin(Stdio::piped());
+        } else {
+            command.arg(text);
+        }
+        let Ok(mut child) = command.spawn() else {
+            continue;
+        };
+        if let Some(mut input) = child.stdin.take() {
+            let text = text.to_string();
+            // Written from a thread so a long text can't block on a full pipe
+            std::thread::spawn(move || {
+                let _ = input.write_all(text.as_bytes());
+            });
+        }
+        return Ok(Speech { child, program });
+    }
+    Err("no speech synthesizer found".into())
+}
@@ -0,0 +1,480 @@
+use crate::tokens::TokenCounter;
+use ollama_rs::generation::chat::{ChatMessage, MessageRole};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+pub fn init_table(db: &Connection) {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS message_stats (message_id INTEGER PRIMARY KEY, prompt_tokens INTEGER, response_tokens INTEGER, prompt_eval_ms INTEGER, eval_ms INTEGER, tokens_per_sec REAL)",
+        [],
+    )
+    .unwrap();
+    // What each reply's request carried, as a `UsedContext` in JSON
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS message_context (message_id INTEGER PRIMARY KEY, context TEXT)",
+        [],
+    )
+    .unwrap();
+}
+
+/// Token counts and timings of one reply.
+#[derive(Default)]
+pub struct ResponseStats {
+    pub prompt_tokens: Option<u64>,
+    pub response_tokens: Option<u64>,
+    // As reported by the server
+    pub prompt_eval_ms: Option<u64>,
+    pub eval_ms: Option<u64>,
+    // First to last token as seen here, for servers that don't report timings
+    pub stream_ms: Option<u64>,
+}
+
+impl ResponseStats {
+    pub fn tokens_per_sec(&self) -> Option<f64> {
+        let tokens = self.response_tokens?;
+        let ms = self.eval_ms.or(self.stream_ms).filter(|ms| *ms > 0)?;
+        Some(tokens as f64 * 1000.0 / ms as f64)
+    }
+}
+
+pub fn record_response(db: &Connection, message_id: i64, stats: &ResponseStats) {
+    let _ = db.execute(
+        "INSERT OR REPLACE INTO message_stats (message_id, prompt_tokens, response_tokens, prompt_eval_ms, eval_ms, tokens_per_sec) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            message_id,
+            stats.prompt_tokens.map(|t| t as i64),
+            stats.response_tokens.map(|t| t as i64),
+            stats.prompt_eval_ms.map(|t| t as i64),
+            stats.eval_ms.map(|t| t as i64),
+            stats.tokens_per_sec(),
+        ],
+    );
+}
+
+/// The line shown under each message of a session, in message order: the
+/// detected language of prompts, and the model and token stats of replies,
+/// empty where none of it was recorded.
+pub fn message_labels(db: &Connection, session_id: &str) -> Vec<String> {
+    let mut stmt = db
+        .prepare(
+            "SELECT ms.prompt_tokens, ms.response_tokens, ms.tokens_per_sec, m.language,
+                    CASE WHEN m.role = 'assistant' THEN m.model END
+             FROM messages m
+             LEFT JOIN message_stats ms ON ms.message_id = m.id
+             WHERE m.session_id = ?1 AND m.deleted = 0 ORDER BY m.id",
+        )
+        .unwrap();
+    stmt.query_map(params![session_id], |row| {
+        let prompt: Option<i64> = row.get(0)?;
+        let response: Option<i64> = row.get(1)?;
+        let rate: Option<f64> = row.get(2)?;
+        let language: Option<String> = row.get(3)?;
+        let model: Option<String> = row.get(4)?;
+        // Which model answered, so chats that switched models stay traceable
+        let mut parts: Vec<String> = language
+            .into_iter()
+            .chain(model.filter(|m| !m.is_empty()))
+            .collect();
+        if let Some(response) = response {
+            parts.push(format!("{} tokens", response));
+        }
+        if let Some(rate) = rate {
+            parts.push(format!("{:.1} tok/s", rate));
+        }
+        if let Some(prompt) = prompt {
+            parts.push(format!("{} in prompt", prompt));
+        }
+        Ok(parts.join(" · "))
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
+
+/// One aggregated line of the usage dashboard, keyed by day or model.
+pub struct UsageRow {
+    pub label: String,
+    pub messages: i64,
+    pub prompt_tokens: i64,
+    pub response_tokens: i64,
+    pub avg_response_ms: i64,
+}
+
+fn query(db: &Connection, sql: &str) -> Vec<UsageRow> {
+    let mut stmt = db.prepare(sql).unwrap();
+    stmt.query_map([], |row| {
+        Ok(UsageRow {
+            label: row
+                .get::<usize, Option<String>>(0)?
+                .unwrap_or_else(|| "unknown".into()),
+            messages: row.get(1)?,
+            prompt_tokens: row.get::<usize, Option<i64>>(2)?.unwrap_or(0),
+            response_tokens: row.get::<usize, Option<i64>>(3)?.unwrap_or(0),
+            avg_response_ms: row.get::<usize, Option<f64>>(4)?.unwrap_or(0.0) as i64,
+        })
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
+
+/// Last 30 days with activity, oldest first so charts read left to right.
+/// Messages written before timestamps were recorded are not counted.
+pub fn by_day(db: &Connection) -> Vec<UsageRow> {
+    let mut rows = query(
+        db,
+        "SELECT date(created_at) AS day, COUNT(*), SUM(prompt_tokens), SUM(response_tokens),
+                AVG(CASE WHEN role = 'assistant' THEN duration_ms END)
+         FROM messages WHERE created_at IS NOT NULL
+         GROUP BY day ORDER BY day DESC LIMIT 30",
+    );
+    rows.reverse();
+    rows
+}
+
+/// Assistant replies grouped by the model that produced them.
+pub fn by_model(db: &Connection) -> Vec<UsageRow> {
+    query(
+        db,
+        "SELECT model, COUNT(*), SUM(prompt_tokens), SUM(response_tokens), AVG(duration_ms)
+         FROM messages WHERE role = 'assistant' AND model IS NOT NULL
+         GROUP BY model ORDER BY COUNT(*) DESC",
+    )
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Both aggregations in one file, distinguished by the `dimension` column.
+pub fn to_csv(by_day: &[UsageRow], by_model: &[UsageRow]) -> String {
+    let mut out =
+        String::from("dimension,label,messages,prompt_tokens,response_tokens,avg_response_ms\n");
+    for (dimension, rows) in [("day", by_day), ("model", by_model)] {
+        for row in rows {
+            out.push_str(&format!(
+                "{},{},{},{},{},{}\n",
+                dimension,
+                csv_field(&row.label),
+                row.messages,
+                row.prompt_tokens,
+                row.response_tokens,
+                row.avg_response_ms
+            ));
+        }
+    }
+    out
+}
+
+pub struct SessionSummary {
+    pub messages: i64,
+    pub tokens: i64,
+    pub models: Vec<String>,
+    pub created_at: String,
+    pub generation_ms: i64,
+    pub summary: String,
+}
+
+pub fn session_summary(db: &Connection, session_id: &str) -> SessionSummary {
+    let (messages, tokens, generation_ms) = db
+        .query_row(
+            "SELECT COUNT(*), SUM(COALESCE(prompt_tokens, 0) + COALESCE(response_tokens, 0)), SUM(duration_ms)
+             FROM messages WHERE session_id = ?1 AND deleted = 0",
+            params![session_id],
+            |row| {
+                Ok((
+                    row.get::<usize, i64>(0)?,
+                    row.get::<usize, Option<i64>>(1)?.unwrap_or(0),
+                    row.get::<usize, Option<i64>>(2)?.unwrap_or(0),
+                ))
+            },
+        )
+        .unwrap_or((0, 0, 0));
+
+    let models = db
+        .prepare("SELECT DISTINCT model FROM messages WHERE session_id = ?1 AND model IS NOT NULL AND deleted = 0")
+        .and_then(|mut stmt| {
+            let names: Vec<String> = stmt
+                .query_map(params![session_id], |row| row.get(0))?
+                .flatten()
+                .collect();
+            Ok(names)
+        })
+        .unwrap_or_default();
+
+    let (created_at, summary) = db
+        .query_row(
+            "SELECT created_at, summary FROM sessions WHERE id = ?1",
+            params![session_id],
+            |row| {
+                Ok((
+                    row.get::<usize, String>(0)?,
+                    row.get::<usize, Option<String>>(1)?.unwrap_or_default(),
+                ))
+            },
+        )
+        .unwrap_or_default();
+
+    SessionSummary {
+        messages,
+        tokens,
+        models,
+        created_at,
+        generation_ms,
+        summary,
+    }
+}
+
+/// Running word and character count of a streamed reply, fed chunk by
+/// chunk so the text never has to be rescanned.
+#[derive(Clone, Copy, Default)]
+pub struct ReplyLength {
+    pub words: usize,
+    pub chars: usize,
+    in_word: bool,
+}
+
+impl ReplyLength {
+    pub fn feed(&mut self, chunk: &str) {
+        for c in chunk.chars() {
+            self.chars += 1;
+            if c.is_whitespace() {
+                self.in_word = false;
+            } else if !self.in_word {
+                self.in_word = true;
+                self.words += 1;
+            }
+        }
+    }
+}
+
+/// How the prompt tokens of one request were spent.
+pub struct PromptBudget {
+    pub system: i64,
+    pub history: i64,
+    pub attachments: i64,
+    pub prompt: i64,
+}
+
+impl PromptBudget {
+    /// Splits the prompt token count the server reported across the parts of
+    /// `messages`, in proportion to their local token counts.
+    /// `attachment_tokens` is the file content injected into the request; it
+    /// sits in the last message on the first round and in the history once
+    /// tool rounds follow.
+    pub fn split(
+        counter: &TokenCounter,
+        messages: &[ChatMessage],
+        attachment_tokens: usize,
+        attachments_in_prompt: bool,
+        prompt_tokens: u64,
+    ) -> Self {
+        let counts: Vec<usize> = messages.iter().map(|m| counter.count(&m.content)).collect();
+        let system: usize = messages
+            .iter()
+            .zip(&counts)
+            .filter(|(m, _)| m.role == MessageRole::System)
+            .map(|(_, n)| n)
+            .sum();
+        let last = counts.last().copied().unwrap_or(0);
+        let total: usize = counts.iter().sum();
+        let mut history = total - system - last;
+        let mut prompt = last;
+        if attachments_in_prompt {
+            prompt = prompt.saturating_sub(attachment_tokens);
+        } else {
+            history = history.saturating_sub(attachment_tokens);
+        }
+
+        let share = |tokens: usize| (tokens as u64 * prompt_tokens / total.max(1) as u64) as i64;
+        Self {
+            system: share(system),
+            history: share(history),
+            attachments: share(attachment_tokens),
+            prompt: share(prompt),
+        }
+    }
+}
+
+/// What the request behind one reply included, after turns were left out to
+/// fit the context window.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct UsedContext {
+    pub system_prompt: bool,
+    pub summary: bool,
+    pub tools: bool,
+    /// Earlier messages sent: the latest `history_sent` of `history_total`
+    pub history_sent: usize,
+    pub history_total: usize,
+    pub attachments: Vec<String>,
+}
+
+impl UsedContext {
+    pub fn describe(&self) -> String {
+        let mut lines = Vec::new();
+        if self.history_sent == self.history_total {
+            lines.push(format!("All {} earlier messages", self.history_total));
+        } else {
+            lines.push(format!(
+                "Last {} of {} earlier messages; the {} oldest were {} to fit the context",
+                self.history_sent,
+                self.history_total,
+                self.history_total - self.history_sent,
+                if self.summary {
+                    "summarized or left out"
+                } else {
+                    "left out"
+                }
+            ));
+        }
+        if !self.attachments.is_empty() {
+            lines.push(format!("Files: {}", self.attachments.join(", ")));
+        }
+        let extras: Vec<&str> = [
+            (self.system_prompt, "system prompt"),
+            (self.summary, "summary of the chat"),
+            (self.tools, "tool descriptions"),
+        ]
+        .into_iter()
+        .filter_map(|(used, name)| used.then_some(name))
+        .collect();
+        if !extras.is_empty() {
+            lines.push(format!("Also: {}", extras.join(", ")));
+        }
+        lines.join("\n")
+    }
+}
+
+pub fn record_context(db: &Connection, message_id: i64, context: &UsedContext) {
+    let _ = db.execute(
+        "INSERT OR REPLACE INTO message_context (message_id, context) VALUES (?1, ?2)",
+        params![
+            message_id,
+            serde_json::to_string(context).unwrap_or_default()
+        ],
+    );
+}
+
+/// `UsedContext::describe` of each message in the session in order, empty
+/// for messages that aren't replies or were written before it was recorded.
+pub fn message_contexts(db: &Connection, session_id: &str) -> Vec<String> {
+    let mut stmt = db
+        .prepare(
+            "SELECT mc.context FROM messages m
+             LEFT JOIN message_context mc ON mc.message_id = m.id
+             WHERE m.session_id = ?1 AND m.deleted = 0 ORDER BY m.id",
+        )
+        .unwrap();
+    stmt.query_map(params![session_id], |row| {
+        Ok(row
+            .get::<usize, Option<String>>(0)?
+            .and_then(|json| serde_json::from_str::<UsedContext>(&json).ok())
+            .map(|context| context.describe())
+            .unwrap_or_default())
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
+
+/// The budget of the most recent reply in the session that has one.
+pub fn last_prompt_budget(db: &Connection, session_id: &str) -> Option<PromptBudget> {
+    db.query_row(
+        "SELECT budget_system, budget_history, budget_attachments, budget_prompt FROM messages
+         WHERE session_id = ?1 AND budget_prompt IS NOT NULL AND deleted = 0 ORDER BY rowid DESC LIMIT 1",
+        params![session_id],
+        |row| {
+            Ok(PromptBudget {
+                system: row.get(0)?,
+                history: row.get(1)?,
+                attachments: row.get(2)?,
+                prompt: row.get(3)?,
+            })
+        },
+    )
+    .ok()
+}
+
+pub struct LatencyPoint {
+    pub at: String,
+    pub ttft_ms: i64,
+    pub tokens_per_sec: f64,
+}
+
+/// Time-to-first-token and decode speed of the model's last 60 replies,
+/// oldest first. Speed excludes the prompt evaluation before the first token.
+pub fn latency_history(db: &Connection, model: &str) -> Vec<LatencyPoint> {
+    let mut stmt = db
+        .prepare(
+            "SELECT created_at, ttft_ms, duration_ms, response_tokens FROM messages
+             WHERE role = 'assistant' AND model = ?1 AND ttft_ms IS NOT NULL
+             ORDER BY created_at DESC LIMIT 60",
+        )
+        .unwrap();
+    let mut points: Vec<LatencyPoint> = stmt
+        .query_map(params![model], |row| {
+            let ttft_ms: i64 = row.get(1)?;
+            let duration_ms: i64 = row.get::<usize, Option<i64>>(2)?.unwrap_or(0);
+            let tokens: i64 = row.get::<usize, Option<i64>>(3)?.unwrap_or(0);
+            let decode_ms = (duration_ms - ttft_ms).max(1);
+            Ok(LatencyPoint {
+                at: row.get::<usize, Option<String>>(0)?.unwrap_or_default(),
+                ttft_ms,
+                tokens_per_sec: tokens as f64 * 1000.0 / decode_ms as f64,
+            })
+        })
+        .unwrap()
+        .flatten()
+        .collect();
+    points.reverse();
+    points
+}
+
+pub fn file_size(path: impl AsRef<Path>) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+pub fn dir_size(path: impl AsRef<Path>) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| {
+            let path = entry.path();
+            if path.is_dir() {
+                dir_size(&path)
+            } else {
+                file_size(&path)
+            }
+        })
+        .sum()
+}
+
+/// Sessions ranked by the bytes of message text they hold.
+pub fn largest_sessions(db: &Connection, limit: usize) -> Vec<(String, String, u64)> {
+    let mut stmt = db
+        .prepare(
+            "SELECT s.id, s.title, SUM(length(CAST(m.content AS BLOB))) AS bytes
+             FROM sessions s JOIN messages m ON m.session_id = s.id
+             WHERE s.deleted = 0
+             GROUP BY s.id ORDER BY bytes DESC LIMIT ?1",
+        )
+        .unwrap();
+    stmt.query_map(params![limit as i64], |row| {
+        Ok((
+            row.get(0)?,
+            row.get::<usize, Option<String>>(1)?.unwrap_or_default(),
+            row.get::<usize, Option<i64>>(2)?.unwrap_or(0) as u64,
+        ))
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
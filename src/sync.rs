@@ -0,0 +1,589 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::{Hmac, Mac};
+use rusqlite::{params, Connection};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::db;
+
+pub fn init_table(db: &Connection) {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS sync_conflicts (session_id TEXT PRIMARY KEY, remote TEXT)",
+        [],
+    )
+    .unwrap();
+}
+
+const OBJECT_NAME: &str = "ollama-native-sync.json";
+const BUNDLE_VERSION: u64 = 2;
+// Prefix of encrypted bundles, followed by the salt, nonce and ciphertext
+const SEALED_MAGIC: &[u8] = b"ONSYNC-SEALED1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Where the sync bundle lives, from the `sync` config object. WebDAV uses
+/// basic auth with `user`/`secret`; S3 (and compatibles such as MinIO) takes
+/// a path-style bucket URL and signs with `user` as the access key id.
+/// With a `passphrase` the bundle is encrypted before it leaves the machine.
+pub struct SyncTarget {
+    kind: String,
+    url: String,
+    user: String,
+    secret: String,
+    region: String,
+    passphrase: String,
+}
+
+impl SyncTarget {
+    /// `None` until a URL has been configured; sync is opt-in.
+    pub fn from_config(cfg: &serde_json::Value) -> Option<Self> {
+        let sync = &cfg["sync"];
+        let url = sync["url"].as_str().filter(|u| !u.is_empty())?;
+        Some(Self {
+            kind: sync["kind"].as_str().unwrap_or("webdav").to_string(),
+            url: format!("{}/{}", url.trim_end_matches('/'), OBJECT_NAME),
+            user: sync["user"].as_str().unwrap_or("").to_string(),
+            secret: sync["secret"].as_str().unwrap_or("").to_string(),
+            region: sync["region"].as_str().unwrap_or("us-east-1").to_string(),
+            passphrase: sync["passphrase"].as_str().unwrap_or("").to_string(),
+        })
+    }
+
+    fn request(
+        &self,
+        method: reqwest::Method,
+        body: Vec<u8>,
+    ) -> Result<reqwest::RequestBuilder, String> {
+        let client = reqwest::Client::new();
+        let req = client.request(method.clone(), &self.url);
+        if self.kind == "s3" {
+            let headers = self.sign_s3(method.as_str(), &body)?;
+            Ok(headers
+                .into_iter()
+                .fold(req, |req, (name, value)| req.header(name, value))
+                .body(body))
+        } else if self.user.is_empty() {
+            Ok(req.body(body))
+        } else {
+            Ok(req.basic_auth(&self.user, Some(&self.secret)).body(body))
+        }
+    }
+
+    /// The remote bundle, or `None` if nothing has been pushed yet.
+    pub async fn download(&self) -> Result<Option<Vec<u8>>, String> {
+        let resp = self
+            .request(reqwest::Method::GET, Vec::new())?
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let resp = resp.error_for_status().map_err(|e| e.to_string())?;
+        let body = resp.bytes().await.map_err(|e| e.to_string())?.to_vec();
+        match body.strip_prefix(SEALED_MAGIC) {
+            Some(_) if self.passphrase.is_empty() => {
+                Err("the remote data is encrypted, enter the sync passphrase".into())
+            }
+            Some(sealed) => unseal(&self.passphrase, sealed).map(Some),
+            // Still plaintext from before a passphrase was set; the next
+            // push replaces it with an encrypted copy
+            None => Ok(Some(body)),
+        }
+    }
+
+    pub async fn upload(&self, body: Vec<u8>) -> Result<(), String> {
+        let body = if self.passphrase.is_empty() {
+            body
+        } else {
+            seal(&self.passphrase, &body)?
+        };
+        self.request(reqwest::Method::PUT, body)?
+            .send()
+            .await
+            .map_err(|e| e.to_string())?
+            .error_for_status()
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// AWS Signature Version 4 headers for a single-object request.
+    fn sign_s3(&self, method: &str, body: &[u8]) -> Result<Vec<(&'static str, String)>, String> {
+        let url = reqwest::Url::parse(&self.url).map_err(|e| e.to_string())?;
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or(""), port),
+            None => url.host_str().unwrap_or("").to_string(),
+        };
+        let (date, amz_date) = utc_timestamp();
+        let payload_hash = hex(&Sha256::digest(body));
+
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            method,
+            url.path(),
+            host,
+            payload_hash,
+            amz_date,
+            payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let mut key = hmac(format!("AWS4{}", self.secret).as_bytes(), date.as_bytes());
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            key = hmac(&key, part.as_bytes());
+        }
+        let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+
+        Ok(vec![
+            ("x-amz-date", amz_date),
+            ("x-amz-content-sha256", payload_hash),
+            (
+                "authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+                    self.user, scope, signature
+                ),
+            ),
+        ])
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, String> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+/// Encrypts with ChaCha20-Poly1305 under a key derived from the passphrase
+/// with Argon2id. A fresh salt and nonce are stored with every upload.
+fn seal(passphrase: &str, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| "encryption failed".to_string())?;
+    let mut out = SEALED_MAGIC.to_vec();
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+fn unseal(passphrase: &str, sealed: &[u8]) -> Result<Vec<u8>, String> {
+    if sealed.len() < SALT_LEN + NONCE_LEN {
+        return Err("the remote data is truncated".into());
+    }
+    let (salt, rest) = sealed.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, salt)?);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "wrong sync passphrase or corrupted data".to_string())
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `(YYYYMMDD, YYYYMMDDTHHMMSSZ)` for the current UTC time.
+fn utc_timestamp() -> (String, String) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let time = format!("{:02}{:02}{:02}", rem / 3600, rem / 60 % 60, rem % 60);
+    (date.clone(), format!("{}T{}Z", date, time))
+}
+
+/// Every finished session plus the settings, minus the sync credentials.
+pub fn export(db: &Connection, cfg: &serde_json::Value) -> serde_json::Value {
+    let mut stmt = db
+        .prepare("SELECT id, title, created_at, icon, summary FROM sessions WHERE deleted = 0")
+        .unwrap();
+    let sessions: Vec<serde_json::Value> = stmt
+        .query_map([], |row| {
+            Ok(json!({
+                "id": row.get::<usize, String>(0)?,
+                "title": row.get::<usize, Option<String>>(1)?,
+                "created_at": row.get::<usize, Option<String>>(2)?,
+                "icon": row.get::<usize, Option<String>>(3)?,
+                "summary": row.get::<usize, Option<String>>(4)?,
+            }))
+        })
+        .unwrap()
+        .flatten()
+        .map(|mut session| {
+            let messages = export_messages(db, session["id"].as_str().unwrap_or(""));
+            session["rev"] = revision(&messages).into();
+            session["updated_at"] = messages
+                .iter()
+                .filter_map(|m| m["created_at"].as_str())
+                .max()
+                .unwrap_or("")
+                .into();
+            session["messages"] = messages.into();
+            session
+        })
+        .collect();
+
+    let mut settings = cfg.clone();
+    if let Some(map) = settings.as_object_mut() {
+        map.remove("sync");
+    }
+    json!({
+        "version": BUNDLE_VERSION,
+        "sessions": sessions,
+        "settings": settings,
+    })
+}
+
+pub fn export_messages(db: &Connection, session_id: &str) -> Vec<serde_json::Value> {
+    let mut stmt = db
+        .prepare(&format!(
+            "SELECT role, {}, created_at, model FROM messages
+             WHERE session_id = ?1 AND COALESCE(partial, 0) = 0 AND deleted = 0 ORDER BY rowid",
+            db::MESSAGE_TEXT
+        ))
+        .unwrap();
+    stmt.query_map(params![session_id], |row| {
+        Ok(json!({
+            "role": row.get::<usize, String>(0)?,
+            "content": row.get::<usize, String>(1)?,
+            "created_at": row.get::<usize, Option<String>>(2)?,
+            "model": row.get::<usize, Option<String>>(3)?,
+        }))
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
+
+/// Revision of a session's messages as exported; equal hashes mean equal
+/// content.
+pub fn revision(messages: &[serde_json::Value]) -> String {
+    let hash = messages
+        .iter()
+        .flat_map(|m| {
+            let role = m["role"].as_str().unwrap_or("");
+            let content = m["content"].as_str().unwrap_or("");
+            role.bytes().chain([0]).chain(content.bytes()).chain([0])
+        })
+        .fold(0xcbf29ce484222325u64, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+    format!("{:016x}", hash)
+}
+
+/// Outcome of merging a remote bundle.
+pub struct MergeReport {
+    pub changed: usize,
+    pub conflicts: usize,
+}
+
+/// One session edited on both machines since they last synced.
+pub struct Conflict {
+    pub session_id: String,
+    pub title: String,
+    pub local_updated: String,
+    pub remote_updated: String,
+}
+
+/// Writes `session` in the sync bundle's shape as session `id`, replacing
+/// its messages. Only the columns that travel in the bundle are touched;
+/// local ones such as the lock, system prompt and sampling options stay.
+pub fn replace_session(db: &Connection, id: &str, session: &serde_json::Value) {
+    let _ = db.execute(
+        "INSERT INTO sessions (id, title, created_at, icon, summary, synced_rev) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(id) DO UPDATE SET
+             title = excluded.title,
+             created_at = excluded.created_at,
+             icon = excluded.icon,
+             summary = excluded.summary,
+             synced_rev = excluded.synced_rev",
+        params![
+            id,
+            session["title"].as_str(),
+            session["created_at"].as_str(),
+            session["icon"].as_str(),
+            session["summary"].as_str(),
+            session["rev"].as_str(),
+        ],
+    );
+    let _ = db.execute("DELETE FROM messages WHERE session_id = ?1", params![id]);
+    for m in session["messages"].as_array().into_iter().flatten() {
+        let row_id = db::insert_message(
+            db,
+            id,
+            m["role"].as_str().unwrap_or("user"),
+            m["content"].as_str().unwrap_or(""),
+            m["created_at"].as_str(),
+        );
+        let _ = db.execute(
+            "UPDATE messages SET model = ?1 WHERE rowid = ?2",
+            params![m["model"].as_str(), row_id],
+        );
+    }
+    db::after_delete(db, 0);
+}
+
+/// Merges a remote bundle into the local database. Each session's revision
+/// is compared with `synced_rev`, the revision both sides had at the last
+/// sync: a side that still matches it takes the other side's copy, and
+/// sessions changed on both sides are parked in `sync_conflicts` for the
+/// user to resolve. So are remote changes to locked sessions, which are
+/// never overwritten. Settings only fill in keys missing locally.
+pub fn merge(
+    db: &Connection,
+    cfg: &mut serde_json::Value,
+    bundle: &serde_json::Value,
+) -> MergeReport {
+    let mut report = MergeReport {
+        changed: 0,
+        conflicts: 0,
+    };
+    for session in bundle["sessions"].as_array().into_iter().flatten() {
+        let Some(id) = session["id"].as_str() else {
+            continue;
+        };
+        let remote_messages = session["messages"].as_array().cloned().unwrap_or_default();
+        let remote_rev = revision(&remote_messages);
+        let base: Option<Option<String>> = db
+            .query_row(
+                "SELECT synced_rev FROM sessions WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .ok();
+        let Some(base) = base else {
+            replace_session(db, id, session);
+            report.changed += 1;
+            continue;
+        };
+        let local_rev = revision(&export_messages(db, id));
+        if local_rev == remote_rev {
+            continue;
+        }
+        if base.as_deref() == Some(remote_rev.as_str()) {
+            // Only changed here; the next push carries it over
+            continue;
+        }
+        if base.as_deref() == Some(local_rev.as_str()) && !db::is_session_locked(db, id) {
+            replace_session(db, id, session);
+            report.changed += 1;
+        } else {
+            let _ = db.execute(
+                "INSERT OR REPLACE INTO sync_conflicts (session_id, remote) VALUES (?1, ?2)",
+                params![id, session.to_string()],
+            );
+            report.conflicts += 1;
+        }
+    }
+
+    if let (Some(local), Some(remote)) = (cfg.as_object_mut(), bundle["settings"].as_object()) {
+        for (key, value) in remote {
+            if key != "sync" && !local.contains_key(key) {
+                local.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    report
+}
+
+/// Records the revisions that were just pushed as the common base. Sessions
+/// with an open conflict keep their old base until resolved.
+pub fn mark_synced(db: &Connection, bundle: &serde_json::Value) {
+    for session in bundle["sessions"].as_array().into_iter().flatten() {
+        let _ = db.execute(
+            "UPDATE sessions SET synced_rev = ?1 WHERE id = ?2
+             AND id NOT IN (SELECT session_id FROM sync_conflicts)",
+            params![session["rev"].as_str(), session["id"].as_str()],
+        );
+    }
+}
+
+pub fn conflicts(db: &Connection) -> Vec<Conflict> {
+    let mut stmt = db
+        .prepare(
+            "SELECT c.session_id, COALESCE(s.title, ''), c.remote,
+                    (SELECT COALESCE(MAX(created_at), '') FROM messages WHERE session_id = c.session_id)
+             FROM sync_conflicts c LEFT JOIN sessions s ON s.id = c.session_id",
+        )
+        .unwrap();
+    stmt.query_map([], |row| {
+        let remote: serde_json::Value =
+            serde_json::from_str(&row.get::<usize, String>(2)?).unwrap_or_default();
+        Ok(Conflict {
+            session_id: row.get(0)?,
+            title: row.get(1)?,
+            local_updated: row.get(3)?,
+            remote_updated: remote["updated_at"].as_str().unwrap_or("").to_string(),
+        })
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
+
+/// How a conflict is settled.
+pub enum Resolution {
+    KeepMine,
+    KeepTheirs,
+    /// The remote copy becomes a separate session next to the local one
+    KeepBoth,
+    Newest,
+}
+
+/// Settles the conflict on `session_id`. Returns false, leaving it open,
+/// when that would overwrite a locked session.
+pub fn resolve(db: &Connection, session_id: &str, resolution: Resolution) -> bool {
+    let Ok(remote) = db.query_row(
+        "SELECT remote FROM sync_conflicts WHERE session_id = ?1",
+        params![session_id],
+        |row| row.get::<usize, String>(0),
+    ) else {
+        return true;
+    };
+    let mut remote: serde_json::Value = serde_json::from_str(&remote).unwrap_or_default();
+    let resolution = match resolution {
+        Resolution::Newest => {
+            let local_updated: String = db
+                .query_row(
+                    "SELECT COALESCE(MAX(created_at), '') FROM messages WHERE session_id = ?1",
+                    params![session_id],
+                    |row| row.get(0),
+                )
+                .unwrap_or_default();
+            if remote["updated_at"].as_str().unwrap_or("") > local_updated.as_str() {
+                Resolution::KeepTheirs
+            } else {
+                Resolution::KeepMine
+            }
+        }
+        other => other,
+    };
+
+    if matches!(resolution, Resolution::KeepTheirs) && db::is_session_locked(db, session_id) {
+        return false;
+    }
+    let remote_rev = remote["rev"].as_str().unwrap_or("").to_string();
+    match resolution {
+        Resolution::KeepTheirs => replace_session(db, session_id, &remote),
+        Resolution::KeepBoth => {
+            let branch_id = uuid::Uuid::new_v4().to_string();
+            let title = format!("{} (other device)", remote["title"].as_str().unwrap_or(""));
+            remote["title"] = title.into();
+            replace_session(db, &branch_id, &remote);
+            // The branch is new to the other side and gets pushed as-is
+            let _ = db.execute(
+                "UPDATE sessions SET synced_rev = NULL WHERE id = ?1",
+                params![branch_id],
+            );
+        }
+        _ => {}
+    }
+    // Ours now supersedes the remote copy, so the next push wins
+    let _ = db.execute(
+        "UPDATE sessions SET synced_rev = ?1 WHERE id = ?2",
+        params![remote_rev, session_id],
+    );
+    let _ = db.execute(
+        "DELETE FROM sync_conflicts WHERE session_id = ?1",
+        params![session_id],
+    );
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_bundle_round_trips() {
+        let sealed = seal("correct horse", b"{\"version\":2}").unwrap();
+        let body = sealed.strip_prefix(SEALED_MAGIC).unwrap();
+        assert_eq!(unseal("correct horse", body).unwrap(), b"{\"version\":2}");
+    }
+
+    #[test]
+    fn wrong_passphrase_is_refused() {
+        let sealed = seal("correct horse", b"secret").unwrap();
+        let body = sealed.strip_prefix(SEALED_MAGIC).unwrap();
+        assert!(unseal("battery staple", body).is_err());
+        assert!(unseal("correct horse", &body[..SALT_LEN]).is_err());
+    }
+
+    fn bundle(messages: serde_json::Value) -> serde_json::Value {
+        let rev = revision(messages.as_array().unwrap());
+        json!({
+            "version": BUNDLE_VERSION,
+            "sessions": [{ "id": "s1", "title": "Remote", "rev": rev, "messages": messages }],
+            "settings": { "theme": "light", "font_size": 14, "sync": { "url": "x" } },
+        })
+    }
+
+    #[test]
+    fn merge_takes_remote_changes_and_parks_conflicts() {
+        let db = Connection::open_in_memory().unwrap();
+        crate::init_db(&db);
+        let mut cfg = json!({ "theme": "dark" });
+
+        let first = bundle(json!([
+            { "role": "user", "content": "hi" },
+            { "role": "assistant", "content": "hello" },
+        ]));
+        let report = merge(&db, &mut cfg, &first);
+        assert_eq!((report.changed, report.conflicts), (1, 0));
+        assert_eq!(export_messages(&db, "s1").len(), 2);
+        assert_eq!(cfg, json!({ "theme": "dark", "font_size": 14 }));
+
+        // Changed only remotely since the last sync
+        let second = bundle(json!([
+            { "role": "user", "content": "hi" },
+            { "role": "assistant", "content": "hello" },
+            { "role": "user", "content": "more" },
+        ]));
+        let report = merge(&db, &mut cfg, &second);
+        assert_eq!((report.changed, report.conflicts), (1, 0));
+        assert_eq!(export_messages(&db, "s1").len(), 3);
+
+        // Changed on both sides
+        db::insert_message(&db, "s1", "assistant", "local reply", None);
+        let third = bundle(json!([{ "role": "user", "content": "remote only" }]));
+        let report = merge(&db, &mut cfg, &third);
+        assert_eq!((report.changed, report.conflicts), (0, 1));
+        assert_eq!(export_messages(&db, "s1").len(), 4);
+        assert_eq!(conflicts(&db)[0].session_id, "s1");
+    }
+}
@@ -0,0 +1,112 @@
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+/// One exemplar message a conversation template starts with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TemplateTurn {
+    // "user" or "assistant"
+    pub role: String,
+    pub content: String,
+}
+
+/// A conversation scaffold: a system prompt plus few-shot turns that a new
+/// session is seeded with before the first real prompt.
+#[derive(Clone, Debug)]
+pub struct Template {
+    pub id: i64,
+    pub name: String,
+    pub system_prompt: String,
+    pub turns: Vec<TemplateTurn>,
+}
+
+pub fn init_table(db: &Connection) {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS conversation_templates (id INTEGER PRIMARY KEY, name TEXT UNIQUE, system_prompt TEXT, turns TEXT)",
+        [],
+    )
+    .unwrap();
+    // Id of the last turn a template put in the session
+    crate::db::ensure_column(db, "sessions", "seeded_through", "INTEGER");
+}
+
+/// SQL condition on `messages` that leaves out the turns a template seeded
+/// session `?1` with, so what follows counts as the start of the chat.
+pub const AFTER_SEED: &str =
+    "messages.id > COALESCE((SELECT seeded_through FROM sessions WHERE sessions.id = ?1), 0)";
+
+/// Whether the session was started from a template.
+pub fn is_seeded(db: &Connection, session_id: &str) -> bool {
+    db.query_row(
+        "SELECT seeded_through IS NOT NULL FROM sessions WHERE id = ?1",
+        params![session_id],
+        |row| row.get(0),
+    )
+    .unwrap_or(false)
+}
+
+pub fn load_templates(db: &Connection) -> Vec<Template> {
+    let mut stmt = db
+        .prepare("SELECT id, name, system_prompt, turns FROM conversation_templates ORDER BY name")
+        .unwrap();
+    stmt.query_map([], |row| {
+        let turns_json: String = row.get(3)?;
+        Ok(Template {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            system_prompt: row.get(2)?,
+            turns: serde_json::from_str(&turns_json).unwrap_or_default(),
+        })
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
+
+pub fn save_template(db: &Connection, template: &Template) -> rusqlite::Result<i64> {
+    let turns_json = serde_json::to_string(&template.turns).unwrap_or_else(|_| "[]".into());
+    if template.id > 0 {
+        db.execute(
+            "UPDATE conversation_templates SET name = ?1, system_prompt = ?2, turns = ?3 WHERE id = ?4",
+            params![template.name, template.system_prompt, turns_json, template.id],
+        )?;
+        Ok(template.id)
+    } else {
+        db.execute(
+            "INSERT INTO conversation_templates (name, system_prompt, turns) VALUES (?1, ?2, ?3)",
+            params![template.name, template.system_prompt, turns_json],
+        )?;
+        Ok(db.last_insert_rowid())
+    }
+}
+
+pub fn delete_template(db: &Connection, id: i64) {
+    let _ = db.execute(
+        "DELETE FROM conversation_templates WHERE id = ?1",
+        params![id],
+    );
+}
+
+/// Creates a session holding the template's system prompt and turns and
+/// returns its id. The title is the template's name until the first reply
+/// gets a suggested one or the user renames it.
+pub fn start_session(db: &Connection, template: &Template) -> String {
+    let id = uuid::Uuid::new_v4().to_string();
+    let _ = db.execute(
+        "INSERT INTO sessions (id, title, created_at, icon, system_prompt) VALUES (?1, ?2, datetime('now'), ?3, ?4)",
+        params![
+            id,
+            template.name,
+            crate::db::default_session_icon(&id),
+            template.system_prompt
+        ],
+    );
+    let mut seeded_through = 0;
+    for turn in &template.turns {
+        seeded_through = crate::db::insert_message(db, &id, &turn.role, &turn.content, None);
+    }
+    let _ = db.execute(
+        "UPDATE sessions SET seeded_through = ?1 WHERE id = ?2",
+        params![seeded_through, id],
+    );
+    id
+}
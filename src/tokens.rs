@@ -0,0 +1,34 @@
+use std::sync::OnceLock;
+use tiktoken_rs::CoreBPE;
+
+// Fallback when the BPE tables can't be loaded
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Counts tokens the way the size warnings and budgets need them. Local
+/// models each ship their own vocabulary, but cl100k is close enough for
+/// English text and code to decide whether a request is "large".
+///
+/// The BPE tables take a moment to build, so they're loaded on first use.
+pub struct TokenCounter {
+    bpe: OnceLock<Option<CoreBPE>>,
+}
+
+impl TokenCounter {
+    pub fn new() -> Self {
+        Self {
+            bpe: OnceLock::new(),
+        }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        let bpe = self.bpe.get_or_init(|| {
+            tiktoken_rs::cl100k_base()
+                .map_err(|e| eprintln!("Tokenizer unavailable, estimating from length: {}", e))
+                .ok()
+        });
+        match bpe {
+            Some(bpe) => bpe.encode_with_special_tokens(text).len(),
+            None => text.len().div_ceil(CHARS_PER_TOKEN),
+        }
+    }
+}
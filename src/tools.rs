@@ -0,0 +1,188 @@
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ToolParam {
+    pub name: String,
+    pub kind: String,
+    pub description: String,
+    pub required: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct ToolDef {
+    pub id: i64,
+    pub name: String,
+    pub description: String,
+    pub params: Vec<ToolParam>,
+    pub kind: String,
+    pub template: String,
+    pub enabled: bool,
+}
+
+pub fn init_table(db: &Connection) {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS tools (id INTEGER PRIMARY KEY, name TEXT UNIQUE, description TEXT, params TEXT, kind TEXT, template TEXT, enabled INTEGER DEFAULT 1)",
+        [],
+    )
+    .unwrap();
+}
+
+pub fn load_tools(db: &Connection) -> Vec<ToolDef> {
+    let mut stmt = db
+        .prepare("SELECT id, name, description, params, kind, template, enabled FROM tools ORDER BY name")
+        .unwrap();
+    stmt.query_map([], |row| {
+        let params_json: String = row.get(3)?;
+        Ok(ToolDef {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            description: row.get(2)?,
+            params: serde_json::from_str(&params_json).unwrap_or_default(),
+            kind: row.get(4)?,
+            template: row.get(5)?,
+            enabled: row.get::<usize, i64>(6)? != 0,
+        })
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
+
+pub fn save_tool(db: &Connection, tool: &ToolDef) -> rusqlite::Result<()> {
+    let params_json = serde_json::to_string(&tool.params).unwrap_or_else(|_| "[]".into());
+    if tool.id > 0 {
+        db.execute(
+            "UPDATE tools SET name = ?1, description = ?2, params = ?3, kind = ?4, template = ?5, enabled = ?6 WHERE id = ?7",
+            params![tool.name, tool.description, params_json, tool.kind, tool.template, tool.enabled, tool.id],
+        )?;
+    } else {
+        db.execute(
+            "INSERT INTO tools (name, description, params, kind, template, enabled) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![tool.name, tool.description, params_json, tool.kind, tool.template, tool.enabled],
+        )?;
+    }
+    Ok(())
+}
+
+pub fn delete_tool(db: &Connection, id: i64) {
+    let _ = db.execute("DELETE FROM tools WHERE id = ?1", params![id]);
+}
+
+/// Renders the enabled tools as JSON schemas plus the calling convention the
+/// model has to follow. Sent as a system message ahead of the history.
+pub fn system_prompt(tools: &[ToolDef]) -> Option<String> {
+    let enabled: Vec<&ToolDef> = tools.iter().filter(|t| t.enabled).collect();
+    if enabled.is_empty() {
+        return None;
+    }
+
+    let schemas: Vec<serde_json::Value> = enabled
+        .iter()
+        .map(|t| {
+            let mut properties = serde_json::Map::new();
+            let mut required = Vec::new();
+            for p in &t.params {
+                properties.insert(
+                    p.name.clone(),
+                    serde_json::json!({ "type": p.kind, "description": p.description }),
+                );
+                if p.required {
+                    required.push(p.name.clone());
+                }
+            }
+            serde_json::json!({
+                "name": t.name,
+                "description": t.description,
+                "parameters": {
+                    "type": "object",
+                    "properties": properties,
+                    "required": required,
+                }
+            })
+        })
+        .collect();
+
+    Some(format!(
+        "You can call the following tools:\n{}\n\nTo call a tool, reply with nothing but a JSON object of the form {{\"tool\": \"<name>\", \"arguments\": {{...}}}}. The result will be sent back to you in the next message.",
+        serde_json::to_string_pretty(&schemas).unwrap_or_default()
+    ))
+}
+
+/// Returns the tool name and arguments if the whole response is a tool call.
+pub fn parse_tool_call(response: &str) -> Option<(String, serde_json::Value)> {
+    let trimmed = response
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+    let value: serde_json::Value = serde_json::from_str(trimmed).ok()?;
+    let name = value.get("tool")?.as_str()?.to_string();
+    let args = value
+        .get("arguments")
+        .cloned()
+        .unwrap_or_else(|| serde_json::json!({}));
+    Some((name, args))
+}
+
+fn fill_template(template: &str, args: &serde_json::Value, escape: fn(&str) -> String) -> String {
+    let mut out = template.to_string();
+    if let Some(map) = args.as_object() {
+        for (key, value) in map {
+            let raw = match value {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            out = out.replace(&format!("{{{{{}}}}}", key), &escape(&raw));
+        }
+    }
+    out
+}
+
+fn shell_escape(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn url_escape(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Runs a tool with the arguments the model supplied. Command tools are run
+/// through the shell, HTTP tools issue a GET against the filled-in URL.
+pub async fn run_tool(tool: &ToolDef, args: &serde_json::Value) -> Result<String, String> {
+    match tool.kind.as_str() {
+        "http" => {
+            let url = fill_template(&tool.template, args, url_escape);
+            let resp = reqwest::get(&url).await.map_err(|e| e.to_string())?;
+            resp.text().await.map_err(|e| e.to_string())
+        }
+        _ => {
+            let cmd = fill_template(&tool.template, args, shell_escape);
+            let output = tokio::task::spawn_blocking(move || {
+                if cfg!(target_os = "windows") {
+                    Command::new("cmd").args(["/C", &cmd]).output()
+                } else {
+                    Command::new("sh").args(["-c", &cmd]).output()
+                }
+            })
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+            let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+            if !output.status.success() {
+                text.push_str(&String::from_utf8_lossy(&output.stderr));
+            }
+            Ok(text)
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::db;
+
+/// Outputs of a reply that was retried, so the user can flip between them.
+/// The one on display is also the message's own text, and `messages.variant`
+/// says which it is. Replies that were never retried have no rows here.
+pub fn init_table(db: &Connection) {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS message_variants (id INTEGER PRIMARY KEY, message_id INTEGER, content TEXT, model TEXT, created_at DATETIME)",
+        [],
+    )
+    .unwrap();
+    db::ensure_column(db, "messages", "variant", "INTEGER");
+}
+
+/// Which variant of each message in the session is shown, counted from 1,
+/// the number there are and the shown one's model. `(0, 0, "")` for
+/// messages without variants.
+pub fn session_variants(db: &Connection, session_id: &str) -> Vec<(usize, usize, String)> {
+    let mut stmt = db
+        .prepare(
+            "SELECT
+                 (SELECT COUNT(*) FROM message_variants v WHERE v.message_id = m.id AND v.id <= m.variant),
+                 (SELECT COUNT(*) FROM message_variants v WHERE v.message_id = m.id),
+                 m.model
+             FROM messages m WHERE m.session_id = ?1 AND m.deleted = 0 ORDER BY m.id",
+        )
+        .unwrap();
+    stmt.query_map(params![session_id], |row| {
+        Ok((
+            row.get::<usize, i64>(0)? as usize,
+            row.get::<usize, i64>(1)? as usize,
+            row.get::<usize, Option<String>>(2)?.unwrap_or_default(),
+        ))
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
+
+/// Every output of a retried reply, oldest first, as (text, model).
+pub fn outputs(db: &Connection, message_id: i64) -> Vec<(String, String)> {
+    db.prepare("SELECT content, COALESCE(model, '') FROM message_variants WHERE message_id = ?1 ORDER BY id")
+        .and_then(|mut stmt| {
+            let rows: Vec<(String, String)> = stmt
+                .query_map(params![message_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .flatten()
+                .collect();
+            Ok(rows)
+        })
+        .unwrap_or_default()
+}
+
+/// Readies a reply to be generated again by `model`: its current output is
+/// kept as a variant if it's the first retry, then the row is emptied and
+/// flagged partial like a reply that's about to stream in. `variant` still
+/// points at the output that was on display, for `abandon_retry`.
+pub fn start_retry(db: &Connection, message_id: i64, model: &str) {
+    let tracked: bool = db
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM message_variants WHERE message_id = ?1",
+            params![message_id],
+            |row| row.get(0),
+        )
+        .unwrap_or(false);
+    if !tracked {
+        let _ = db.execute(
+            &format!(
+                "INSERT INTO message_variants (message_id, content, model, created_at)
+                 SELECT id, {}, model, created_at FROM messages WHERE id = ?1",
+                db::MESSAGE_TEXT
+            ),
+            params![message_id],
+        );
+        let _ = db.execute(
+            "UPDATE messages SET variant = ?1 WHERE id = ?2",
+            params![db.last_insert_rowid(), message_id],
+        );
+    }
+    let _ = db.execute(
+        "UPDATE messages SET content = '', blob = NULL, partial = 1, model = ?1 WHERE id = ?2",
+        params![model, message_id],
+    );
+}
+
+/// Puts back the output a retry was about to replace, for one that failed
+/// or was stopped before any new output came in. Returns its text, or
+/// `None` if the reply has nothing to go back to.
+pub fn abandon_retry(db: &Connection, message_id: i64) -> Option<String> {
+    let restored = db
+        .execute(
+            "UPDATE messages SET (content, model, blob, partial) =
+                 (SELECT content, model, NULL, 0 FROM message_variants WHERE id = messages.variant)
+             WHERE id = ?1 AND variant IS NOT NULL",
+            params![message_id],
+        )
+        .unwrap_or(0);
+    if restored == 0 {
+        return None;
+    }
+    db.query_row(
+        &format!("SELECT {} FROM messages WHERE id = ?1", db::MESSAGE_TEXT),
+        params![message_id],
+        |row| row.get(0),
+    )
+    .ok()
+}
+
+/// Keeps a finished output of a retried reply as its newest variant and
+/// shows it. Does nothing for replies that were never retried.
+pub fn add_output(db: &Connection, message_id: i64, content: &str, model: &str) {
+    let inserted = db
+        .execute(
+            "INSERT INTO message_variants (message_id, content, model, created_at)
+             SELECT ?1, ?2, ?3, datetime('now')
+             WHERE EXISTS (SELECT 1 FROM message_variants WHERE message_id = ?1)",
+            params![message_id, content, model],
+        )
+        .unwrap_or(0);
+    if inserted > 0 {
+        let _ = db.execute(
+            "UPDATE messages SET variant = ?1 WHERE id = ?2",
+            params![db.last_insert_rowid(), message_id],
+        );
+    }
+}
+
+/// Shows the variant `step` places before or after the current one.
+/// Returns false if there's none there.
+pub fn select(db: &Connection, message_id: i64, step: i32) -> bool {
+    let ids: Vec<i64> = db
+        .prepare("SELECT id FROM message_variants WHERE message_id = ?1 ORDER BY id")
+        .and_then(|mut stmt| {
+            let ids: Vec<i64> = stmt
+                .query_map(params![message_id], |row| row.get(0))?
+                .flatten()
+                .collect();
+            Ok(ids)
+        })
+        .unwrap_or_default();
+    let current: Option<i64> = db
+        .query_row(
+            "SELECT variant FROM messages WHERE id = ?1",
+            params![message_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .ok()
+        .flatten()
+        .flatten();
+    let Some(pos) = current.and_then(|c| ids.iter().position(|&id| id == c)) else {
+        return false;
+    };
+    let Some(&target) = pos
+        .checked_add_signed(step as isize)
+        .and_then(|p| ids.get(p))
+    else {
+        return false;
+    };
+    db.execute(
+        "UPDATE messages SET (content, model, blob, variant) =
+             (SELECT content, model, NULL, id FROM message_variants WHERE id = ?1)
+         WHERE id = ?2",
+        params![target, message_id],
+    )
+    .map(|n| n > 0)
+    .unwrap_or(false)
+}